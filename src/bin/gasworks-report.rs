@@ -1,7 +1,12 @@
 extern crate heapsize;
+extern crate ron;
 extern crate gasworks;
 
 use std::mem;
+use std::fs;
+
+#[macro_use] extern crate quicli;
+use quicli::prelude::*;
 
 use gasworks::types::*;
 use gasworks::prim::*;
@@ -9,9 +14,85 @@ use gasworks::layout::*;
 use gasworks::value::*;
 use gasworks::packet::*;
 use gasworks::loclayout::*;
+use gasworks::report::*;
+use gasworks::registry::*;
+
+
+#[derive(Debug, StructOpt)]
+struct Cli {
+    /// packet definition files, possibly versioned, or layout files (RON). With none, the sizes of gasworks' own types are printed
+    definitions: Vec<String>,
+
+    /// write the report as Markdown to this file
+    #[structopt(long="markdown")]
+    markdown: Option<String>,
+
+    /// write the report as HTML to this file
+    #[structopt(long="html")]
+    html: Option<String>,
+}
+
+// Write an interface control document for the given definition files, in Markdown
+// to stdout unless other outputs are given.
+main!(|args: Cli| {
+    if args.definitions.is_empty() {
+        type_sizes();
+        return Ok(());
+    }
+
+    let mut reports = Vec::new();
+    for path in args.definitions.iter() {
+        let text = fs::read_to_string(path)?;
 
+        // a file holds a packet definition, versions of one, or a layout, titled by its name if it has one
+        match ron::de::from_str::<LayoutPacketDef>(&text) {
+            Ok(packet) => reports.push(report_packet(packet.name().unwrap_or(path), &packet)),
+            Err(err) => match ron::de::from_str::<Vec<VersionDef<Item>>>(&text) {
+                Ok(versions) => {
+                    for (index, version) in versions.iter().enumerate() {
+                        let title = format!("{} version {}{}", version.packet.name().unwrap_or(path), index, valid_times(version));
+                        reports.push(report_packet(&title, &version.packet));
+                    }
+                },
+                Err(_) => match ron::de::from_str::<Layout>(&text) {
+                    Ok(layout) => reports.push(report_layout(layout_name(&layout).unwrap_or(path), &layout)),
+                    Err(_) => bail!("{}: {}", path, err),
+                },
+            },
+        }
+    }
+
+    if let Some(ref markdown_path) = args.markdown {
+        fs::write(markdown_path, markdown(&reports))?;
+    }
+
+    if let Some(ref html_path) = args.html {
+        fs::write(html_path, html(&reports))?;
+    }
+
+    if args.markdown.is_none() && args.html.is_none() {
+        print!("{}", markdown(&reports));
+    }
+});
+
+// when a version of a definition applies, if it is limited
+fn valid_times(version: &VersionDef<Item>) -> String {
+    match (version.valid_from, version.valid_until) {
+        (Some(from), Some(until)) => format!(", valid from {} until {}", from, until),
+        (Some(from), None) => format!(", valid from {}", from),
+        (None, Some(until)) => format!(", valid until {}", until),
+        (None, None) => String::new(),
+    }
+}
+
+fn layout_name(layout: &Layout) -> Option<&Name> {
+    match layout {
+        Layout::Seq(name, _) | Layout::All(name, _) | Layout::Array(name, _, _) => Some(name),
+        Layout::Prim(_) | Layout::Bits(_) => None,
+    }
+}
 
-fn main()
+fn type_sizes()
 {
     println!("sizeof(ChoicePoints) = {}", mem::size_of::<ChoicePoints>());
     println!("sizeof(Endianness) = {}", mem::size_of::<Endianness>());
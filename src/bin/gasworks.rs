@@ -7,33 +7,133 @@ extern crate crossbeam_channel;
 extern crate crossbeam;
 extern crate sorted_list;
 extern crate revord;
+extern crate ron;
 
 //use std::thread;
-use std::io::{Cursor, Read, Write};
+use std::io;
+use std::io::{Cursor, Read, Write, BufReader, BufWriter};
 use std::fs::File;
 use std::vec::Vec;
-use std::iter::Filter;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::iter;
+use std::cell::Cell;
+use std::time::Duration;
+use std::str::FromStr;
+use std::env;
+use std::ffi::OsString;
 
 #[macro_use] extern crate quicli;
 use quicli::prelude::*;
 
-use memmap::{ MmapOptions };
-
 use crossbeam_channel as channel;
 
 use revord::RevOrd;
 
 use gasworks::*;
 use gasworks::csv::*;
+use gasworks::jsonl::*;
+use gasworks::sqlite::*;
+use gasworks::index::*;
+use gasworks::input::*;
+use gasworks::framing::*;
+use gasworks::stuffing::*;
+use gasworks::net::*;
+use gasworks::sink::*;
+use gasworks::follow::*;
+use gasworks::pcap::*;
+use gasworks::capture::*;
+use gasworks::replay::*;
+use gasworks::merge::*;
+use gasworks::tmframe::*;
+use gasworks::segment::*;
+use gasworks::continuity::*;
+use gasworks::validate::*;
+use gasworks::registry::*;
+use gasworks::commutation::*;
+use gasworks::timetag::*;
+use gasworks::correlation::*;
+#[cfg(unix)]
+use gasworks::serial::*;
+use gasworks::prim::{Prim, Item, IntPrim, IntSize, Signedness, Endianness};
 use gasworks::decode::*;
 use gasworks::packet::*;
-use gasworks::layout::*;
 use gasworks::loclayout::*;
+use gasworks::layout::Layout;
+use gasworks::types::{NumBytes, LocItem, LocPath, Name};
 
 
+/// decode, index, replay, and merge packet captures. without a subcommand the
+/// arguments are those of decode, as in "gasworks IN OUT".
 #[derive(Debug, StructOpt)]
 struct Cli {
+    #[structopt(subcommand)]
+    command: Command,
+
+    #[structopt(flatten)]
+    verbosity : Verbosity,
+}
+
+// the subcommand names, so invocations from before there were subcommands decode as they did
+const COMMANDS: [&str; 7] = ["decode", "index", "extract", "replay", "merge", "check", "help"];
+
+impl Cli {
+    // parse the command line, taking it as a decode command if it does not start with a
+    // subcommand, as in "gasworks -v IN OUT". this is found by main! before StructOpt::from_args.
+    fn from_args() -> Cli {
+        let mut args: Vec<OsString> = env::args_os().collect();
+
+        // skip the program name and any verbosity flags, which come before the subcommand
+        let start = args.iter().skip(1).position(|arg| {
+            let arg = arg.to_string_lossy();
+            !(arg == "--verbosity" || (arg.starts_with("-v") && arg.chars().skip(1).all(|c| c == 'v')))
+        });
+
+        if let Some(position) = start.map(|position| position + 1) {
+            let first = args[position].to_string_lossy().into_owned();
+            let top_level = ["-h", "--help", "-V", "--version"];
+            if !COMMANDS.contains(&first.as_str()) && !top_level.contains(&first.as_str()) {
+                args.insert(position, OsString::from("decode"));
+            }
+        }
+
+        <Cli as StructOpt>::from_iter(args)
+    }
+}
+
+// NOTE decode has many more options then the other commands, but the command is only parsed once
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// decode a capture into csv, jsonl, or sqlite
+    #[structopt(name="decode")]
+    Decode(DecodeArgs),
+
+    /// build an index file for a capture
+    #[structopt(name="index")]
+    Index(IndexArgs),
+
+    /// extract packets from a capture by time range or id, using its index
+    #[structopt(name="extract")]
+    Extract(ExtractArgs),
+
+    /// replay a recorded capture to outputs, at its original or a scaled rate
+    #[structopt(name="replay")]
+    Replay(ReplayArgs),
+
+    /// merge captures into one capture in time tag order, dropping duplicate packets
+    #[structopt(name="merge")]
+    Merge(MergeArgs),
+
+    /// check definition files for mistakes, such as duplicate names or undefined array counts
+    #[structopt(name="check")]
+    Check(CheckArgs),
+}
+
+#[derive(Debug, StructOpt)]
+struct DecodeArgs {
     infile : String,
 
     outfile : String,
@@ -53,23 +153,409 @@ struct Cli {
     #[structopt(short="i", long="items", default_value="")]
     items: String,
 
+    /// output format, either csv, jsonl, or sqlite. jsonl objects list their keys in
+    /// alphabetical order, not definition order.
+    #[structopt(short="f", long="format", default_value="csv")]
+    format: OutputFormat,
+
+    /// csv column naming, either full (the item's full path) or last (the item name only)
+    #[structopt(long="naming", default_value="full")]
+    naming: String,
+
+    /// separator between path components in full csv column names
+    #[structopt(long="separator", default_value=".")]
+    separator: String,
+
+    /// include the packet name in each jsonl record
+    #[structopt(short="n", long="name")]
+    include_name: bool,
+
     #[structopt(flatten)]
-    verbosity : Verbosity,
+    time: TimeArgs,
+
+    /// item holding the packet's sequence count, recorded in the sqlite seq column
+    #[structopt(long="seq-item", default_value="")]
+    seq_item: String,
+
+    /// packet definition file (RON), defaulting to the built in VN200 definition
+    #[structopt(short="d", long="definition", default_value="")]
+    definition: String,
+
+    /// forward packets to a udp destination or to tcp clients, such as udp://host:port
+    /// or tcp-listen://addr:port?format=jsonl&ids=0x10,0x11. may be given more then once.
+    #[structopt(short="o", long="output", raw(number_of_values = "1"))]
+    outputs: Vec<String>,
+
+    /// item identifying each packet, such as an APID, for outputs that select packets by ids
+    #[structopt(long="id-item", default_value="")]
+    id_item: String,
+
+    #[structopt(long="id-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    id_mask: u64,
+
+    /// queue depth for each forwarding output. packets are dropped when it is full.
+    #[structopt(long="outputqueue", default_value="100")]
+    output_queue_depth: u16,
+
+    /// record every packet received, with its receive time, to a gasworks capture file
+    #[structopt(long="record")]
+    record: Option<String>,
+
+    /// keep decoding a capture file as it grows, following truncation and rotation
+    #[structopt(short="F", long="follow")]
+    follow: bool,
+
+    /// with --follow, stop once the file has not grown for this many seconds
+    #[structopt(long="follow-timeout")]
+    follow_timeout: Option<f64>,
+
+    /// sync marker at the start of each packet on followed files and tcp, serial, and pcap tcp inputs,
+    /// in hex, such as FA or 1ACFFC1D
+    #[structopt(long="sync", default_value="")]
+    sync: String,
+
+    /// byte offset of a packet length field on tcp and serial inputs. without one,
+    /// packets are taken to be the size of the definition.
+    #[structopt(long="length-offset")]
+    length_offset: Option<usize>,
+
+    /// size of the length field in bytes, either 1, 2, or 4
+    #[structopt(long="length-size", default_value="2")]
+    length_size: u8,
+
+    /// the length field is little endian
+    #[structopt(long="length-le")]
+    length_le: bool,
+
+    /// added to the length field to get the full packet length, such as 7 for CCSDS
+    #[structopt(long="length-adjust", default_value="0", raw(allow_hyphen_values = "true"))]
+    length_adjust: i64,
+
+    /// frame tcp and serial inputs as CCSDS space packets, by their primary header length
+    #[structopt(long="ccsds")]
+    ccsds: bool,
+
+    /// frame files and stream inputs by byte stuffing rather then by length: slip, kiss, kiss:<port>, cobs, or hdlc
+    #[structopt(long="stuffing")]
+    stuffing: Option<String>,
+
+    /// the input is TM transfer frames of this many bytes, not counting any sync marker.
+    /// the space packets they carry are decoded.
+    #[structopt(long="tm-frame")]
+    tm_frame: Option<usize>,
+
+    /// TM frames end with a frame error control field, which is checked
+    #[structopt(long="tm-fecf")]
+    tm_fecf: bool,
+
+    /// only decode TM frames from this spacecraft id
+    #[structopt(long="spacecraft", parse(try_from_str = "parse_int"))]
+    spacecraft_id: Option<u64>,
+
+    /// only decode packets from this TM virtual channel
+    #[structopt(long="vc")]
+    virtual_channel: Option<u8>,
+
+    /// join space packets split with the CCSDS sequence flags, decoding each product as one packet
+    #[structopt(long="reassemble")]
+    reassemble: bool,
+
+    /// with --reassemble, bytes at the start of each segment's data, such as a secondary header,
+    /// that are kept from the first segment only
+    #[structopt(long="segment-header", default_value="0")]
+    segment_header: usize,
+
+    /// check the continuity of the --seq-item sequence count, warning about lost, repeated,
+    /// and reordered packets and summarizing them at the end
+    #[structopt(long="check-continuity")]
+    check_continuity: bool,
+
+    /// with --check-continuity, the bits of the --seq-item that count, such as 0x3FFF for CCSDS
+    #[structopt(long="seq-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    seq_mask: u64,
+
+    /// with --check-continuity, item separating packet streams with their own sequence counts, such as an APID
+    #[structopt(long="stream-item", default_value="")]
+    stream_item: String,
+
+    #[structopt(long="stream-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    stream_mask: u64,
+
+    /// subcommutation cycle definition file (RON). packets are taken as minor frames of the cycle,
+    /// by their frame counter, and a csv row is written for each complete major frame.
+    #[structopt(long="cycle")]
+    cycle: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct MergeArgs {
+    /// gasworks captures, or raw captures of packets of the definition's size,
+    /// each in time order
+    #[structopt(raw(required = "true"))]
+    infiles: Vec<String>,
+
+    /// the merged gasworks capture
+    #[structopt(short="o", long="outfile")]
+    outfile: String,
+
+    /// write the merged packets one after another, as a raw capture
+    #[structopt(long="raw")]
+    raw: bool,
+
+    // the time tag to merge by, defaulting to the receive time of gasworks captures
+    #[structopt(flatten)]
+    time: TimeArgs,
+
+    /// item holding the packet's sequence count, telling apart packets with the same time tag
+    #[structopt(long="seq-item", default_value="")]
+    seq_item: String,
+
+    #[structopt(long="seq-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    seq_mask: u64,
+
+    /// packet definition file (RON), defaulting to the built in VN200 definition
+    #[structopt(short="d", long="definition", default_value="")]
+    definition: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct CheckArgs {
+    /// packet definition files, possibly versioned, or layout files (RON)
+    #[structopt(raw(required = "true"))]
+    definitions: Vec<String>,
+}
+
+// Options for the packet time, shared by the commands that use time tags.
+#[derive(Debug, StructOpt)]
+struct TimeArgs {
+    /// item holding the packet's time tag, as recorded in the sqlite time column
+    #[structopt(long="time-item", default_value="")]
+    time_item: String,
+
+    /// format of the --time-item, converting it to UTC, such as for a UTC column in each record:
+    /// cuc:<coarse>.<fine>, cds:<days>.<submillis>, gps-week, or seconds[:<unit>]
+    #[structopt(long="time-format")]
+    time_format: Option<String>,
+
+    /// with --time-format, item holding the binary fraction of a second, or the seconds of a GPS week
+    #[structopt(long="time-fine-item", default_value="")]
+    time_fine_item: String,
+
+    /// with --time-format, epoch of the time tag: ccsds, gps, unix, or a date on a time scale,
+    /// such as tai:2000-01-01T12:00:00. defaults to the usual epoch of the format.
+    #[structopt(long="time-epoch")]
+    time_epoch: Option<String>,
+
+    /// with --time-format, leap second table with a date and TAI - UTC on each line,
+    /// defaulting to the built in table
+    #[structopt(long="leap-seconds")]
+    leap_seconds: Option<String>,
+
+    /// with --time-format, csv of spacecraft clock and UTC correlation points to convert the
+    /// time tag through, recorded with each output record
+    #[structopt(long="time-correlation")]
+    time_correlation: Option<String>,
+
+    /// with --time-correlation, linear or piecewise
+    #[structopt(long="interpolation", default_value="piecewise")]
+    interpolation: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct IndexArgs {
+    infile : String,
+
+    indexfile : String,
+
+    /// item identifying the packet, such as an APID
+    #[structopt(long="id-item", default_value="")]
+    id_item: String,
+
+    #[structopt(long="id-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    id_mask: u64,
+
+    /// item holding the packet's sequence count
+    #[structopt(long="seq-item", default_value="")]
+    seq_item: String,
+
+    #[structopt(long="seq-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    seq_mask: u64,
+
+    /// item holding the packet's time tag
+    #[structopt(long="time-item", default_value="")]
+    time_item: String,
+
+    /// packet definition file (RON), defaulting to the built in VN200 definition
+    #[structopt(short="d", long="definition", default_value="")]
+    definition: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct ExtractArgs {
+    infile : String,
+
+    indexfile : String,
+
+    outfile : String,
+
+    /// earliest time tag to extract
+    #[structopt(long="start")]
+    start_time: Option<f64>,
+
+    /// latest time tag to extract
+    #[structopt(long="end")]
+    end_time: Option<f64>,
+
+    /// comma separated ids to extract
+    #[structopt(long="ids", default_value="")]
+    ids: String,
+
+    /// packet definition file (RON), checked against the definitions used to build the index
+    #[structopt(short="d", long="definition", default_value="")]
+    definition: String,
+
+    /// extract even if the definitions have changed since indexing
+    #[structopt(long="force")]
+    force: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct ReplayArgs {
+    /// a gasworks capture, or a raw capture along with --index
+    infile : String,
+
+    /// index of a raw capture, whose time tags give the replay timing
+    #[structopt(long="index")]
+    indexfile: Option<String>,
+
+    /// forward packets to an output, such as udp://127.0.0.1:5000?format=raw (may be repeated)
+    #[structopt(short="o", long="output", raw(number_of_values = "1", required = "true"))]
+    outputs: Vec<String>,
+
+    /// replay this many times faster than recorded
+    #[structopt(long="speed", default_value="1")]
+    speed: f64,
+
+    /// replay as fast as possible, ignoring the recorded timing
+    #[structopt(long="fast")]
+    fast: bool,
+
+    /// earliest time to replay
+    #[structopt(long="start")]
+    start_time: Option<f64>,
+
+    /// latest time to replay
+    #[structopt(long="end")]
+    end_time: Option<f64>,
+
+    /// comma separated ids to replay, when replaying through an index
+    #[structopt(long="ids", default_value="")]
+    ids: String,
+
+    /// item identifying each packet, such as an APID, for outputs that select packets by ids
+    #[structopt(long="id-item", default_value="")]
+    id_item: String,
+
+    #[structopt(long="id-mask", default_value="0xFFFFFFFFFFFFFFFF", parse(try_from_str="parse_int"))]
+    id_mask: u64,
+
+    /// packet definition file (RON), used to render csv and jsonl outputs
+    #[structopt(short="d", long="definition", default_value="")]
+    definition: String,
+
+    /// packets to queue for each output
+    #[structopt(long="queue", default_value="1000")]
+    queue_depth: u32,
+}
+
+fn parse_int(arg: &str) -> ::std::result::Result<u64, ::std::num::ParseIntError> {
+    if arg.starts_with("0x") || arg.starts_with("0X") {
+        u64::from_str_radix(&arg[2..], 16)
+    } else {
+        arg.parse()
+    }
+}
+
+// parse a comma separated list of packet ids, if any are given
+fn parse_ids(ids: &str) -> Result<Option<Vec<u64>>> {
+    if ids.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parsed = Vec::new();
+    for id in ids.split(",") {
+        match parse_int(id.trim()) {
+            Ok(id) => parsed.push(id),
+            Err(err) => bail!("invalid packet id '{}' in --ids: {}", id.trim(), err),
+        }
+    }
+
+    Ok(Some(parsed))
 }
 
 fn get_current_index<T: std::cmp::Ord>(queue: &BinaryHeap<(RevOrd<usize>, T)>) -> usize {
     let revord = queue.peek().unwrap();
     let current_index = &revord.0;
 
-    return current_index.0;
+    current_index.0
 }
 
-main!(|args: Cli, log_level : verbosity| {
-    // Open output file
-    //let mut writer = csv::Writer::from_path(args.outfile).unwrap(); 
-    let mut writer = File::create(args.outfile).unwrap();
+// an item identifying or counting packets, which must be an integer
+fn index_item(loc_item: LocItem, mask: u64) -> Result<IndexItem> {
+    match IndexItem::masked(loc_item, mask) {
+        Ok(item) => Ok(item),
+        Err(err) => bail!("{}", err),
+    }
+}
+
+// the item identifying packets routed to outputs, if one is given
+fn route_id_item(loc_layout: &LocLayout, name: &str, mask: u64, naming: &ColumnNaming) -> Result<Option<IndexItem>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    match find_item(loc_layout, name, naming) {
+        Some(index) => index_item(loc_layout.loc_items[index].clone(), mask).map(Some),
+        None => bail!("--id-item {} is not in the packet definition", name),
+    }
+}
+
+// whether an item's values are integers, as sequence counts must be
+fn is_integer(typ: &Prim) -> bool {
+    match typ {
+        Prim::Int(_) => true,
+        Prim::Float(_) | Prim::Enum(_) => false,
+    }
+}
+
+// find an item by its name or full path name
+fn find_item(loc_layout: &LocLayout, name: &str, naming: &ColumnNaming) -> Option<usize> {
+    loc_layout.loc_items.iter().position(|item| {
+        item.name.last().unwrap() == name || path_name(&item.name, naming) == name
+    })
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum OutputFormat {
+    Csv,
+    Jsonl,
+    Sqlite,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
 
-    let vn200_tlm : LayoutPacketDef = 
+    fn from_str(format: &str) -> ::std::result::Result<OutputFormat, String> {
+        match format {
+            "csv"    => Ok(OutputFormat::Csv),
+            "jsonl"  => Ok(OutputFormat::Jsonl),
+            "sqlite" => Ok(OutputFormat::Sqlite),
+            _        => Err(format!("unknown output format '{}', expected csv, jsonl, or sqlite", format)),
+        }
+    }
+}
+
+fn vn200_packet() -> LayoutPacketDef {
       seq("vn200".to_string(),
           vec!(seq("ccsds_pri".to_string(),
                    vec!(u16_le("packet_word"),
@@ -123,7 +609,7 @@ main!(|args: Cli, log_level : verbosity| {
                         f64_le("gpsPos[1]"),
                         f64_le("gpsPos[2]"),
                         f32_le("gpsUncert[0]"),
-                        f32_le("gpsUncert[2]"),
+                        f32_le("gpsUncert[1]"),
                         f32_le("gpsUncert[2]"),
                         u16_le("vpeStatus"),
                         f32_le("insPosUncertainty"),
@@ -134,129 +620,1495 @@ main!(|args: Cli, log_level : verbosity| {
 
                u16_le("ccsds_crc16")
               )
-          );
-    
-    let num_threads: usize = args.num_threads as usize;
+          )
+}
+
+// The versions of the packet definition, each with its items located.
+struct Definitions {
+    name: Name,
+    registry: DefinitionRegistry<Item>,
+    loc_layouts: HashMap<u64, LocLayout>,
+}
+
+impl Definitions {
+    // A definitions file holds a packet definition, or a list of its versions oldest first.
+    // Without one, the built in VN200 definition is used.
+    fn load(path: &str) -> Result<Definitions> {
+        let versions = if path.is_empty() {
+            vec![VersionDef { valid_from: None, valid_until: None, packet: vn200_packet() }]
+        } else {
+            let text = std::fs::read_to_string(path)?;
+            match ron::de::from_str::<LayoutPacketDef>(&text) {
+                Ok(packet) => vec![VersionDef { valid_from: None, valid_until: None, packet }],
+                Err(err) => match ron::de::from_str::<Vec<VersionDef<Item>>>(&text) {
+                    Ok(versions) => versions,
+                    Err(_) => bail!("{}: {}", path, err),
+                },
+            }
+        };
+        if versions.is_empty() {
+            bail!("{}: no packet definitions", path);
+        }
 
-    let packet : LayoutPacketDef = vn200_tlm;
+        let name = versions[versions.len() - 1].packet.name().cloned().unwrap_or_default();
+        let registry = DefinitionRegistry::from_versions(&name, versions);
 
-    // read whole file
-    let byte_vec: Vec<u8>;
+        // NOTE items whose location depends on the packet's contents can not be located
+        let loc_layouts = registry.versions(&name)
+                                  .iter()
+                                  .filter_map(|version| version.packet.locate().map(|loc_layout| (version.hash, loc_layout)))
+                                  .collect();
 
-    {
-        let mut byte_vec_mut: Vec<u8> = Vec::new();
-        File::open(args.infile).unwrap().read_to_end(&mut byte_vec_mut).unwrap();
-        byte_vec = byte_vec_mut;
+        Ok(Definitions { name, registry, loc_layouts })
     }
 
-    // create packet stream
-    let mut loc_layout = packet.locate().unwrap();
+    fn versions(&self) -> &[DefinitionVersion<Item>] {
+        self.registry.versions(&self.name)
+    }
 
-    // filter items to parse, if provided on the command line
-    if args.items.len() > 0 {
-        // collect provided names into a vector
-        let names: Vec<String> =
-            args.items.split(",").map(|st| st.trim().to_string()).collect();
+    fn latest(&self) -> &DefinitionVersion<Item> {
+        self.registry.latest(&self.name).unwrap()
+    }
 
-        // filter out elements that are not in the given list of items
-        loc_layout.loc_items
-                  .retain(|item| {
-                      names.contains(&item.name.last().unwrap())
-                  });
+    // the version data was recorded with, as found by the hash in its capture or index
+    fn recorded(&self, hash: u64) -> Option<&DefinitionVersion<Item>> {
+        self.registry.select(&self.name, &VersionSelector::Hash(hash))
     }
 
-    // Write CSV header
-    loclayout_csvheader(&loc_layout, &mut writer);
+    // the version data was recorded with, falling back to the latest version if the
+    // definitions have changed since
+    fn recorded_or_latest(&self, hash: u64, path: &str) -> &DefinitionVersion<Item> {
+        match self.recorded(hash) {
+            Some(version) => version,
+            None => {
+                warn!("{} was recorded with different definitions (hash {:016x}), using the latest", path, hash);
+                self.latest()
+            },
+        }
+    }
 
-    // packet stream
-    let packet_stream = PacketStream::new(packet, &byte_vec);
+    // the version valid at a packet's time tag, or the default version if the packet has no
+    // time tag or no version is valid at it
+    fn at_time<'a>(&'a self,
+                   time_item: Option<&LocItem>,
+                   bytes:     &[u8],
+                   default:   &'a DefinitionVersion<Item>) -> &'a DefinitionVersion<Item> {
+        match time_item {
+            Some(time_item) if fits(time_item, bytes) => {
+                let time = decode_loc_item(time_item, &mut Cursor::new(bytes)).val.as_f64();
+                self.registry.at_time(&self.name, time).unwrap_or(default)
+            },
 
-    // if single threaded, decode reach packet and write to csv
-    if args.single_threaded {
-        let mut line = String::new();
+            _ => default,
+        }
+    }
 
-        for packet in packet_stream {
-            let points = decode_loc_layout(&loc_layout, &mut Cursor::new(packet));
+    // with several versions, the item whose time tag selects each packet's version. It is
+    // found in the latest version, so must be at the same location in every version.
+    fn time_item(&self, name: &str, naming: &ColumnNaming) -> Option<LocItem> {
+        if self.versions().len() < 2 {
+            return None;
+        }
 
-            points_to_str(&points, &mut line);
+        let loc_layout = self.loc_layouts.get(&self.latest().hash)?;
+        find_item(loc_layout, name, naming).map(|index| loc_layout.loc_items[index].clone())
+    }
 
-            writer.write(line.as_bytes()).unwrap();
+    fn loc_layout(&self, version: &DefinitionVersion<Item>) -> Result<&LocLayout> {
+        match self.loc_layouts.get(&version.hash) {
+            Some(loc_layout) => Ok(loc_layout),
+            None => bail!("the items of {} can not be located, as their locations depend on the packet's contents", self.name),
         }
     }
-    else {
-        // if multi-threaded, spawn tasks for decoding and writing.
-        let (send_line, receive_line) = channel::bounded(args.line_queue_depth as usize);
 
-        let (pack_sender, pack_receiver) = channel::bounded(args.packet_queue_depth as usize);
+    // keep only the named items in every version
+    fn retain_items(&mut self, names: &[String], naming: &ColumnNaming) {
+        for loc_layout in self.loc_layouts.values_mut() {
+            loc_layout.loc_items
+                      .retain(|item| {
+                          names.contains(item.name.last().unwrap()) ||
+                          names.contains(&path_name(&item.name, naming))
+                      });
+        }
+    }
+}
 
-        crossbeam::scope(|scope| {
-            let mut join_handles = Vec::new();
+// The versions packets are decoded with: a default version, or with a time item, the version
+// valid at each packet's time tag.
+struct Decoding<'a> {
+    definitions: &'a Definitions,
+    default: &'a DefinitionVersion<Item>,
+    time_item: Option<LocItem>,
+    // csv columns covering the items of every version that may be decoded with,
+    // and the column of each located item by version hash
+    columns: Vec<LocPath>,
+    column_indices: HashMap<u64, Vec<usize>>,
+}
 
-            for _ in 0..num_threads {
-                let join_handle = scope.spawn(|| {
-                    while let Some(option_packet) = pack_receiver.recv() {
-                        match option_packet {
-                            Some((packet, index)) => {
-                                let points = decode_loc_layout(&loc_layout, &mut Cursor::new(packet));
-                                let mut line = String::new();
+impl<'a> Decoding<'a> {
+    fn new(definitions: &'a Definitions,
+           default:     &'a DefinitionVersion<Item>,
+           time_item:   Option<LocItem>) -> Result<Decoding<'a>> {
+        let versions: Vec<&DefinitionVersion<Item>> = match time_item {
+            Some(_) => definitions.versions().iter().collect(),
+            None => vec![default],
+        };
 
-                                points_to_str(&points, &mut line);
+        let mut columns: Vec<LocPath> = Vec::new();
+        let mut column_indices = HashMap::new();
+        for version in versions {
+            let indices = definitions.loc_layout(version)?
+                                     .loc_items
+                                     .iter()
+                                     .map(|loc_item| match columns.iter().position(|path| *path == loc_item.name) {
+                                         Some(index) => index,
+                                         None => {
+                                             columns.push(loc_item.name.clone());
+                                             columns.len() - 1
+                                         },
+                                     })
+                                     .collect();
+            column_indices.insert(version.hash, indices);
+        }
 
-                                send_line.send(Some((line, index)));
-                            },
-                            None => break,
-                        }
-                    }
-                });
-                join_handles.push(join_handle);
-            }
+        Ok(Decoding { definitions, default, time_item, columns, column_indices })
+    }
 
-            scope.spawn(|| {
-                let mut to_write = BinaryHeap::new();
-                let mut next_index = 0;
+    fn packet(&self) -> &'a LayoutPacketDef {
+        &self.default.packet
+    }
 
-                while let Some(option_line) = receive_line.recv() {
-                    match option_line {
-                        Some((line, index)) => {
-                            to_write.push((RevOrd(index), line));
+    fn loc_layout(&self) -> &'a LocLayout {
+        &self.definitions.loc_layouts[&self.default.hash]
+    }
 
-                            // process stored lines
-                            while to_write.len() > 0 {
-                                let current_index = get_current_index(&to_write);
-                                // if we have the line we are look for, write to file
-                                if current_index == next_index {
-                                    let (_, line) = to_write.pop().unwrap();
-                                    writer.write(line.as_bytes()).unwrap();
-                                    next_index += 1;
-                                    writer.flush().unwrap();
-                                }
-                                else { // no lines to write
-                                    break;
-                                }
-                            }
-                        },
+    fn select(&self, bytes: &[u8]) -> &'a DefinitionVersion<Item> {
+        self.definitions.at_time(self.time_item.as_ref(), bytes, self.default)
+    }
 
-                        // if we receive None, end the task
-                        None => break,
-                    }
-                }
-            });
+    // the smallest packet any of the versions decodes
+    fn min_size(&self) -> usize {
+        match self.time_item {
+            Some(_) => self.definitions.versions().iter().map(|version| version.packet.size().min).min().unwrap() as usize,
+            None => self.default.packet.size().min as usize,
+        }
+    }
+}
 
-            // send packets to worker streams
-            let mut index = 0;
-            for packet in packet_stream {
-                pack_sender.send(Some((packet, index)));
-                index += 1;
-            }
+// whether a packet is long enough to hold an item
+fn fits(loc_item: &LocItem, bytes: &[u8]) -> bool {
+    bytes.len() as u64 >= loc_item.loc + loc_item.num_bytes()
+}
 
-            // send Nones to clean up workers
-            for _ in 0..num_threads {
-                pack_sender.send(None);
+fn read_file(path: &str) -> Result<Vec<u8>> {
+    let mut byte_vec: Vec<u8> = Vec::new();
+    match File::open(path).and_then(|mut file| file.read_to_end(&mut byte_vec)) {
+        Ok(_) => Ok(byte_vec),
+        Err(err) => bail!("could not read {}: {}", path, err),
+    }
+}
+
+// stamp packets from a live source with the time they were received
+fn stamped<I: Iterator<Item=Vec<u8>>>(packets: I) -> impl Iterator<Item=TimedPacket> {
+    packets.map(|bytes| TimedPacket { time: unix_time_now(), bytes })
+}
+
+// write each packet to the capture being recorded, if any, as it is received. an error
+// writing the capture ends the packets, and is kept to be reported once they are decoded.
+fn recorded<'a, P, I, W>(capture: &'a mut Option<CaptureWriter<W>>,
+                         error:   &'a mut Option<io::Error>,
+                         mut packets: I) -> impl Iterator<Item=P> + 'a
+    where P: AsRef<[u8]> + ReceiveTime + 'a,
+          I: Iterator<Item=P> + 'a,
+          W: Write + 'a {
+    iter::from_fn(move || {
+        if error.is_some() {
+            return None;
+        }
+
+        let received = packets.next()?;
+
+        if let Some(ref mut capture) = capture {
+            let time = received.receive_time().unwrap_or_else(unix_time_now);
+            if let Err(err) = capture.write_packet(time, 0, received.as_ref()) {
+                *error = Some(err);
+                return None;
             }
+        }
 
-            // send None to writer thread to end it.
-            send_line.send(None);
-        });
+        Some(received)
+    })
+}
+
+// pair each packet with its offset, for packets laid end to end
+fn with_offsets<'a, I: Iterator<Item=&'a [u8]>>(packets: I) -> impl Iterator<Item=(u64, &'a [u8])> {
+    packets.scan(0, |offset, packet| {
+        let packet_offset = *offset;
+        *offset += packet.len() as u64;
+        Some((packet_offset, packet))
+    })
+}
+
+fn decode(args: DecodeArgs) -> Result<()> {
+    let format = args.format;
+
+    let naming = match args.naming.as_str() {
+        "full" => ColumnNaming::Full(args.separator.clone()),
+        "last" => ColumnNaming::Last,
+        naming => bail!("unknown column naming '{}', expected full or last", naming),
+    };
+
+    if args.tm_frame.is_some_and(|frame_length| frame_length < FRAME_HEADER_BYTES) {
+        bail!("--tm-frame must be at least the {} bytes of a TM frame header", FRAME_HEADER_BYTES);
     }
-});
 
+    let mut definitions = Definitions::load(&args.definition)?;
+
+    let input = match InputSpec::parse(&args.infile) {
+        Ok(input) => input,
+        Err(err) => bail!("{}", err),
+    };
+
+    // with several versions of the definitions, the time item selects each packet's version
+    let time_item = definitions.time_item(&args.time.time_item, &naming);
+
+    // filter items to parse, if provided on the command line
+    if !args.items.is_empty() {
+        // collect provided names into a vector
+        let names: Vec<String> =
+            args.items.split(",").map(|st| st.trim().to_string()).collect();
+
+        // filter out elements that are not in the given list of items
+        definitions.retain_items(&names, &naming);
+    }
+
+    // packets are decoded with the latest version unless they were recorded with another
+    let decoding = |default| Decoding::new(&definitions, default, time_item.clone());
+    let latest = definitions.latest();
+
+    // live sources deliver packets of the definition's size, or its smallest size if it varies.
+    // datagrams and frames too short to hold a packet are dropped rather then decoded.
+    let num_bytes = latest.packet.size().min as usize;
+
+    match input {
+        InputSpec::File(path) => {
+            if args.follow {
+                let mut source = FollowSource::open(&path, stream_framer(&args, num_bytes)?)?;
+                if let Some(timeout) = args.follow_timeout {
+                    source = source.with_idle_timeout(Duration::from_millis((timeout * 1000.0) as u64));
+                }
+                decode_source(&args, &decoding(latest)?, &naming, format, false, source)
+            } else {
+                let byte_vec = read_file(&path)?;
+
+                if is_capture(&byte_vec) {
+                    let capture = CaptureReader::new(Cursor::new(&byte_vec[..]))?;
+                    let recorded = definitions.recorded_or_latest(capture.header().definition_hash, &path);
+                    decode_source(&args, &decoding(recorded)?, &naming, format, true, capture)
+                } else if args.tm_frame.is_some() || args.stuffing.is_some() {
+                    let frames = FrameReader::new(Cursor::new(&byte_vec[..]), stream_framer(&args, num_bytes)?);
+                    decode_source(&args, &decoding(latest)?, &naming, format, false, frames)
+                } else {
+                    decode_source(&args, &decoding(latest)?, &naming, format, false, PacketStream::new(&latest.packet, &byte_vec))
+                }
+            }
+        },
+
+        InputSpec::Udp(addr) => {
+            let source = UdpSource::bind(addr.as_str())?;
+            decode_source(&args, &decoding(latest)?, &naming, format, true,
+                           stamped(source))
+        },
+
+        InputSpec::TcpClient(addr) => {
+            let source = tcp_client(addr.as_str(), stream_framer(&args, num_bytes)?)?;
+            decode_source(&args, &decoding(latest)?, &naming, format, true, stamped(source))
+        },
+
+        InputSpec::TcpServer(addr) => {
+            let source = TcpServerSource::bind(addr.as_str(), stream_framer(&args, num_bytes)?)?;
+            decode_source(&args, &decoding(latest)?, &naming, format, true, stamped(source))
+        },
+
+        #[cfg(unix)]
+        InputSpec::Serial(path, baud) => {
+            let source = serial_source(&path, baud, stream_framer(&args, num_bytes)?)?;
+            decode_source(&args, &decoding(latest)?, &naming, format, true, stamped(source))
+        },
+
+        #[cfg(not(unix))]
+        InputSpec::Serial(..) => bail!("serial input is only supported on unix"),
+
+        InputSpec::Pcap(path, filter) => {
+            let mut source = PcapSource::new(BufReader::new(File::open(&path)?), filter, stream_framer(&args, num_bytes)?)?;
+            decode_source(&args, &decoding(latest)?, &naming, format, true, source.by_ref())?;
+
+            // a read error or corrupt record ends the packets early, which is an error once they are decoded
+            if let Some(err) = source.take_error() {
+                bail!("pcap {} ended early: {}", path, err);
+            }
+
+            Ok(())
+        },
+    }
+}
+
+// Decode the packets from a source, first taking them out of TM frames if the source carries frames.
+// The source is recorded as it is received, before frames are taken apart or packets are dropped.
+fn decode_source<P, I>(args:       &DecodeArgs,
+                       decoding:   &Decoding,
+                       naming:     &ColumnNaming,
+                       format:     OutputFormat,
+                       receive_times: bool,
+                       source: I) -> Result<()>
+    where P: AsRef<[u8]> + ReceiveTime + Send,
+          I: Iterator<Item=P> {
+    let mut capture = match args.record {
+        Some(ref path) => {
+            let header = CaptureHeader { definition_hash: decoding.default.hash,
+                                         created: unix_time_now(),
+                                         source: args.infile.clone() };
+            let mut capture = CaptureWriter::new(BufWriter::new(File::create(path)?), &header)?;
+
+            // keep live captures up to date on disk, in case we are stopped
+            if InputSpec::parse(&args.infile).is_ok_and(|input| input.is_live()) {
+                capture = capture.with_flush_interval(Duration::from_secs(0));
+            }
+
+            Some(capture)
+        },
+
+        None => None,
+    };
+    let mut record_error = None;
+
+    let source = recorded(&mut capture, &mut record_error, source);
+
+    let decoded = match args.tm_frame {
+        Some(frame_length) => {
+            let packets = tm_packets(args, frame_length, source);
+            decode_products(args, decoding, naming, format, receive_times, packets)
+        },
+
+        None => decode_products(args, decoding, naming, format, receive_times, source),
+    };
+
+    if let (Some(path), Some(err)) = (args.record.as_ref(), record_error) {
+        bail!("could not record to {}: {}", path, err);
+    }
+    decoded?;
+
+    if let Some(capture) = capture {
+        capture.into_inner()?;
+    }
+
+    Ok(())
+}
+
+// Decode space packets, first joining segmented products if asked to.
+fn decode_products<P, I>(args:       &DecodeArgs,
+                         decoding:   &Decoding,
+                         naming:     &ColumnNaming,
+                         format:     OutputFormat,
+                         receive_times: bool,
+                         packets: I) -> Result<()>
+    where P: AsRef<[u8]> + ReceiveTime + Send,
+          I: Iterator<Item=P> {
+    // framed packets, such as from byte stuffing or a length field, may be too short to decode
+    let num_bytes = decoding.min_size();
+
+    let packets = checked_packets(continuity_checker(args, decoding.loc_layout(), naming)?, packets);
+
+    if !args.reassemble {
+        let packets = packets.filter(|packet| packet.as_ref().len() >= num_bytes);
+        return decode_packets(args, decoding, naming, format, receive_times, packets);
+    }
+
+    let mut products = Reassembly::new(packets, Reassembler::new().with_header_bytes(args.segment_header));
+
+    let products = iter::from_fn(move || {
+        loop {
+            let product = products.next();
+
+            for problem in products.reassembler_mut().take_problems() {
+                match problem {
+                    SegmentProblem::Incomplete { apid, seq_count, missing } =>
+                        warn!("dropped incomplete product on APID {} starting at {}, missing segments {:?}", apid, seq_count, missing),
+                    SegmentProblem::OutOfOrder { apid, seq_count } =>
+                        warn!("segment {} on APID {} arrived out of order", seq_count, apid),
+                    SegmentProblem::Orphan { apid, seq_count } =>
+                        warn!("dropped segment {} on APID {} with no first segment", seq_count, apid),
+                }
+            }
+
+            match product {
+                Some(product) => {
+                    if product.bytes.len() >= num_bytes {
+                        return Some(product);
+                    }
+                },
+
+                None => return None,
+            }
+        }
+    });
+
+    decode_packets(args, decoding, naming, format, receive_times, products)
+}
+
+// A packet time, warning the first time a time tag is extrapolated past its time correlation.
+struct PacketClock {
+    time: PacketTime,
+    correlation: Option<String>,
+    warned: AtomicBool,
+}
+
+impl PacketClock {
+    fn new(time: PacketTime) -> PacketClock {
+        let correlation = time.correlation.as_ref().map(|correlation| correlation.describe());
+        PacketClock { time, correlation, warned: AtomicBool::new(false) }
+    }
+
+    fn utc(&self, bytes: &[u8]) -> Option<f64> {
+        let correlated = self.time.correlated(bytes)?;
+
+        if correlated.extrapolated && !self.warned.swap(true, Ordering::Relaxed) {
+            if let Some(ref correlation) = self.time.correlation {
+                let points = correlation.points();
+                warn!("extrapolating packet times outside the time correlation points, from spacecraft clock {} to {}",
+                      points[0].sclk, points[points.len() - 1].sclk);
+            }
+        }
+
+        Some(correlated.utc)
+    }
+}
+
+// The packet time given by --time-item and --time-format, if any.
+fn packet_clock(args: &TimeArgs, loc_layout: &LocLayout, naming: &ColumnNaming) -> Result<Option<PacketClock>> {
+    let format = match args.time_format {
+        Some(ref format) => match TimeFormat::parse(format) {
+            Ok(format) => format,
+            Err(err) => bail!("{}", err),
+        },
+        None => return Ok(None),
+    };
+
+    let find = |name: &str| match find_item(loc_layout, name, naming) {
+        Some(index) => Ok(loc_layout.loc_items[index].clone()),
+        None => bail!("no item named '{}' in the packet definition", name),
+    };
+
+    let mut packet_time = PacketTime::new(find(&args.time_item)?, format);
+    if !args.time_fine_item.is_empty() {
+        packet_time = match packet_time.with_fine(find(&args.time_fine_item)?) {
+            Ok(packet_time) => packet_time,
+            Err(err) => bail!("{}", err),
+        };
+    }
+    if let Some(ref epoch) = args.time_epoch {
+        packet_time = match Epoch::parse(epoch) {
+            Ok(epoch) => packet_time.with_epoch(epoch),
+            Err(err) => bail!("{}", err),
+        };
+    }
+    if let Some(ref path) = args.leap_seconds {
+        let text = std::fs::read_to_string(path)?;
+        packet_time = match LeapSeconds::parse(&text) {
+            Ok(leap_seconds) => packet_time.with_leap_seconds(leap_seconds),
+            Err(err) => bail!("{}: {}", path, err),
+        };
+    }
+    if let Some(ref path) = args.time_correlation {
+        let interpolation = match Interpolation::parse(&args.interpolation) {
+            Ok(interpolation) => interpolation,
+            Err(err) => bail!("{}", err),
+        };
+        let correlation = correlation_points_csv(File::open(path)?)
+                              .and_then(|points| TimeCorrelation::new(path, points, interpolation));
+        packet_time = match correlation {
+            Ok(correlation) => packet_time.with_correlation(correlation),
+            Err(err) => bail!("{}: {}", path, err),
+        };
+    }
+
+    Ok(Some(PacketClock::new(packet_time)))
+}
+
+fn continuity_checker(args: &DecodeArgs, loc_layout: &LocLayout, naming: &ColumnNaming) -> Result<Option<ContinuityChecker>> {
+    if !args.check_continuity {
+        return Ok(None);
+    }
+
+    let find = |name: &str| match find_item(loc_layout, name, naming) {
+        Some(index) => Ok(loc_layout.loc_items[index].clone()),
+        None => bail!("no item named '{}' in the packet definition", name),
+    };
+
+    let mut checker = ContinuityChecker::new(index_item(find(&args.seq_item)?, args.seq_mask)?);
+    if !args.stream_item.is_empty() {
+        checker = checker.with_stream(index_item(find(&args.stream_item)?, args.stream_mask)?);
+    }
+
+    Ok(Some(checker))
+}
+
+// Check packets for continuity as they pass, warning about each problem as it is found
+// and summarizing each stream at the end.
+fn checked_packets<P, I>(checker: Option<ContinuityChecker>, packets: I) -> impl Iterator<Item=P>
+    where P: AsRef<[u8]>,
+          I: Iterator<Item=P> {
+    let mut checker = checker;
+    let mut packets = packets;
+
+    iter::from_fn(move || {
+        let packet = packets.next();
+
+        match packet {
+            Some(ref packet) => {
+                if let Some(ref mut checker) = checker {
+                    checker.check(packet.as_ref());
+
+                    for event in checker.take_events() {
+                        match event {
+                            ContinuityEvent::Gap { stream, expected, found, missing } =>
+                                warn!("lost {} packets on stream {}, expected sequence count {} but found {}", missing, stream, expected, found),
+                            ContinuityEvent::Duplicate { stream, seq } =>
+                                warn!("repeated sequence count {} on stream {}", seq, stream),
+                            ContinuityEvent::OutOfOrder { stream, expected, found } =>
+                                warn!("sequence count {} on stream {} arrived out of order, expected {}", found, stream, expected),
+                        }
+                    }
+                }
+            },
+
+            None => {
+                // summarize once, at the end of the packets
+                if let Some(checker) = checker.take() {
+                    for (stream, summary) in checker.summary() {
+                        eprintln!("stream {}: {} packets, sequence counts {} to {}, {} missing in {} gaps, {} repeated, {} out of order",
+                                  stream, summary.packets, summary.first_seq, summary.last_seq,
+                                  summary.missing, summary.gaps, summary.duplicates, summary.out_of_order);
+                    }
+                }
+            },
+        }
+
+        packet
+    })
+}
+
+// Take space packets out of TM frames, warning about frame count gaps as they are found.
+// Packets from other virtual channels are skipped.
+fn tm_packets<P, I>(args: &DecodeArgs, frame_length: usize, frames: I) -> impl Iterator<Item=TmPacket>
+    where P: AsRef<[u8]> + ReceiveTime,
+          I: Iterator<Item=P> {
+    let mut demux = TmDemux::new(frame_length);
+    if args.tm_fecf {
+        demux = demux.with_fecf();
+    }
+    if let Some(spacecraft_id) = args.spacecraft_id {
+        demux = demux.with_spacecraft_id(spacecraft_id as u16);
+    }
+
+    let marker_len = parse_hex(&args.sync).map_or(0, |marker| marker.len());
+    let mut packets = TmPackets::new(frames, demux).with_marker_len(marker_len);
+    let virtual_channel = args.virtual_channel;
+
+    iter::from_fn(move || {
+        loop {
+            let packet = packets.next();
+
+            for gap in packets.demux_mut().take_gaps() {
+                match gap.virtual_channel {
+                    Some(channel) => warn!("lost {} frames on virtual channel {}", gap.missing(), channel),
+                    None => warn!("lost {} frames on the master channel", gap.missing()),
+                }
+            }
+
+            match packet {
+                Some(packet) => {
+                    if virtual_channel.is_none_or(|channel| channel == packet.virtual_channel) {
+                        return Some(packet);
+                    }
+                },
+
+                None => {
+                    let demux = packets.demux();
+                    if demux.bad_frames() > 0 {
+                        warn!("dropped {} bad TM frames of {}", demux.bad_frames(), demux.frames() + demux.bad_frames());
+                    }
+                    return None;
+                },
+            }
+        }
+    })
+}
+
+// The framer for stream inputs, either by sync marker and length or by byte stuffing.
+#[derive(Debug, Clone)]
+enum StreamFramer {
+    Sync(SyncFramer),
+    Stuffed(StuffedFramer),
+}
+
+impl Framer for StreamFramer {
+    fn push(&mut self, bytes: &[u8]) {
+        match self {
+            StreamFramer::Sync(framer) => framer.push(bytes),
+            StreamFramer::Stuffed(framer) => framer.push(bytes),
+        }
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        match self {
+            StreamFramer::Sync(framer) => framer.next_packet(),
+            StreamFramer::Stuffed(framer) => framer.next_packet(),
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        match self {
+            StreamFramer::Sync(framer) => framer.buffered(),
+            StreamFramer::Stuffed(framer) => framer.buffered(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            StreamFramer::Sync(framer) => framer.clear(),
+            StreamFramer::Stuffed(framer) => framer.clear(),
+        }
+    }
+}
+
+// Build the framer for stream inputs from the stuffing, sync marker, and length field options.
+fn stream_framer(args: &DecodeArgs, num_bytes: usize) -> Result<StreamFramer> {
+    if let Some(ref name) = args.stuffing {
+        if !args.sync.is_empty() || args.length_offset.is_some() || args.ccsds {
+            bail!("--stuffing frames by delimiters, so --sync, --length-offset, and --ccsds do not apply");
+        }
+
+        return match Stuffing::parse(name) {
+            Ok(stuffing) => Ok(StreamFramer::Stuffed(StuffedFramer::new(stuffing))),
+            Err(err) => bail!("{}", err),
+        };
+    }
+
+    let marker = match parse_hex(&args.sync) {
+        Some(marker) => marker,
+        None => bail!("invalid sync marker '{}', expected hex bytes", args.sync),
+    };
+
+    if args.ccsds && args.length_offset.is_some() {
+        bail!("--ccsds already frames by the space packet length, so --length-offset is not needed");
+    }
+    if args.tm_frame.is_some() && (args.ccsds || args.length_offset.is_some()) {
+        bail!("TM frames have a fixed length, so --ccsds and --length-offset do not apply");
+    }
+
+    let length = match args.length_offset {
+        _ if args.tm_frame.is_some() => FrameLength::Fixed(marker.len() + args.tm_frame.unwrap()),
+
+        _ if args.ccsds => FrameLength::Field(ccsds::length_field()),
+
+        Some(offset) => {
+            let size = match args.length_size {
+                1 => IntSize::Bits8,
+                2 => IntSize::Bits16,
+                4 => IntSize::Bits32,
+                size => bail!("invalid length field size {}, expected 1, 2, or 4", size),
+            };
+            let endianness = if args.length_le { Endianness::LittleEndian } else { Endianness::BigEndian };
+
+            FrameLength::Field(LengthField::new(offset,
+                                                IntPrim::new(size, Signedness::Unsigned, endianness),
+                                                args.length_adjust))
+        },
+
+        // packets that may be empty, such as those made only of variable arrays, have no length to frame by
+        None if num_bytes == 0 =>
+            bail!("the packets have no fixed size to frame a stream by, so it needs --ccsds, --length-offset, or --stuffing"),
+
+        None if num_bytes < marker.len() =>
+            bail!("the packets are {} bytes, too short to start with the {} byte sync marker", num_bytes, marker.len()),
+
+        None => FrameLength::Fixed(num_bytes),
+    };
+
+    Ok(StreamFramer::Sync(SyncFramer::new(marker, length)))
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim_start_matches("0x");
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0 .. hex.len() / 2).map(|index| u8::from_str_radix(&hex[index * 2 .. index * 2 + 2], 16).ok())
+                        .collect()
+}
+
+// Decode packets from any source, writing them in the order they were received.
+fn decode_packets<P, I>(args:       &DecodeArgs,
+                        decoding:   &Decoding,
+                        naming:     &ColumnNaming,
+                        format:     OutputFormat,
+                        receive_times: bool,
+                        packet_stream: I) -> Result<()>
+    where P: AsRef<[u8]> + ReceiveTime + Send,
+          I: Iterator<Item=P> {
+    let num_threads: usize = args.num_threads as usize;
+
+    // open forwarding outputs
+    let mut router = Router::new(args.output_queue_depth as usize);
+    for output in args.outputs.iter() {
+        let spec = match SinkSpec::parse(output) {
+            Ok(spec) => spec,
+            Err(err) => bail!("{}", err),
+        };
+        router.add(spec)?;
+    }
+
+    // assemble major frames rather then decoding each packet on its own
+    if args.cycle.is_some() {
+        return decode_cycles(args, decoding.loc_layout(), naming, format, receive_times, packet_stream);
+    }
+
+    let packet_clock = packet_clock(&args.time, decoding.loc_layout(), naming)?;
+
+    let route_csv = router.needs(SinkFormat::Csv);
+    let route_jsonl = router.needs(SinkFormat::Jsonl);
+    let routing = !router.is_empty();
+    let id_item = route_id_item(decoding.loc_layout(), &args.id_item, args.id_mask, naming)?;
+
+    // prepare a packet for forwarding, rendering only the lines some output asks for
+    let packet_record = |received: &P| -> Option<SinkRecord> {
+        if !routing {
+            return None;
+        }
+
+        Some(sink_record(decoding, packet_clock.as_ref(), id_item.as_ref(), received.as_ref(), received.receive_time(), route_csv, route_jsonl))
+    };
+
+    // archive packets into an sqlite database rather then writing lines of output
+    if format == OutputFormat::Sqlite {
+        let packet = decoding.packet();
+        let loc_layout = decoding.loc_layout();
+        let name = match packet.name() {
+            Some(name) => name,
+            None => bail!("the definition has no name to name its archive table"),
+        };
+        let time_index = find_item(loc_layout, &args.time.time_item, naming);
+        let seq_index = find_item(loc_layout, &args.seq_item, naming);
+        if let Some(index) = seq_index {
+            if !is_integer(&loc_layout.loc_items[index].typ) {
+                bail!("--seq-item {} is not an integer item", args.seq_item);
+            }
+        }
+
+        let mut archive = SqliteArchive::open(&args.outfile)?;
+        archive.add_packet(name, packet, loc_layout)?;
+        if let Some(correlation) = packet_clock.as_ref().and_then(|clock| clock.correlation.as_ref()) {
+            archive.record_time_correlation(name, correlation, unix_time_now())?;
+        }
+
+        // NOTE an archive holds one version of a packet, so packets are decoded with the
+        // default version even if their time tags fall in another
+        let mut other_version = 0;
+
+        for received in packet_stream {
+            if decoding.select(received.as_ref()).hash != decoding.default.hash {
+                other_version += 1;
+            }
+
+            let points = decode_loc_layout(loc_layout, &mut Cursor::new(received.as_ref()));
+            // the packet time in UTC or the raw time item if given, otherwise the time the packet was received
+            let time = match packet_clock {
+                Some(ref packet_clock) => packet_clock.utc(received.as_ref()),
+                None => time_index.map(|index| points[index].val.as_f64()),
+            };
+            let time = time.or(received.receive_time());
+            let seq = seq_index.map(|index| points[index].val.value());
+
+            archive.insert(name, time, seq, &points)?;
+
+            if let Some(record) = packet_record(&received) {
+                router.route(&record);
+            }
+        }
+        archive.flush()?;
+
+        if other_version > 0 {
+            warn!("archived {} packets whose time tags fall in other versions of the definitions", other_version);
+        }
+
+        router.close();
+    check_outputs(&mut router)?;
+    report_dropped(&router);
+
+        return Ok(());
+    }
+
+    // Open output file
+    let mut writer = File::create(&args.outfile).unwrap();
+
+    // Write CSV header
+    if format == OutputFormat::Csv {
+        if receive_times {
+            writer.write_all(b"receive_time,").unwrap();
+        }
+        if let Some(ref packet_clock) = packet_clock {
+            writer.write_all(b"utc,").unwrap();
+            if packet_clock.correlation.is_some() {
+                writer.write_all(b"correlation,").unwrap();
+            }
+        }
+        let mut line = String::new();
+        paths_to_str(&decoding.columns, naming, &mut line);
+        writer.write_all(line.as_bytes()).unwrap();
+    }
+
+    let packet_name = if args.include_name { decoding.packet().name().map(|name| name.as_str()) } else { None };
+
+    // decode a packet into a line of output
+    let packet_line = |received: &P, line: &mut String| {
+        match format {
+            OutputFormat::Csv => {
+                csv_line(decoding, packet_clock.as_ref(), received.as_ref(), received.receive_time(), line);
+            },
+
+            OutputFormat::Jsonl => {
+                let packet = &decoding.select(received.as_ref()).packet;
+                let value_map = match decode_layoutpacket(packet, &mut Cursor::new(received.as_ref())) {
+                    Ok(value_map) => value_map,
+                    Err(err) => {
+                        // packets that can not be decoded are left out of the output
+                        warn!("{}", err);
+                        line.clear();
+                        return;
+                    },
+                };
+                let utc = packet_clock.as_ref().and_then(|packet_clock| packet_clock.utc(received.as_ref())).map(utc_string);
+                let correlation = packet_clock.as_ref().and_then(|packet_clock| packet_clock.correlation.as_deref());
+                valuemap_jsonl(&value_map, packet_name, received.receive_time(), utc.as_deref(), correlation, line);
+            },
+
+            OutputFormat::Sqlite => unreachable!(),
+        }
+    };
+
+    // if single threaded, decode reach packet and write to csv
+    if args.single_threaded {
+        let mut line = String::new();
+
+        for packet in packet_stream {
+            packet_line(&packet, &mut line);
+
+            writer.write_all(line.as_bytes()).unwrap();
+
+            if let Some(record) = packet_record(&packet) {
+                router.route(&record);
+            }
+        }
+    }
+    else {
+        // if multi-threaded, spawn tasks for decoding and writing.
+        let (send_line, receive_line) = channel::bounded(args.line_queue_depth as usize);
+
+        let (pack_sender, pack_receiver) = channel::bounded::<Option<(P, usize)>>(args.packet_queue_depth as usize);
+
+        crossbeam::scope(|scope| {
+            let mut join_handles = Vec::new();
+
+            for _ in 0..num_threads {
+                let join_handle = scope.spawn(|| {
+                    while let Some(option_packet) = pack_receiver.recv() {
+                        match option_packet {
+                            Some((packet, index)) => {
+                                let mut line = String::new();
+
+                                packet_line(&packet, &mut line);
+
+                                let record = packet_record(&packet);
+
+                                send_line.send(Some((line, record, index)));
+                            },
+                            None => break,
+                        }
+                    }
+                });
+                join_handles.push(join_handle);
+            }
+
+            scope.spawn(|| {
+                let mut to_write = BinaryHeap::new();
+                let mut to_route = HashMap::new();
+                let mut next_index = 0;
+
+                while let Some(option_line) = receive_line.recv() {
+                    match option_line {
+                        Some((line, record, index)) => {
+                            to_write.push((RevOrd(index), line));
+                            if let Some(record) = record {
+                                to_route.insert(index, record);
+                            }
+
+                            // process stored lines
+                            while !to_write.is_empty() {
+                                let current_index = get_current_index(&to_write);
+                                // if we have the line we are look for, write to file
+                                if current_index == next_index {
+                                    let (_, line) = to_write.pop().unwrap();
+                                    writer.write_all(line.as_bytes()).unwrap();
+                                    if let Some(record) = to_route.remove(&next_index) {
+                                        router.route(&record);
+                                    }
+                                    next_index += 1;
+                                    writer.flush().unwrap();
+                                }
+                                else { // no lines to write
+                                    break;
+                                }
+                            }
+                        },
+
+                        // if we receive None, end the task
+                        None => break,
+                    }
+                }
+            });
+
+            // send packets to worker streams
+            for (index, packet) in packet_stream.enumerate() {
+                pack_sender.send(Some((packet, index)));
+            }
+
+            // send Nones to clean up workers
+            for _ in 0..num_threads {
+                pack_sender.send(None);
+            }
+
+            // wait for the workers to send their last lines
+            for join_handle in join_handles {
+                join_handle.join().unwrap();
+            }
+
+            // send None to writer thread to end it.
+            send_line.send(None);
+        });
+    }
+
+    router.close();
+    check_outputs(&mut router)?;
+    report_dropped(&router);
+
+    Ok(())
+}
+
+// Decode a packet into a csv line, with its receive time first if the source records one,
+// then its time in UTC and the time correlation used, if it has a packet time.
+// Items missing from the packet's version of the definitions are left empty.
+fn csv_line(decoding: &Decoding, packet_clock: Option<&PacketClock>, bytes: &[u8], receive_time: Option<f64>, line: &mut String) {
+    let version = decoding.select(bytes);
+    let loc_layout = &decoding.definitions.loc_layouts[&version.hash];
+
+    line.clear();
+    if (bytes.len() as u64) < loc_layout.num_bytes() {
+        // packets too short for their version are left out of the output
+        warn!("dropped a packet of {} bytes, too short for its version of the definitions", bytes.len());
+        return;
+    }
+
+    let mut values = vec![String::new(); decoding.columns.len()];
+    let points = decode_loc_layout(loc_layout, &mut Cursor::new(bytes));
+    for (point, &column) in points.iter().zip(decoding.column_indices[&version.hash].iter()) {
+        values[column] = point.val.to_string();
+    }
+    for value in values {
+        line.push_str(&value);
+        line.push(',');
+    }
+    line.push('\n');
+
+    if let Some(packet_clock) = packet_clock {
+        if let Some(ref correlation) = packet_clock.correlation {
+            line.insert_str(0, &format!("{},", correlation));
+        }
+        let utc = packet_clock.utc(bytes).map_or(String::new(), utc_string);
+        line.insert_str(0, &format!("{},", utc));
+    }
+
+    if let Some(time) = receive_time {
+        line.insert_str(0, &format!("{},", time));
+    }
+}
+
+// Assemble packets into major frames with a subcommutation cycle definition, writing a csv
+// row for each complete major frame. Incomplete cycles are warned about as they are found
+// and counted at the end.
+fn decode_cycles<P, I>(args:       &DecodeArgs,
+                       loc_layout: &LocLayout,
+                       naming:     &ColumnNaming,
+                       format:     OutputFormat,
+                       receive_times: bool,
+                       packets:    I) -> Result<()>
+    where P: AsRef<[u8]> + ReceiveTime,
+          I: Iterator<Item=P> {
+    if format != OutputFormat::Csv {
+        bail!("major frames from --cycle are only written as csv");
+    }
+    if !args.outputs.is_empty() {
+        bail!("major frames from --cycle can not be forwarded");
+    }
+
+    let path = args.cycle.as_ref().unwrap();
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    let def: CycleDef = match ron::de::from_str(&text) {
+        Ok(def) => def,
+        Err(err) => bail!("{}: {}", path, err),
+    };
+
+    let counter = match find_item(loc_layout, &def.counter, naming) {
+        Some(index) => index_item(loc_layout.loc_items[index].clone(), !0)?,
+        None => bail!("no item named '{}' in the packet definition", def.counter),
+    };
+    let mut decoder = match CycleDecoder::new(counter, &def) {
+        Ok(decoder) => decoder,
+        Err(err) => bail!("{}: {}", path, err),
+    };
+
+    let mut writer = BufWriter::new(File::create(&args.outfile)?);
+    let mut line = String::new();
+
+    if receive_times {
+        writer.write_all(b"receive_time,")?;
+    }
+    paths_to_str(decoder.paths(), naming, &mut line);
+    writer.write_all(line.as_bytes())?;
+
+    let mut complete = 0;
+    let mut incomplete = 0;
+    let mut warn_problems = |decoder: &mut CycleDecoder| {
+        for problem in decoder.take_problems() {
+            match problem {
+                CycleProblem::Incomplete { missing } => {
+                    incomplete += 1;
+                    warn!("incomplete major frame, missing minor frames {:?}", missing);
+                },
+                CycleProblem::BadCounter { counter } =>
+                    warn!("dropped packet with frame count {} outside the cycle", counter),
+                CycleProblem::Short { frame } =>
+                    warn!("dropped minor frame {} too short for its parameters", frame),
+            }
+        }
+    };
+
+    for received in packets {
+        let major_frame = decoder.push(received.as_ref(), received.receive_time());
+        warn_problems(&mut decoder);
+
+        if let Some(major_frame) = major_frame {
+            line.clear();
+            if receive_times {
+                if let Some(time) = major_frame.time {
+                    line.push_str(&time.to_string());
+                }
+                line.push(',');
+            }
+            for value in major_frame.values.iter() {
+                line.push_str(&value.to_string());
+                line.push(',');
+            }
+            line.push('\n');
+
+            writer.write_all(line.as_bytes())?;
+            complete += 1;
+        }
+    }
+
+    decoder.finish();
+    warn_problems(&mut decoder);
+
+    eprintln!("{} complete major frames, {} incomplete", complete, incomplete);
+
+    Ok(())
+}
+
+// Prepare a packet for forwarding, rendering only the lines some output asks for.
+fn sink_record(decoding: &Decoding, packet_clock: Option<&PacketClock>, id_item: Option<&IndexItem>,
+               bytes: &[u8], receive_time: Option<f64>, route_csv: bool, route_jsonl: bool) -> SinkRecord {
+    let packet = &decoding.select(bytes).packet;
+    let record_name = packet.name().map_or("", |name| name.as_str());
+    let mut record = SinkRecord::new(record_name, bytes);
+    record.id = id_item.filter(|id_item| fits(&id_item.loc_item, bytes))
+                       .map(|id_item| id_item.decode(&mut Cursor::new(bytes)));
+
+    if route_csv {
+        let mut line = String::new();
+        csv_line(decoding, packet_clock, bytes, receive_time, &mut line);
+        record.csv = Some(Arc::new(line));
+    }
+
+    // packets that can not be decoded are not forwarded to jsonl outputs
+    if route_jsonl {
+        if let Ok(value_map) = decode_layoutpacket(packet, &mut Cursor::new(bytes)) {
+            let mut line = String::new();
+            let utc = packet_clock.and_then(|packet_clock| packet_clock.utc(bytes)).map(utc_string);
+            let correlation = packet_clock.and_then(|packet_clock| packet_clock.correlation.as_deref());
+            valuemap_jsonl(&value_map, Some(record_name), receive_time, utc.as_deref(), correlation, &mut line);
+            record.jsonl = Some(Arc::new(line));
+        }
+    }
+
+    record
+}
+
+// fail if an output could not be written
+fn check_outputs(router: &mut Router) -> Result<()> {
+    match router.take_error() {
+        Some((destination, err)) => bail!("could not write to output {:?}: {}", destination, err),
+        None => Ok(()),
+    }
+}
+
+// warn about packets an output could not keep up with
+fn report_dropped(router: &Router) {
+    for (spec, dropped) in router.dropped() {
+        if dropped > 0 {
+            warn!("dropped {} packets for output {:?}", dropped, spec.destination);
+        }
+    }
+}
+
+fn index(args: IndexArgs) -> Result<()> {
+    let definitions = Definitions::load(&args.definition)?;
+    let naming = ColumnNaming::default();
+
+    let byte_vec = read_file(&args.infile)?;
+
+    // an index records one version of the definitions, the one valid at the first packet's time tag
+    let latest = definitions.latest();
+    let time_item = definitions.time_item(&args.time_item, &naming);
+    let version = match PacketStream::new(&latest.packet, &byte_vec).next() {
+        Some(first) => definitions.at_time(time_item.as_ref(), first, latest),
+        None => latest,
+    };
+    let loc_layout = definitions.loc_layout(version)?;
+
+    let find = |name: &str| find_item(loc_layout, name, &naming).map(|index| loc_layout.loc_items[index].clone());
+
+    let config = IndexConfig {
+        id: find(&args.id_item).map(|loc_item| index_item(loc_item, args.id_mask)).transpose()?,
+        seq: find(&args.seq_item).map(|loc_item| index_item(loc_item, args.seq_mask)).transpose()?,
+        time: find(&args.time_item),
+    };
+
+    let packet_stream = PacketStream::new(&version.packet, &byte_vec);
+
+    let index = build_index(with_offsets(packet_stream), &config, version.hash);
+
+    let other_version = index.entries.iter()
+                                     .filter_map(|entry| entry.time)
+                                     .filter(|time| definitions.registry.at_time(&definitions.name, *time)
+                                                                       .is_some_and(|other| other.hash != version.hash))
+                                     .count();
+    if time_item.is_some() && other_version > 0 {
+        warn!("indexed {} packets whose time tags fall in other versions of the definitions", other_version);
+    }
+
+    let mut writer = BufWriter::new(File::create(&args.indexfile)?);
+    index.write(&mut writer)?;
+
+    Ok(())
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let definitions = Definitions::load(&args.definition)?;
+
+    let index = PacketIndex::read(&mut BufReader::new(File::open(&args.indexfile)?))?;
+
+    if definitions.recorded(index.definition_hash).is_none() && !args.force {
+        bail!("the packet definitions have changed since {} was built", args.indexfile);
+    }
+
+    let ids = parse_ids(&args.ids)?;
+
+    let query = IndexQuery { start_time: args.start_time, end_time: args.end_time, ids };
+    let entries = index.query(&query);
+
+    let packets = extract_packets(&mut File::open(&args.infile)?, &entries)?;
+
+    let mut writer = File::create(&args.outfile)?;
+    for packet in packets {
+        writer.write_all(&packet)?;
+    }
+
+    Ok(())
+}
+
+fn replay(args: ReplayArgs) -> Result<()> {
+    let definitions = Definitions::load(&args.definition)?;
+    let naming = ColumnNaming::default();
+
+    let rate = if args.fast {
+        ReplayRate::Unpaced
+    } else if args.speed > 0.0 {
+        ReplayRate::Scaled(args.speed)
+    } else {
+        bail!("the replay speed must be positive, not {}", args.speed);
+    };
+
+    // replaying is for reproducing a recording, so outputs block rather then drop packets
+    let mut router = Router::lossless(args.queue_depth as usize);
+    for output in args.outputs.iter() {
+        let spec = match SinkSpec::parse(output) {
+            Ok(spec) => spec,
+            Err(err) => bail!("{}", err),
+        };
+        router.add(spec)?;
+    }
+    let route_csv = router.needs(SinkFormat::Csv);
+    let route_jsonl = router.needs(SinkFormat::Jsonl);
+
+    let in_range = |time: f64| args.start_time.is_none_or(|start| time >= start) &&
+                               args.end_time.is_none_or(|end| time <= end);
+
+    match args.indexfile {
+        Some(ref indexfile) => {
+            let index = PacketIndex::read(&mut BufReader::new(File::open(indexfile)?))?;
+            let decoding = Decoding::new(&definitions, definitions.recorded_or_latest(index.definition_hash, indexfile), None)?;
+            let id_item = route_id_item(decoding.loc_layout(), &args.id_item, args.id_mask, &naming)?;
+
+            let ids = parse_ids(&args.ids)?;
+
+            let query = IndexQuery { start_time: args.start_time, end_time: args.end_time, ids };
+            let byte_vec = read_file(&args.infile)?;
+            let packets = match indexed_packets(&byte_vec, &index.query(&query)) {
+                Some(packets) => packets,
+                None => bail!("{} has packets past the end of {}, so it is not an index of it", indexfile, args.infile),
+            };
+
+            for timed in Replay::new(packets.into_iter(), rate) {
+                router.route(&sink_record(&decoding, None, id_item.as_ref(), &timed.bytes, Some(timed.time), route_csv, route_jsonl));
+                check_outputs(&mut router)?;
+            }
+        },
+
+        None => {
+            if !args.ids.is_empty() {
+                bail!("--ids needs an --index to find packet ids");
+            }
+
+            let mut reader = CaptureReader::new(BufReader::new(File::open(&args.infile)?))?;
+            let decoding = Decoding::new(&definitions, definitions.recorded_or_latest(reader.header().definition_hash, &args.infile), None)?;
+            let id_item = route_id_item(decoding.loc_layout(), &args.id_item, args.id_mask, &naming)?;
+
+            let records = reader.by_ref().filter(|record| in_range(record.time));
+            for record in Replay::new(records, rate) {
+                router.route(&sink_record(&decoding, None, id_item.as_ref(), &record.bytes, Some(record.time), route_csv, route_jsonl));
+                check_outputs(&mut router)?;
+            }
+
+            if let Some(err) = reader.take_error() {
+                warn!("capture {} ended early: {}", args.infile, err);
+            }
+        },
+    }
+
+    router.close();
+    check_outputs(&mut router)?;
+
+    Ok(())
+}
+
+// The items a packet is merged by, found in the version of the definitions it was recorded with.
+struct MergeItems {
+    packet_clock: Option<PacketClock>,
+    time_item: Option<LocItem>,
+    seq_item: Option<IndexItem>,
+}
+
+fn merge_items(args: &MergeArgs, loc_layout: &LocLayout, naming: &ColumnNaming) -> Result<MergeItems> {
+    Ok(MergeItems {
+        packet_clock: packet_clock(&args.time, loc_layout, naming)?,
+        time_item: find_item(loc_layout, &args.time.time_item, naming).map(|index| loc_layout.loc_items[index].clone()),
+        seq_item: find_item(loc_layout, &args.seq_item, naming)
+                      .map(|index| index_item(loc_layout.loc_items[index].clone(), args.seq_mask))
+                      .transpose()?,
+    })
+}
+
+fn merge(args: MergeArgs) -> Result<()> {
+    let definitions = Definitions::load(&args.definition)?;
+    let naming = ColumnNaming::default();
+
+    let files: Vec<Vec<u8>> = args.infiles.iter().map(|path| read_file(path)).collect::<Result<_>>()?;
+
+    // each input is merged with the version of the definitions it was recorded with, and
+    // raw captures with the latest version
+    let mut versions = Vec::new();
+    for (path, byte_vec) in args.infiles.iter().zip(files.iter()) {
+        if is_capture(byte_vec) {
+            let capture = CaptureReader::new(Cursor::new(&byte_vec[..]))?;
+            versions.push(definitions.recorded_or_latest(capture.header().definition_hash, path));
+        } else {
+            versions.push(definitions.latest());
+        }
+    }
+
+    let mut items = HashMap::new();
+    for version in versions.iter() {
+        if let Entry::Vacant(entry) = items.entry(version.hash) {
+            entry.insert(merge_items(&args, definitions.loc_layout(version)?, &naming)?);
+        }
+    }
+
+    // the time tag is the packet time if given, then the raw time item, then the receive time
+    let untimed = Cell::new(0);
+    let merge_packet = |items: &MergeItems, bytes: &[u8], receive_time: Option<f64>| -> Option<MergePacket> {
+        let time = match (&items.packet_clock, &items.time_item) {
+            (Some(packet_clock), _) => packet_clock.utc(bytes),
+            (None, Some(time_item)) if fits(time_item, bytes) =>
+                Some(decode_loc_item(time_item, &mut Cursor::new(bytes)).val.as_f64()),
+            (None, Some(_)) => None,
+            (None, None) => receive_time,
+        };
+        let time = match time {
+            Some(time) => time,
+            None => {
+                untimed.set(untimed.get() + 1);
+                return None;
+            },
+        };
+
+        let seq = items.seq_item.as_ref().filter(|item| fits(&item.loc_item, bytes))
+                                         .map(|item| item.decode(&mut Cursor::new(bytes)));
+
+        Some(MergePacket { time, seq, input: 0, bytes: bytes.to_vec() })
+    };
+
+    let mut inputs: Vec<Box<dyn Iterator<Item=MergePacket>>> = Vec::new();
+    for ((path, byte_vec), version) in args.infiles.iter().zip(files.iter()).zip(versions.iter()) {
+        let items = &items[&version.hash];
+        let merge_packet = &merge_packet;
+        if is_capture(byte_vec) {
+            let capture = CaptureReader::new(Cursor::new(&byte_vec[..]))?;
+            inputs.push(Box::new(capture.filter_map(move |record| merge_packet(items, &record.bytes, Some(record.time)))));
+        } else {
+            if items.packet_clock.is_none() && items.time_item.is_none() {
+                bail!("{} is a raw capture, so merging it needs a --time-item", path);
+            }
+            inputs.push(Box::new(PacketStream::new(&version.packet, byte_vec).filter_map(move |bytes| merge_packet(items, bytes, None))));
+        }
+    }
+
+    if items.values().any(|items| items.packet_clock.is_none() && items.time_item.is_none()) {
+        warn!("without a --time-item, captures are merged by receive time, so packets received by more then one recorder \
+               are rarely found to be duplicates");
+    }
+
+    // the merged capture records the version its inputs share
+    let merged_version = match versions.split_first() {
+        Some((first, rest)) if rest.iter().all(|version| version.hash == first.hash) => first,
+        _ => {
+            warn!("the inputs were recorded with different versions of the definitions, so the merged capture records the latest");
+            definitions.latest()
+        },
+    };
+
+    let mut merge = Merge::new(inputs);
+    let mut writer = BufWriter::new(File::create(&args.outfile)?);
+
+    if args.raw {
+        for merged in merge.by_ref() {
+            writer.write_all(&merged.bytes)?;
+        }
+        writer.flush()?;
+    } else {
+        let header = CaptureHeader { definition_hash: merged_version.hash,
+                                     created: unix_time_now(),
+                                     source: args.infiles.join(",") };
+        let mut capture = CaptureWriter::new(writer, &header)?;
+        for merged in merge.by_ref() {
+            // the source id is the input the packet came from
+            capture.write_packet(merged.time, merged.input as u16, &merged.bytes)?;
+        }
+        capture.into_inner()?.flush()?;
+    }
+
+    if merge.duplicates() > 0 {
+        warn!("dropped {} duplicate packets", merge.duplicates());
+    }
+    if merge.out_of_order() > 0 {
+        warn!("{} packets were out of time order within their capture", merge.out_of_order());
+    }
+    if untimed.get() > 0 {
+        warn!("dropped {} packets without a time tag", untimed.get());
+    }
+
+    Ok(())
+}
+
+// Check each definition file, printing its problems or its size.
+fn check(args: CheckArgs) -> Result<()> {
+    let mut num_problems = 0;
+
+    for path in args.definitions.iter() {
+        let text = std::fs::read_to_string(path)?;
+
+        // a file holds a packet definition, versions of one, or a layout
+        let checks: Vec<(String, Vec<DefinitionProblem>, Option<PacketSize>)> =
+            match ron::de::from_str::<LayoutPacketDef>(&text) {
+                Ok(packet) => vec![(path.clone(), validate_packet(&packet), Some(packet.size()))],
+                Err(err) => match ron::de::from_str::<Vec<VersionDef<Item>>>(&text) {
+                    Ok(versions) => versions.iter()
+                                            .enumerate()
+                                            .map(|(index, version)| (format!("{} version {}", path, index),
+                                                                     validate_packet(&version.packet),
+                                                                     Some(version.packet.size())))
+                                            .collect(),
+                    Err(_) => match ron::de::from_str::<Layout>(&text) {
+                        Ok(layout) => vec![(path.clone(), validate_layout(&layout), None)],
+                        Err(_) => bail!("{}: {}", path, err),
+                    },
+                },
+            };
+
+        for (label, problems, size) in checks {
+            for problem in problems.iter() {
+                println!("{}: {}", label, problem);
+            }
+            num_problems += problems.len();
+
+            if problems.is_empty() {
+                match size {
+                    Some(size) => match (size.fixed(), size.max) {
+                        (Some(fixed), _) => println!("{}: ok, {} bytes", label, fixed),
+                        (None, Some(max)) => println!("{}: ok, {} to {} bytes", label, size.min, max),
+                        (None, None) => println!("{}: ok, at least {} bytes", label, size.min),
+                    },
+                    None => println!("{}: ok", label),
+                }
+            }
+        }
+    }
+
+    if num_problems > 0 {
+        bail!("found {} problems", num_problems);
+    }
+
+    Ok(())
+}
+
+main!(|args: Cli, log_level : verbosity| {
+    match args.command {
+        Command::Decode(decode_args)   => decode(decode_args)?,
+        Command::Index(index_args)     => index(index_args)?,
+        Command::Extract(extract_args) => extract(extract_args)?,
+        Command::Replay(replay_args)   => replay(replay_args)?,
+        Command::Merge(merge_args)     => merge(merge_args)?,
+        Command::Check(check_args)     => check(check_args)?,
+    }
+});
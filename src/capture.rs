@@ -0,0 +1,238 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::io;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use self::byteorder::{LittleEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+
+use input::*;
+
+
+// A capture keeps raw packets along with when they were received and which
+// source they came from, so they can be decoded again later with their timing.
+// The hash of the definitions in use when recording is kept in the header.
+//
+// The file is a header followed by a record for each packet, all little endian:
+//   magic "GWCP", version u16, definition hash u64, creation time f64,
+//   source description length u32, source description (utf-8)
+//   receive time f64, source id u16, length u32, bytes
+//
+// Times are in seconds since the unix epoch.
+
+pub const CAPTURE_MAGIC: &[u8; 4] = b"GWCP";
+const CAPTURE_VERSION: u16 = 1;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CaptureHeader {
+    pub definition_hash: u64,
+    pub created: f64,
+    pub source: String,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CaptureRecord {
+    pub time: f64,
+    pub source_id: u16,
+    pub bytes: Vec<u8>,
+}
+
+impl AsRef<[u8]> for CaptureRecord {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl ReceiveTime for CaptureRecord {
+    fn receive_time(&self) -> Option<f64> {
+        Some(self.time)
+    }
+}
+
+// whether the bytes, such as the start of a file, are a capture
+pub fn is_capture(bytes: &[u8]) -> bool {
+    bytes.starts_with(CAPTURE_MAGIC)
+}
+
+pub struct CaptureWriter<W : Write> {
+    writer: W,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl<W : Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, header: &CaptureHeader) -> io::Result<CaptureWriter<W>> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_u16::<LittleEndian>(CAPTURE_VERSION)?;
+        writer.write_u64::<LittleEndian>(header.definition_hash)?;
+        writer.write_f64::<LittleEndian>(header.created)?;
+        writer.write_u32::<LittleEndian>(header.source.len() as u32)?;
+        writer.write_all(header.source.as_bytes())?;
+        writer.flush()?;
+
+        Ok(CaptureWriter { writer, flush_interval: Duration::from_secs(1), last_flush: Instant::now() })
+    }
+
+    // Flush at least this often, so a live capture is kept up to date on disk
+    // without flushing every packet.
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> CaptureWriter<W> {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn write_packet(&mut self, time: f64, source_id: u16, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_f64::<LittleEndian>(time)?;
+        self.writer.write_u16::<LittleEndian>(source_id)?;
+        self.writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+        self.writer.write_all(bytes)?;
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        self.write_packet(record.time, record.source_id, &record.bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub struct CaptureReader<R : Read> {
+    reader: R,
+    header: CaptureHeader,
+    error: Option<io::Error>,
+}
+
+// the largest record we expect to see, to catch corrupt lengths
+const MAX_RECORD: u32 = 16 * 1024 * 1024;
+
+impl<R : Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gasworks capture file"));
+        }
+
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version != CAPTURE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("unsupported capture version {}", version)));
+        }
+
+        let definition_hash = reader.read_u64::<LittleEndian>()?;
+        let created = reader.read_f64::<LittleEndian>()?;
+
+        let source_len = reader.read_u32::<LittleEndian>()?;
+        let mut source = Vec::new();
+        reader.by_ref().take(source_len as u64).read_to_end(&mut source)?;
+        let source = match String::from_utf8(source) {
+            Ok(source) => source,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "capture source is not utf-8")),
+        };
+
+        Ok(CaptureReader { reader, header: CaptureHeader { definition_hash, created, source }, error: None })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    // the error that ended the records, if they did not end cleanly. a capture
+    // cut off part way through its last record ends with an UnexpectedEof error.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        // the end of the capture is only clean between records
+        let mut time = [0; 8];
+        loop {
+            match self.reader.read(&mut time[..1]) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        self.reader.read_exact(&mut time[1..])?;
+        let time = LittleEndian::read_f64(&time);
+
+        let source_id = self.reader.read_u16::<LittleEndian>()?;
+        let length = self.reader.read_u32::<LittleEndian>()?;
+        if length > MAX_RECORD {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad capture record length {}", length)));
+        }
+
+        let mut bytes = vec![0; length as usize];
+        self.reader.read_exact(&mut bytes)?;
+
+        Ok(Some(CaptureRecord { time, source_id, bytes }))
+    }
+}
+
+impl<R : Read> Iterator for CaptureReader<R> {
+    type Item = CaptureRecord;
+
+    fn next(&mut self) -> Option<CaptureRecord> {
+        match self.read_record() {
+            Ok(record) => record,
+
+            Err(err) => {
+                self.error = Some(err);
+                None
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_capture {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_capture_records() {
+        let header = CaptureHeader { definition_hash: 0xABCD, created: 1.5e9, source: "udp://0.0.0.0:5000".to_string() };
+
+        let mut writer = CaptureWriter::new(Vec::new(), &header).unwrap();
+        writer.write_packet(1.5e9 + 0.25, 0, &[1, 2, 3]).unwrap();
+        writer.write_record(&CaptureRecord { time: 1.5e9 + 0.5, source_id: 2, bytes: vec![4, 5] }).unwrap();
+        let mut file = writer.into_inner().unwrap();
+        assert!(is_capture(&file));
+
+        let mut reader = CaptureReader::new(Cursor::new(file.clone())).unwrap();
+        assert_eq!(reader.header(), &header);
+        let records: Vec<CaptureRecord> = reader.by_ref().collect();
+        assert!(reader.take_error().is_none());
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].bytes, vec![1, 2, 3]);
+        assert_eq!(records[1].receive_time(), Some(1.5e9 + 0.5));
+        assert_eq!(records[1].source_id, 2);
+
+        // a record cut off while being written ends the capture with an error
+        file.pop();
+        let mut reader = CaptureReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.by_ref().count(), 1);
+        assert_eq!(reader.take_error().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
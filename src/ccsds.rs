@@ -0,0 +1,425 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::io::Cursor;
+
+use self::byteorder::{BigEndian, ByteOrder};
+
+use types::*;
+use prim::*;
+use layout::*;
+use value::*;
+use packet::*;
+use decode::*;
+use framing::*;
+
+
+// Built in support for CCSDS space packets (CCSDS 133.0-B), so packet
+// definitions only need to describe what follows the primary header.
+//
+// The primary header is 6 bytes, big endian:
+//   version (3 bits), packet type (1), secondary header flag (1), APID (11),
+//   sequence flags (2), sequence count (14), packet data length (16)
+// The length field is the number of bytes after the primary header, minus one.
+
+pub const PRIMARY_HEADER_BYTES: usize = 6;
+
+// the APID reserved for idle packets, which carry no data
+pub const IDLE_APID: u16 = 0x7FF;
+
+// The primary header as a bit field layout, decoding into a "ccsds_pri" section.
+pub fn primary_header_layout() -> Layout {
+    let entries = vec![("version".to_string(),      3,  IntPrim::u8_be()),
+                       ("packet_type".to_string(),  1,  IntPrim::u8_be()),
+                       ("sec_hdr_flag".to_string(), 1,  IntPrim::u8_be()),
+                       ("apid".to_string(),         11, IntPrim::u16_be()),
+                       ("seq_flags".to_string(),    2,  IntPrim::u8_be()),
+                       ("seq_count".to_string(),    14, IntPrim::u16_be()),
+                       ("length".to_string(),       16, IntPrim::u16_be())];
+
+    Layout::Seq("ccsds_pri".to_string(),
+                vec![Layout::Bits(BitPrim { entries, num_bytes: PRIMARY_HEADER_BYTES as u64 })])
+}
+
+// The length field of the primary header, for framing a stream of space packets.
+pub fn length_field() -> LengthField {
+    LengthField::new(4, IntPrim::u16_be(), PRIMARY_HEADER_BYTES as i64 + 1)
+}
+
+// Frame a stream of back to back space packets by their length field.
+pub fn packet_framer() -> SyncFramer {
+    SyncFramer::length_prefixed(length_field())
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum PacketType {
+    Telemetry,
+    Telecommand,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SeqFlags {
+    Continuation,
+    First,
+    Last,
+    Unsegmented,
+}
+
+impl SeqFlags {
+    pub fn from_bits(bits: u8) -> SeqFlags {
+        match bits & 0x3 {
+            0 => SeqFlags::Continuation,
+            1 => SeqFlags::First,
+            2 => SeqFlags::Last,
+            _ => SeqFlags::Unsegmented,
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            SeqFlags::Continuation => 0,
+            SeqFlags::First        => 1,
+            SeqFlags::Last         => 2,
+            SeqFlags::Unsegmented  => 3,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct PrimaryHeader {
+    pub version: u8,
+    pub packet_type: PacketType,
+    pub sec_header: bool,
+    pub apid: u16,
+    pub seq_flags: SeqFlags,
+    pub seq_count: u16,
+    // the packet data length field, one less then the bytes after the header
+    pub length: u16,
+}
+
+impl PrimaryHeader {
+    // Parse the header at the start of the bytes, if there are enough of them.
+    pub fn parse(bytes: &[u8]) -> Option<PrimaryHeader> {
+        if bytes.len() < PRIMARY_HEADER_BYTES {
+            return None;
+        }
+
+        let id = BigEndian::read_u16(&bytes[0..2]);
+        let seq = BigEndian::read_u16(&bytes[2..4]);
+
+        Some(PrimaryHeader {
+            version: (id >> 13) as u8,
+            packet_type: if id & 0x1000 != 0 { PacketType::Telecommand } else { PacketType::Telemetry },
+            sec_header: id & 0x0800 != 0,
+            apid: id & 0x07FF,
+            seq_flags: SeqFlags::from_bits((seq >> 14) as u8),
+            seq_count: seq & 0x3FFF,
+            length: BigEndian::read_u16(&bytes[4..6]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; PRIMARY_HEADER_BYTES] {
+        let mut id = ((self.version as u16 & 0x7) << 13) | (self.apid & 0x07FF);
+        if self.packet_type == PacketType::Telecommand {
+            id |= 0x1000;
+        }
+        if self.sec_header {
+            id |= 0x0800;
+        }
+        let seq = ((self.seq_flags.bits() as u16) << 14) | (self.seq_count & 0x3FFF);
+
+        let mut bytes = [0; PRIMARY_HEADER_BYTES];
+        BigEndian::write_u16(&mut bytes[0..2], id);
+        BigEndian::write_u16(&mut bytes[2..4], seq);
+        BigEndian::write_u16(&mut bytes[4..6], self.length);
+        bytes
+    }
+
+    // the length of the whole packet, header included
+    pub fn packet_length(&self) -> usize {
+        PRIMARY_HEADER_BYTES + self.length as usize + 1
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.apid == IDLE_APID
+    }
+}
+
+// Choose a packet definition by APID. Definitions describe the bytes after the
+// primary header, which is decoded separately into its own section.
+#[derive(PartialEq, Debug)]
+pub struct ApidDispatch {
+    header_layout: Layout,
+    packets: HashMap<u16, LayoutPacketDef>,
+    default: Option<LayoutPacketDef>,
+}
+
+impl Default for ApidDispatch {
+    fn default() -> ApidDispatch {
+        ApidDispatch { header_layout: primary_header_layout(), packets: HashMap::new(), default: None }
+    }
+}
+
+impl ApidDispatch {
+    pub fn new() -> ApidDispatch {
+        ApidDispatch::default()
+    }
+
+    pub fn add(&mut self, apid: u16, packet: LayoutPacketDef) {
+        self.packets.insert(apid, packet);
+    }
+
+    // the definition for packets with an APID that was not added
+    pub fn with_default(mut self, packet: LayoutPacketDef) -> ApidDispatch {
+        self.default = Some(packet);
+        self
+    }
+
+    pub fn packet(&self, apid: u16) -> Option<&LayoutPacketDef> {
+        self.packets.get(&apid).or(self.default.as_ref())
+    }
+
+    // Decode a space packet into its "ccsds_pri" header section along with its
+    // definition's values. Packets that are short of the length in their header,
+    // have no definition, or are too short for their definition, are not decoded.
+    pub fn decode(&self, bytes: &[u8]) -> Result<ValueMap, String> {
+        let header = match PrimaryHeader::parse(bytes) {
+            Some(header) => header,
+            None => return Err(format!("{} bytes is too short for a space packet", bytes.len())),
+        };
+        if bytes.len() < header.packet_length() {
+            return Err(format!("space packet on APID {} has {} of its {} bytes",
+                               header.apid, bytes.len(), header.packet_length()));
+        }
+        let packet = match self.packet(header.apid) {
+            Some(packet) => packet,
+            None => return Err(format!("no definition for APID {}", header.apid)),
+        };
+        let min_length = PRIMARY_HEADER_BYTES + packet.size().min as usize;
+        if header.packet_length() < min_length {
+            return Err(format!("space packet on APID {} has {} bytes, but its definition needs at least {}",
+                               header.apid, header.packet_length(), min_length));
+        }
+
+        let bytes = &bytes[..header.packet_length()];
+        let mut cursor = Cursor::new(bytes);
+        let mut value_map = decode_to_map(&self.header_layout, &mut cursor);
+
+        let data_map = decode_layoutpacket(packet, &mut cursor)?;
+        value_map.value_map.extend(data_map.value_map);
+
+        Ok(value_map)
+    }
+}
+
+// Time codes commonly found at the start of the secondary header (CCSDS 301.0-B).
+// Times are decoded to seconds since the time code's epoch, which is
+// 1958-01-01 TAI unless the mission defines its own. Use TimeCode::cuc and
+// TimeCode::cds to check the field sizes, as codes with other sizes do not decode.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TimeCode {
+    // unsegmented code: 1 to 4 bytes of seconds, then 0 to 3 bytes of binary fractions of a second
+    Cuc { coarse: u8, fine: u8 },
+    // day segmented code: 2 or 3 bytes of days, 4 bytes of milliseconds of the day,
+    // then 0, 2 (microseconds), or 4 (picoseconds) bytes of submilliseconds
+    Cds { day_bytes: u8, submilli_bytes: u8 },
+}
+
+impl NumBytes for TimeCode {
+    fn num_bytes(&self) -> u64 {
+        match self {
+            TimeCode::Cuc { coarse, fine } => *coarse as u64 + *fine as u64,
+            TimeCode::Cds { day_bytes, submilli_bytes } => *day_bytes as u64 + 4 + *submilli_bytes as u64,
+        }
+    }
+}
+
+impl TimeCode {
+    pub fn cuc(coarse: u8, fine: u8) -> Result<TimeCode, String> {
+        let code = TimeCode::Cuc { coarse, fine };
+        if !code.is_valid() {
+            return Err(format!("a CUC time code has 1 to 4 coarse and 0 to 3 fine bytes, not {} and {}", coarse, fine));
+        }
+
+        Ok(code)
+    }
+
+    pub fn cds(day_bytes: u8, submilli_bytes: u8) -> Result<TimeCode, String> {
+        let code = TimeCode::Cds { day_bytes, submilli_bytes };
+        if !code.is_valid() {
+            return Err(format!("a CDS time code has 2 or 3 day and 0, 2, or 4 submillisecond bytes, not {} and {}",
+                               day_bytes, submilli_bytes));
+        }
+
+        Ok(code)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            TimeCode::Cuc { coarse, fine } => (1..=4).contains(&coarse) && fine <= 3,
+            TimeCode::Cds { day_bytes, submilli_bytes } => (day_bytes == 2 || day_bytes == 3) &&
+                                                           [0, 2, 4].contains(&submilli_bytes),
+        }
+    }
+
+    // Decode the time code at the start of the bytes, if there are enough of them.
+    pub fn decode(&self, bytes: &[u8]) -> Option<f64> {
+        if !self.is_valid() || (bytes.len() as u64) < self.num_bytes() {
+            return None;
+        }
+
+        match *self {
+            TimeCode::Cuc { coarse, fine } => {
+                let coarse = coarse as usize;
+                let seconds = read_uint(&bytes[..coarse]);
+                let fraction = read_uint(&bytes[coarse..coarse + fine as usize]);
+
+                Some(seconds as f64 + fraction as f64 / 2f64.powi(8 * fine as i32))
+            },
+
+            TimeCode::Cds { day_bytes, submilli_bytes } => {
+                let day_bytes = day_bytes as usize;
+                let days = read_uint(&bytes[..day_bytes]);
+                let millis = BigEndian::read_u32(&bytes[day_bytes..day_bytes + 4]);
+                let submillis = read_uint(&bytes[day_bytes + 4..day_bytes + 4 + submilli_bytes as usize]);
+
+                let submillis = match submilli_bytes {
+                    2 => submillis as f64 * 1e-6,
+                    4 => submillis as f64 * 1e-12,
+                    _ => 0.0,
+                };
+
+                Some(days as f64 * 86400.0 + millis as f64 * 1e-3 + submillis)
+            },
+        }
+    }
+
+    // Decode the time at the start of a space packet's secondary header, if it has one.
+    pub fn packet_time(&self, packet: &[u8]) -> Option<f64> {
+        if !PrimaryHeader::parse(packet)?.sec_header {
+            return None;
+        }
+
+        self.decode(&packet[PRIMARY_HEADER_BYTES..])
+    }
+}
+
+// a big endian unsigned integer of up to 8 bytes. the fields of a valid time code
+// are at most 4 bytes, so no bits are shifted out.
+fn read_uint(bytes: &[u8]) -> u64 {
+    debug_assert!(bytes.len() <= 8);
+    bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+
+#[cfg(test)]
+mod test_ccsds {
+    use super::*;
+    use *;
+
+    fn space_packet(apid: u16, seq_count: u16, data: &[u8]) -> Vec<u8> {
+        let header = PrimaryHeader { version: 0,
+                                     packet_type: PacketType::Telemetry,
+                                     sec_header: true,
+                                     apid,
+                                     seq_flags: SeqFlags::Unsegmented,
+                                     seq_count,
+                                     length: data.len() as u16 - 1 };
+
+        let mut packet = header.to_bytes().to_vec();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn test_primary_header() {
+        let packet = space_packet(0x123, 0x2345, &[1, 2, 3]);
+        assert_eq!(&packet[..6], &[0x09, 0x23, 0xE3, 0x45, 0x00, 0x02]);
+
+        let header = PrimaryHeader::parse(&packet).unwrap();
+        assert_eq!(header.apid, 0x123);
+        assert_eq!(header.seq_flags, SeqFlags::Unsegmented);
+        assert_eq!(header.seq_count, 0x2345);
+        assert_eq!(header.packet_length(), 9);
+        assert!(PrimaryHeader::parse(&packet[..5]).is_none());
+
+        // the layout decodes the same fields, even when the header is not at the start
+        let mut bytes = vec![0xFF];
+        bytes.extend_from_slice(&packet);
+        let mut cursor = Cursor::new(bytes.as_slice());
+        cursor.set_position(1);
+        let value_map = decode_to_map(&primary_header_layout(), &mut cursor);
+        assert_eq!(cursor.position(), 7);
+        assert_eq!(value_map.lookup(&"apid".to_string()), Some(Value::U16(0x123)));
+        assert_eq!(value_map.lookup(&"sec_hdr_flag".to_string()), Some(Value::U8(1)));
+        assert_eq!(value_map.lookup(&"seq_flags".to_string()), Some(Value::U8(3)));
+        assert_eq!(value_map.lookup(&"seq_count".to_string()), Some(Value::U16(0x2345)));
+        assert_eq!(value_map.lookup(&"length".to_string()), Some(Value::U16(2)));
+
+        // frame a stream of packets by their length
+        let mut framer = packet_framer();
+        framer.push(&packet);
+        framer.push(&space_packet(0x7FF, 0, &[0; 10]));
+        assert_eq!(framer.next_packet(), Some(packet.clone()));
+        assert!(PrimaryHeader::parse(&framer.next_packet().unwrap()).unwrap().is_idle());
+        assert_eq!(framer.next_packet(), None);
+    }
+
+    #[test]
+    fn test_apid_dispatch() {
+        let mut dispatch = ApidDispatch::new();
+        dispatch.add(0x10, seq("temps".to_string(), vec![u16_be("temp0"), u16_be("temp1")]));
+        dispatch.add(0x20, seq("volts".to_string(), vec![u8_be("volt0")]));
+
+        let value_map = dispatch.decode(&space_packet(0x10, 1, &[0x00, 0x05, 0x01, 0x00])).unwrap();
+        assert_eq!(value_map.lookup(&"apid".to_string()), Some(Value::U16(0x10)));
+        assert_eq!(value_map.lookup(&"temp1".to_string()), Some(Value::U16(0x100)));
+
+        let value_map = dispatch.decode(&space_packet(0x20, 2, &[0x07])).unwrap();
+        assert_eq!(value_map.lookup(&"volt0".to_string()), Some(Value::U8(7)));
+        assert_eq!(value_map.lookup(&"temp0".to_string()), None);
+
+        // unknown APIDs and short packets are not decoded
+        assert!(dispatch.decode(&space_packet(0x30, 3, &[0x07])).is_err());
+        assert!(dispatch.decode(&space_packet(0x10, 4, &[0x00, 0x05, 0x01, 0x00])[..8]).is_err());
+        // a packet shorter then its definition, even if there are bytes after it
+        let mut short = space_packet(0x10, 5, &[0x00, 0x05]);
+        short.extend_from_slice(&[0x01, 0x00]);
+        assert!(dispatch.decode(&short).is_err());
+
+        let dispatch = dispatch.with_default(seq("raw".to_string(), vec![u8_be("byte0")]));
+        let value_map = dispatch.decode(&space_packet(0x30, 3, &[0x07])).unwrap();
+        assert_eq!(value_map.lookup(&"byte0".to_string()), Some(Value::U8(7)));
+    }
+
+    #[test]
+    fn test_time_codes() {
+        let cuc = TimeCode::Cuc { coarse: 4, fine: 2 };
+        assert_eq!(cuc.num_bytes(), 6);
+        assert_eq!(cuc.decode(&[0x00, 0x00, 0x01, 0x00, 0x80, 0x00]), Some(256.5));
+        assert_eq!(cuc.decode(&[0x00, 0x00, 0x01]), None);
+
+        let cds = TimeCode::Cds { day_bytes: 2, submilli_bytes: 2 };
+        assert_eq!(cds.num_bytes(), 8);
+        let time = cds.decode(&[0x00, 0x02, 0x00, 0x00, 0x03, 0xE9, 0x01, 0xF4]).unwrap();
+        assert!((time - (2.0 * 86400.0 + 1.001 + 0.0005)).abs() < 1e-9);
+
+        let packet = space_packet(0x10, 0, &[0x00, 0x00, 0x00, 0x10, 0x40, 0x00, 0xAA]);
+        assert_eq!(cuc.packet_time(&packet), Some(16.25));
+
+        assert_eq!(TimeCode::cuc(4, 2), Ok(cuc));
+        assert!(TimeCode::cuc(0, 2).is_err());
+        assert!(TimeCode::cds(2, 3).is_err());
+
+        // codes built with sizes out of range do not decode, however long the bytes
+        let too_wide = TimeCode::Cuc { coarse: 200, fine: 100 };
+        assert_eq!(too_wide.num_bytes(), 300);
+        assert_eq!(too_wide.decode(&[0; 300]), None);
+    }
+}
@@ -0,0 +1,287 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::io::Cursor;
+use std::mem;
+
+use types::*;
+use value::*;
+use loclayout::*;
+use packet::*;
+use decode::*;
+use index::*;
+
+
+// Subcommutation across packets, where a frame counter in each packet, counting
+// 0 to N-1, says which slice of parameters the packet carries. Each packet is a
+// minor frame, and a full count of minor frames is a major frame, holding every
+// parameter of the cycle. Parameters carried in more then one minor frame are
+// supercommutated, sampled more often then the cycle, and each sample is kept.
+//
+// A cycle is defined in RON, such as:
+//   CycleDef(counter: "frame", counter_mask: Some(0x0F), offset: 4,
+//            frames: [[Leaf(...), Leaf(...)], [Leaf(...)], ...])
+// where frames lists the parameters each minor frame carries, starting offset
+// bytes into the packet.
+
+#[derive(PartialEq, Debug, Deserialize, Serialize)]
+pub struct CycleDef {
+    // the item in the packet definition counting minor frames
+    pub counter: Name,
+    #[serde(default)]
+    pub counter_mask: Option<u64>,
+    // where the subcommutated parameters start in each packet, in bytes
+    pub offset: u64,
+    // the parameters carried by each minor frame, by frame count
+    pub frames: Vec<Vec<LayoutPacketDef>>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum CycleProblem {
+    // a major frame that was never completed, with the minor frames missing from it.
+    // a cycle that was already under way when decoding started is reported this way too.
+    Incomplete { missing: Vec<u64> },
+    // a frame count outside the cycle
+    BadCounter { counter: u64 },
+    // a packet too short to hold the parameters of its minor frame
+    Short { frame: u64 },
+}
+
+// A complete major frame, with a value for each of the cycle's columns.
+#[derive(PartialEq, Debug, Clone)]
+pub struct MajorFrame {
+    // the receive time of the first minor frame
+    pub time: Option<f64>,
+    pub values: Vec<Value>,
+}
+
+pub struct CycleDecoder {
+    counter: IndexItem,
+    // the parameters of each minor frame, located within the packet
+    frames: Vec<LocLayout>,
+    // the bytes a packet needs to hold each minor frame
+    frame_bytes: Vec<u64>,
+    // the column of each parameter of each minor frame
+    columns: Vec<Vec<usize>>,
+    paths: Vec<LocPath>,
+    values: Vec<Option<Value>>,
+    received: Vec<bool>,
+    last: Option<u64>,
+    time: Option<f64>,
+    problems: Vec<CycleProblem>,
+}
+
+impl CycleDecoder {
+    // Decode the cycle with its frame counter, found in the packet definition.
+    // Minor frames must have a fixed layout, without subcoms or variable arrays.
+    pub fn new(counter: IndexItem, def: &CycleDef) -> Result<CycleDecoder, String> {
+        if def.frames.is_empty() {
+            return Err("the cycle has no minor frames".to_string());
+        }
+
+        let mut frames = Vec::new();
+        let mut frame_bytes = Vec::new();
+
+        for (frame, packets) in def.frames.iter().enumerate() {
+            let mut offset = def.offset;
+            let mut loc_layout = LocLayout::new();
+
+            for packet in packets {
+                let located = match packet.locate() {
+                    Some(located) => located,
+                    None => return Err(format!("minor frame {} does not have a fixed layout", frame)),
+                };
+
+                for mut loc_item in located.loc_items {
+                    loc_item.loc += offset;
+                    loc_layout.loc_items.push(loc_item);
+                }
+                offset += packet.size().min;
+            }
+
+            frames.push(loc_layout);
+            frame_bytes.push(offset);
+        }
+
+        // a parameter in several minor frames gets a column for each sample, such as temp[0] and temp[1]
+        let mut counts: HashMap<&LocPath, usize> = HashMap::new();
+        for loc_item in frames.iter().flat_map(|loc_layout| loc_layout.loc_items.iter()) {
+            *counts.entry(&loc_item.name).or_insert(0) += 1;
+        }
+
+        let mut samples: HashMap<&LocPath, usize> = HashMap::new();
+        let mut paths = Vec::new();
+        let mut columns = Vec::new();
+        for loc_layout in frames.iter() {
+            let mut frame_columns = Vec::new();
+
+            for loc_item in loc_layout.loc_items.iter() {
+                let mut path = loc_item.name.clone();
+                if counts[&loc_item.name] > 1 {
+                    let sample = samples.entry(&loc_item.name).or_insert(0);
+                    if let Some(last) = path.last_mut() {
+                        last.push_str(&format!("[{}]", sample));
+                    }
+                    *sample += 1;
+                }
+
+                frame_columns.push(paths.len());
+                paths.push(path);
+            }
+
+            columns.push(frame_columns);
+        }
+
+        let mut mask = counter.mask;
+        if let Some(counter_mask) = def.counter_mask {
+            mask &= counter_mask;
+        }
+
+        let num_frames = frames.len();
+        let num_columns = paths.len();
+        Ok(CycleDecoder { counter: IndexItem::masked(counter.loc_item, mask)?,
+                          frames,
+                          frame_bytes,
+                          columns,
+                          paths,
+                          values: vec![None; num_columns],
+                          received: vec![false; num_frames],
+                          last: None,
+                          time: None,
+                          problems: Vec::new() })
+    }
+
+    // the path naming each column of a major frame
+    pub fn paths(&self) -> &[LocPath] {
+        &self.paths
+    }
+
+    pub fn take_problems(&mut self) -> Vec<CycleProblem> {
+        self.problems.drain(..).collect()
+    }
+
+    // Take in the next minor frame, returning the major frame it completes, if any.
+    // A frame count at or before the last one received starts a new cycle.
+    // Packets too short to hold the frame counter are ignored.
+    pub fn push(&mut self, packet: &[u8], time: Option<f64>) -> Option<MajorFrame> {
+        let loc_item = &self.counter.loc_item;
+        if (packet.len() as u64) < loc_item.loc + loc_item.num_bytes() {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(packet);
+        let frame = self.counter.decode(&mut cursor);
+
+        if frame >= self.frames.len() as u64 {
+            self.problems.push(CycleProblem::BadCounter { counter: frame });
+            return None;
+        }
+
+        if self.last.is_some_and(|last| frame <= last) {
+            self.incomplete();
+        }
+        self.last = Some(frame);
+
+        let index = frame as usize;
+        if (packet.len() as u64) < self.frame_bytes[index] {
+            self.problems.push(CycleProblem::Short { frame });
+            return None;
+        }
+
+        if !self.received.contains(&true) {
+            self.time = time;
+        }
+
+        for (loc_item, column) in self.frames[index].loc_items.iter().zip(self.columns[index].iter()) {
+            self.values[*column] = Some(decode_loc_item(loc_item, &mut cursor).val);
+        }
+        self.received[index] = true;
+
+        if self.received.iter().all(|received| *received) {
+            let values = mem::replace(&mut self.values, vec![None; self.paths.len()]);
+            let major_frame = MajorFrame { time: self.time,
+                                           values: values.into_iter().map(|value| value.unwrap()).collect() };
+            self.reset();
+            return Some(major_frame);
+        }
+
+        None
+    }
+
+    // Report the cycle under way as incomplete, such as at the end of the packets.
+    pub fn finish(&mut self) {
+        self.incomplete();
+    }
+
+    fn incomplete(&mut self) {
+        if self.received.contains(&true) {
+            let missing = (0..self.received.len() as u64).filter(|frame| !self.received[*frame as usize])
+                                                          .collect();
+            self.problems.push(CycleProblem::Incomplete { missing });
+        }
+
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        for received in self.received.iter_mut() {
+            *received = false;
+        }
+        for value in self.values.iter_mut() {
+            *value = None;
+        }
+        self.last = None;
+        self.time = None;
+    }
+}
+
+
+#[cfg(test)]
+mod test_commutation {
+    use super::*;
+    use *;
+
+    #[test]
+    fn test_major_frames() {
+        // a counter in the low bits of the first byte, then one byte of parameters.
+        // temp is supercommutated, carried in frames 0 and 2.
+        let def = CycleDef { counter: "frame".to_string(),
+                             counter_mask: Some(0x0F),
+                             offset: 1,
+                             frames: vec![vec![u8_be("temp")],
+                                          vec![u8_be("volts")],
+                                          vec![u8_be("temp")]] };
+        let counter = IndexItem::new(LocItem::new(vec!["frame".to_string()], Prim::Int(IntPrim::u8_be()), 0)).unwrap();
+
+        let mut decoder = CycleDecoder::new(counter, &def).unwrap();
+        let names: Vec<String> = decoder.paths().iter().map(|path| path.join(".")).collect();
+        assert_eq!(names, vec!["temp[0]", "volts", "temp[1]"]);
+
+        // starts mid cycle, then a full cycle, then one with frame 1 lost
+        assert_eq!(decoder.push(&[0xA2, 9], Some(0.0)), None);
+        assert_eq!(decoder.push(&[0xA0, 20], Some(1.0)), None);
+        assert_eq!(decoder.push(&[0xA1, 5], Some(2.0)), None);
+        assert_eq!(decoder.push(&[0xA2, 21], Some(3.0)),
+                   Some(MajorFrame { time: Some(1.0), values: vec![Value::U8(20), Value::U8(5), Value::U8(21)] }));
+        assert_eq!(decoder.push(&[0xA0, 22], Some(4.0)), None);
+        assert_eq!(decoder.push(&[0xA2, 23], Some(6.0)), None);
+        assert_eq!(decoder.push(&[0x07, 0], None), None);
+        assert_eq!(decoder.push(&[0x01], None), None);
+        assert_eq!(decoder.push(&[0xA0, 24], None), None);
+        decoder.finish();
+
+        assert_eq!(decoder.take_problems(),
+                   vec![CycleProblem::Incomplete { missing: vec![0, 1] },
+                        CycleProblem::BadCounter { counter: 7 },
+                        CycleProblem::Incomplete { missing: vec![1] },
+                        CycleProblem::Short { frame: 1 },
+                        CycleProblem::Incomplete { missing: vec![1, 2] }]);
+
+        let empty = CycleDef { counter: "frame".to_string(), counter_mask: None, offset: 0, frames: vec![] };
+        assert!(CycleDecoder::new(decoder.counter.clone(), &empty).is_err());
+    }
+}
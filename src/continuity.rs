@@ -0,0 +1,225 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::collections::VecDeque;
+use std::io::Cursor;
+
+use types::*;
+use index::*;
+
+
+// Check the sequence counts of packet streams for lost, repeated, and
+// reordered packets. Each stream is keyed by an item such as the APID, and its
+// sequence count is an item masked to its counter bits, such as the 14 bit
+// CCSDS sequence count, which wraps around to zero after its largest value.
+//
+// A count ahead of the expected one by less than half the counter's range is a
+// gap, and a count behind it is a duplicate if it was seen recently and out of
+// order otherwise.
+// NOTE the mask must be a run of low bits, such as 0x3FFF, and is narrowed to
+// the width of the item, so an unmasked 16 bit count wraps at 0xFFFF.
+
+// the number of recent sequence counts kept per stream to recognize duplicates,
+// and of recent gaps kept to recognize late packets that were counted as missing
+const RECENT_COUNTS: usize = 64;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ContinuityEvent {
+    // packets were lost between the expected sequence count and the one found
+    Gap { stream: u64, expected: u64, found: u64, missing: u64 },
+    // a sequence count that was already seen
+    Duplicate { stream: u64, seq: u64 },
+    // a sequence count from before the latest one that was not already seen,
+    // such as a packet that was reported missing in an earlier gap
+    OutOfOrder { stream: u64, expected: u64, found: u64 },
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct StreamSummary {
+    pub packets: u64,
+    pub gaps: u64,
+    pub missing: u64,
+    pub duplicates: u64,
+    pub out_of_order: u64,
+    pub first_seq: u64,
+    pub last_seq: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Stream {
+    summary: StreamSummary,
+    recent: VecDeque<u64>,
+    // the counts still missing from recent gaps, as their first count and how many
+    gaps: VecDeque<(u64, u64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ContinuityChecker {
+    seq: IndexItem,
+    // the sequence count mask, within the width of its item
+    mask: u64,
+    stream: Option<IndexItem>,
+    streams: BTreeMap<u64, Stream>,
+    events: Vec<ContinuityEvent>,
+}
+
+impl ContinuityChecker {
+    pub fn new(seq: IndexItem) -> ContinuityChecker {
+        let bits = seq.loc_item.num_bytes() * 8;
+        let mask = if bits < 64 { seq.mask & ((1 << bits) - 1) } else { seq.mask };
+        ContinuityChecker { seq, mask, stream: None, streams: BTreeMap::new(), events: Vec::new() }
+    }
+
+    // Track a stream for each value of an item, such as the APID, rather then
+    // checking all packets as one stream.
+    pub fn with_stream(mut self, stream: IndexItem) -> ContinuityChecker {
+        self.stream = Some(stream);
+        self
+    }
+
+    // the events found since last taken
+    pub fn take_events(&mut self) -> Vec<ContinuityEvent> {
+        self.events.drain(..).collect()
+    }
+
+    // the summary of each stream seen so far, by its key
+    pub fn summary(&self) -> BTreeMap<u64, StreamSummary> {
+        self.streams.iter().map(|(key, stream)| (*key, stream.summary.clone())).collect()
+    }
+
+    // Check the next packet, returning the event it caused, if any.
+    // Packets too short to hold the stream and sequence count items are ignored.
+    pub fn check(&mut self, packet: &[u8]) -> Option<ContinuityEvent> {
+        let fits = |item: &IndexItem| packet.len() as u64 >= item.loc_item.loc + item.loc_item.num_bytes();
+        if !fits(&self.seq) || !self.stream.as_ref().is_none_or(fits) {
+            return None;
+        }
+
+        let mut bytes = Cursor::new(packet);
+        let key = self.stream.as_ref().map_or(0, |item| item.decode(&mut bytes));
+        let seq = self.seq.decode(&mut bytes) & self.mask;
+
+        let mask = self.mask;
+        let stream = self.streams.entry(key).or_insert_with(|| {
+            Stream { summary: StreamSummary { first_seq: seq, last_seq: seq.wrapping_sub(1) & mask, ..Default::default() },
+                     recent: VecDeque::new(),
+                     gaps: VecDeque::new() }
+        });
+
+        stream.summary.packets += 1;
+
+        let expected = stream.summary.last_seq.wrapping_add(1) & mask;
+        let ahead = seq.wrapping_sub(expected) & mask;
+
+        let event = if ahead == 0 {
+            None
+        } else if ahead <= mask / 2 {
+            stream.summary.gaps += 1;
+            stream.summary.missing += ahead;
+            if stream.gaps.len() == RECENT_COUNTS {
+                stream.gaps.pop_front();
+            }
+            stream.gaps.push_back((expected, ahead));
+            Some(ContinuityEvent::Gap { stream: key, expected, found: seq, missing: ahead })
+        } else if stream.recent.contains(&seq) {
+            stream.summary.duplicates += 1;
+            Some(ContinuityEvent::Duplicate { stream: key, seq })
+        } else {
+            stream.summary.out_of_order += 1;
+            // a late packet was counted as missing if it was skipped over in a gap
+            let gap = stream.gaps.iter().position(|&(first, missing)| seq.wrapping_sub(first) & mask < missing);
+            if let Some(index) = gap {
+                let (first, missing) = stream.gaps.remove(index).unwrap();
+                let before = seq.wrapping_sub(first) & mask;
+                let after = missing - before - 1;
+                if after > 0 {
+                    stream.gaps.insert(index, (seq.wrapping_add(1) & mask, after));
+                }
+                if before > 0 {
+                    stream.gaps.insert(index, (first, before));
+                }
+                stream.summary.missing -= 1;
+            }
+            Some(ContinuityEvent::OutOfOrder { stream: key, expected, found: seq })
+        };
+
+        // only counts ahead of the last one move the stream along
+        if ahead <= mask / 2 {
+            stream.summary.last_seq = seq;
+        }
+
+        if !matches!(event, Some(ContinuityEvent::Duplicate { .. })) {
+            if stream.recent.len() == RECENT_COUNTS {
+                stream.recent.pop_front();
+            }
+            stream.recent.push_back(seq);
+        }
+
+        if let Some(ref event) = event {
+            self.events.push(event.clone());
+        }
+
+        event
+    }
+}
+
+
+#[cfg(test)]
+mod test_continuity {
+    use super::*;
+    use prim::*;
+
+    fn packet(apid: u16, seq_count: u16) -> Vec<u8> {
+        vec![0x08 | (apid >> 8) as u8, apid as u8, 0xC0 | (seq_count >> 8) as u8, seq_count as u8]
+    }
+
+    #[test]
+    fn test_continuity_checker() {
+        let apid = LocItem::new(vec!["apid".to_string()], Prim::Int(IntPrim::u16_be()), 0);
+        let seq = LocItem::new(vec!["seq".to_string()], Prim::Int(IntPrim::u16_be()), 2);
+        let mut checker = ContinuityChecker::new(IndexItem::masked(seq, 0x3FFF).unwrap())
+                              .with_stream(IndexItem::masked(apid, 0x07FF).unwrap());
+
+        // counts wrap around, and streams are checked separately
+        assert_eq!(checker.check(&packet(5, 0x3FFE)), None);
+        assert_eq!(checker.check(&packet(6, 100)), None);
+        assert_eq!(checker.check(&packet(5, 0x3FFF)), None);
+        assert_eq!(checker.check(&packet(5, 0x0000)), None);
+        assert_eq!(checker.check(&packet(6, 101)), None);
+
+        assert_eq!(checker.check(&packet(5, 0x0003)),
+                   Some(ContinuityEvent::Gap { stream: 5, expected: 1, found: 3, missing: 2 }));
+        assert_eq!(checker.check(&packet(5, 0x0003)), Some(ContinuityEvent::Duplicate { stream: 5, seq: 3 }));
+        assert_eq!(checker.check(&packet(5, 0x0001)),
+                   Some(ContinuityEvent::OutOfOrder { stream: 5, expected: 4, found: 1 }));
+        assert_eq!(checker.check(&packet(5, 0x0004)), None);
+        assert_eq!(checker.check(&packet(6, 99)),
+                   Some(ContinuityEvent::OutOfOrder { stream: 6, expected: 102, found: 99 }));
+        assert_eq!(checker.check(&[0x08, 0x05]), None);
+        assert_eq!(checker.take_events().len(), 4);
+
+        let summary = checker.summary();
+        assert_eq!(summary[&5], StreamSummary { packets: 7, gaps: 1, missing: 1, duplicates: 1, out_of_order: 1,
+                                                first_seq: 0x3FFE, last_seq: 4 });
+        assert_eq!(summary[&6].packets, 3);
+        assert_eq!(summary[&6].last_seq, 101);
+
+        // only late packets from a gap reduce the count of missing packets
+        assert_eq!(checker.check(&packet(7, 10)), None);
+        assert_eq!(checker.check(&packet(7, 14)),
+                   Some(ContinuityEvent::Gap { stream: 7, expected: 11, found: 14, missing: 3 }));
+        assert_eq!(checker.check(&packet(7, 9)),
+                   Some(ContinuityEvent::OutOfOrder { stream: 7, expected: 15, found: 9 }));
+        assert_eq!(checker.summary()[&7].missing, 3);
+        assert_eq!(checker.check(&packet(7, 12)),
+                   Some(ContinuityEvent::OutOfOrder { stream: 7, expected: 15, found: 12 }));
+        assert_eq!(checker.check(&packet(7, 11)),
+                   Some(ContinuityEvent::OutOfOrder { stream: 7, expected: 15, found: 11 }));
+        assert_eq!(checker.check(&packet(7, 12)), Some(ContinuityEvent::Duplicate { stream: 7, seq: 12 }));
+        assert_eq!(checker.summary()[&7].missing, 1);
+    }
+}
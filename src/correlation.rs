@@ -0,0 +1,207 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate fnv;
+
+use std::hash::Hasher;
+
+use self::fnv::FnvHasher;
+
+// Correlate a drifting spacecraft clock with ground time. Each correlation point
+// pairs a spacecraft clock reading with the UTC time it happened, such as from a
+// ground station's receipt time corrected for light time. Packet time tags are
+// converted to UTC through a line fit to all the points or through straight lines
+// between neighbouring points.
+//
+// Spacecraft clock readings are in the units of the packet time tag, seconds since
+// its epoch, and UTC times are seconds since the unix epoch. Times before the first
+// point or after the last are extrapolated from the nearest line.
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CorrelationPoint {
+    pub sclk: f64,
+    pub utc: f64,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Interpolation {
+    // a least squares line through all the points
+    Linear,
+    // straight lines between neighbouring points
+    Piecewise,
+}
+
+impl Interpolation {
+    pub fn parse(name: &str) -> Result<Interpolation, String> {
+        match name {
+            "linear" => Ok(Interpolation::Linear),
+            "piecewise" => Ok(Interpolation::Piecewise),
+            _ => Err(format!("unknown interpolation '{}', expected linear or piecewise", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Interpolation::Linear => "linear",
+            Interpolation::Piecewise => "piecewise",
+        }
+    }
+}
+
+// a spacecraft clock reading converted to UTC
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Correlated {
+    pub utc: f64,
+    // the reading was outside the correlation points
+    pub extrapolated: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TimeCorrelation {
+    name: String,
+    points: Vec<CorrelationPoint>,
+    interpolation: Interpolation,
+    // the rate and offset of the least squares line, utc = rate * sclk + offset
+    fit: (f64, f64),
+}
+
+impl TimeCorrelation {
+    // Correlate through the given points, which need not be in order. The name identifies
+    // the correlation in outputs, such as the file it was loaded from.
+    // With a single point, the spacecraft clock is taken to run at the rate of UTC.
+    pub fn new(name: &str, points: Vec<CorrelationPoint>, interpolation: Interpolation) -> Result<TimeCorrelation, String> {
+        if points.is_empty() {
+            return Err(format!("time correlation {} has no points", name));
+        }
+        if let Some(point) = points.iter().find(|point| !point.sclk.is_finite() || !point.utc.is_finite()) {
+            return Err(format!("time correlation {} has a point that is not a number, at spacecraft clock {} and UTC {}",
+                               name, point.sclk, point.utc));
+        }
+
+        let mut points = points;
+        points.sort_by(|a, b| a.sclk.partial_cmp(&b.sclk).unwrap());
+
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].sclk == pair[1].sclk) {
+            return Err(format!("time correlation {} has more then one point at spacecraft clock {}", name, pair[0].sclk));
+        }
+
+        let fit = least_squares(&points);
+
+        Ok(TimeCorrelation { name: name.to_string(), points, interpolation, fit })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn points(&self) -> &[CorrelationPoint] {
+        &self.points
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    // A description of the correlation to record with outputs, such as
+    // "sclk.csv piecewise 3 points 5f0e2c9a1b7d4e83", ending with the points_hash so
+    // outputs tell apart correlation tables loaded from the same file name.
+    // It contains no commas so it fits in a csv column.
+    pub fn describe(&self) -> String {
+        format!("{} {} {} points {:016x}", self.name, self.interpolation.name(), self.points.len(), self.points_hash())
+            .replace(',', ";")
+    }
+
+    // A hash of the correlation points, in spacecraft clock order.
+    pub fn points_hash(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for point in self.points.iter() {
+            hasher.write_u64(point.sclk.to_bits());
+            hasher.write_u64(point.utc.to_bits());
+        }
+        hasher.finish()
+    }
+
+    pub fn correlate(&self, sclk: f64) -> Correlated {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        let extrapolated = sclk < first.sclk || sclk > last.sclk;
+
+        let utc = match self.interpolation {
+            _ if self.points.len() == 1 => sclk - first.sclk + first.utc,
+
+            Interpolation::Linear => self.fit.0 * sclk + self.fit.1,
+
+            Interpolation::Piecewise => {
+                // the line through the points on either side, or the nearest pair when extrapolating
+                let after = self.points.iter().position(|point| point.sclk > sclk)
+                                               .unwrap_or(self.points.len() - 1)
+                                               .max(1);
+                let (start, end) = (self.points[after - 1], self.points[after]);
+
+                start.utc + (sclk - start.sclk) * (end.utc - start.utc) / (end.sclk - start.sclk)
+            },
+        };
+
+        Correlated { utc, extrapolated }
+    }
+}
+
+fn least_squares(points: &[CorrelationPoint]) -> (f64, f64) {
+    let count = points.len() as f64;
+    let mean_sclk = points.iter().map(|point| point.sclk).sum::<f64>() / count;
+    let mean_utc = points.iter().map(|point| point.utc).sum::<f64>() / count;
+
+    let covariance: f64 = points.iter().map(|point| (point.sclk - mean_sclk) * (point.utc - mean_utc)).sum();
+    let variance: f64 = points.iter().map(|point| (point.sclk - mean_sclk).powi(2)).sum();
+
+    let rate = if variance > 0.0 { covariance / variance } else { 1.0 };
+    (rate, mean_utc - rate * mean_sclk)
+}
+
+
+#[cfg(test)]
+mod test_correlation {
+    use super::*;
+
+    #[test]
+    fn test_time_correlation() {
+        // the spacecraft clock runs fast, then keeps time
+        let points = vec![CorrelationPoint { sclk: 200.0, utc: 1000.0 + 199.0 },
+                          CorrelationPoint { sclk: 0.0, utc: 1000.0 },
+                          CorrelationPoint { sclk: 100.0, utc: 1000.0 + 99.0 }];
+
+        let piecewise = TimeCorrelation::new("sclk.csv", points.clone(), Interpolation::Piecewise).unwrap();
+        assert_eq!(piecewise.correlate(50.0), Correlated { utc: 1049.5, extrapolated: false });
+        assert_eq!(piecewise.correlate(150.0), Correlated { utc: 1149.0, extrapolated: false });
+        assert_eq!(piecewise.correlate(300.0), Correlated { utc: 1299.0, extrapolated: true });
+        assert_eq!(piecewise.correlate(-100.0), Correlated { utc: 901.0, extrapolated: true });
+        assert_eq!(piecewise.describe(), format!("sclk.csv piecewise 3 points {:016x}", piecewise.points_hash()));
+
+        // the same points in another order are the same correlation, while a moved point is not
+        let reordered = TimeCorrelation::new("sclk.csv", vec![points[2], points[0], points[1]], Interpolation::Piecewise).unwrap();
+        assert_eq!(reordered.describe(), piecewise.describe());
+        let moved = CorrelationPoint { sclk: 100.0, utc: 1000.0 + 98.0 };
+        let updated = TimeCorrelation::new("sclk.csv", vec![points[0], points[1], moved], Interpolation::Piecewise).unwrap();
+        assert_ne!(updated.describe(), piecewise.describe());
+
+        let linear = TimeCorrelation::new("sclk.csv", points.clone(), Interpolation::Linear).unwrap();
+        let correlated = linear.correlate(100.0);
+        assert!((correlated.utc - (1000.0 + 99.0 + 1.0 / 3.0)).abs() < 1e-9);
+        assert!(!correlated.extrapolated);
+
+        let single = TimeCorrelation::new("one", vec![points[0]], Interpolation::Linear).unwrap();
+        assert_eq!(single.correlate(210.0).utc, 1209.0);
+
+        assert!(TimeCorrelation::new("none", vec![], Interpolation::Linear).is_err());
+        assert!(TimeCorrelation::new("repeat", vec![points[0], points[0]], Interpolation::Piecewise).is_err());
+        let nan = CorrelationPoint { sclk: f64::NAN, utc: 5.0 };
+        assert!(TimeCorrelation::new("nan", vec![points[0], nan, points[1]], Interpolation::Piecewise).is_err());
+        let inf = CorrelationPoint { sclk: 300.0, utc: f64::INFINITY };
+        assert!(TimeCorrelation::new("inf", vec![points[0], inf], Interpolation::Linear).is_err());
+        assert_eq!(Interpolation::parse("piecewise"), Ok(Interpolation::Piecewise));
+    }
+}
@@ -8,8 +8,7 @@ use std::collections::BTreeMap;
 
 extern crate csv;
 
-use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 
 
 use types::*;
@@ -17,44 +16,104 @@ use layout::*;
 use loclayout::*;
 use value::*;
 use packet::*;
+use timetag::*;
+use correlation::*;
 
-pub fn valuemap_csv(map : &ValueMap, writer : &mut csv::Writer<File>)
+
+// How a column is named from the path of the item it contains.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ColumnNaming {
+    // only the item's own name, such as yawPitchRoll[0]
+    Last,
+    // the full path joined with the given separator, such as group2.yawPitchRoll[0]
+    Full(String),
+}
+
+impl Default for ColumnNaming {
+    fn default() -> ColumnNaming {
+        ColumnNaming::Full(".".to_string())
+    }
+}
+
+pub fn path_name(path : &LocPath, naming : &ColumnNaming) -> String {
+    match naming {
+        ColumnNaming::Last => path.last().cloned().unwrap_or_default(),
+        ColumnNaming::Full(separator) => path.join(separator),
+    }
+}
+
+// Write the values of a decoded map in the order of the given paths, which should
+// be the same paths used to write the header. Missing values are left empty so
+// every row has a column for each entry in the header.
+pub fn valuemap_csv<W : Write>(map    : &ValueMap,
+                               paths  : &[LocPath],
+                               writer : &mut csv::Writer<W>)
 {
-    writer.write_record(map.values().iter().map(|value| {value.to_string()})).unwrap();
+    writer.write_record(paths.iter().map(|path| {
+        match map.lookup_path(path) {
+            Some(value) => value.to_string(),
+            None => String::new(),
+        }
+    })).unwrap();
 }
 
-pub fn layout_csvheader(layout : &Layout,
-                         writer : &mut csv::Writer<File>)
+pub fn layout_csvheader<W : Write>(layout : &Layout,
+                                   naming : &ColumnNaming,
+                                   writer : &mut csv::Writer<W>)
 {
-    writer.write_record(layout.names().iter()).unwrap();
+    writer.write_record(layout.paths().iter().map(|path| path_name(path, naming))).unwrap();
 }
 
-pub fn layoutpacket_csvheader(packet : &LayoutPacketDef,
-                              writer : &mut File)
+// Write the header for a packet's columns. Variable size arrays have no fixed set
+// of columns, so packets with them are rejected rather then losing their values.
+pub fn layoutpacket_csvheader<W : Write>(packet : &LayoutPacketDef,
+                                         naming : &ColumnNaming,
+                                         writer : &mut W) -> Result<(), String>
 {
+    if let Some(path) = packet.variable_arrays().first() {
+        return Err(format!("array {} has a variable size, so it has no fixed csv columns", path.join(".")));
+    }
+
     let mut line = String::new();
 
-    packet.names().iter().map(|s| {
-        line.push_str(s);
-        line.push_str(",");
-    }).collect::<Vec<()>>();
-    line.push_str("\n");
+    paths_to_str(&packet.paths(), naming, &mut line);
+
+    writer.write_all(line.as_bytes()).unwrap();
 
-    writer.write(line.as_bytes());
+    Ok(())
 }
 
-pub fn loclayout_csvheader(loc_layout : &LocLayout,
-                           writer : &mut File)
+pub fn loclayout_csvheader<W : Write>(loc_layout : &LocLayout,
+                                      naming     : &ColumnNaming,
+                                      writer     : &mut W)
 {
     let mut line = String::new();
 
-    loc_layout.loc_items.iter().map(|loc_item| {
-        line.push_str(loc_item.name.last().unwrap());
-        line.push_str(",");
-    }).collect::<Vec<()>>();
-    line.push_str("\n");
+    let paths: Vec<LocPath> = loc_layout.loc_items.iter().map(|loc_item| loc_item.name.clone()).collect();
+    paths_to_str(&paths, naming, &mut line);
+
+    writer.write_all(line.as_bytes()).unwrap();
+}
+
+pub fn paths_to_str(paths: &[LocPath], naming: &ColumnNaming, line: &mut String) {
+    line.clear();
+    for path in paths {
+        line.push_str(&path_name(path, naming));
+        line.push(',');
+    }
+    line.push('\n');
+}
 
-    writer.write(line.as_bytes());
+// Write a row of values in the same layout as paths_to_str, leaving missing values empty.
+pub fn valuemap_to_str(map: &ValueMap, paths: &[LocPath], line: &mut String) {
+    line.clear();
+    for path in paths {
+        if let Some(value) = map.lookup_path(path) {
+            line.push_str(&format!("{}", value));
+        }
+        line.push(',');
+    }
+    line.push('\n');
 }
 
 pub fn points_to_str(points: &Vec<Point>, line: &mut String) {
@@ -66,3 +125,81 @@ pub fn points_to_str(points: &Vec<Point>, line: &mut String) {
     line.push_str("\n");
 }
 
+// Read time correlation points, with a spacecraft clock reading and a UTC time on each row.
+// UTC times may be unix times or dates, such as 2017-01-01T00:00:00.5Z. A header row and
+// lines starting with # are skipped.
+pub fn correlation_points_csv<R : Read>(reader : R) -> Result<Vec<CorrelationPoint>, String> {
+    let mut reader = csv::ReaderBuilder::new().has_headers(false)
+                                              .comment(Some(b'#'))
+                                              .flexible(true)
+                                              .trim(csv::Trim::All)
+                                              .from_reader(reader);
+    let mut points = Vec::new();
+
+    for (index, row) in reader.records().enumerate() {
+        let row = row.map_err(|err| err.to_string())?;
+
+        let sclk = row.get(0).and_then(|field| field.parse::<f64>().ok());
+        let utc = row.get(1).and_then(|field| field.parse::<f64>().ok().or_else(|| parse_date_time(field)));
+
+        match (sclk, utc) {
+            (Some(sclk), Some(utc)) => points.push(CorrelationPoint { sclk, utc }),
+            // a header
+            _ if index == 0 => {},
+            _ => return Err(format!("invalid correlation point on row {}, expected a spacecraft clock and UTC time",
+                                    index + 1)),
+        }
+    }
+
+    Ok(points)
+}
+
+
+#[cfg(test)]
+mod test_csv {
+    use super::*;
+    use std::io::Cursor;
+    use decode::*;
+    use *;
+
+    #[test]
+    fn test_csv_columns_follow_definition() {
+        let packet : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec!(seq("group1".to_string(), vec!(u8_be("status"), u8_be("count"))),
+                     seq("group2".to_string(), vec!(u8_be("status"),
+                                                   array_fixed("rate".to_string(), 2, u16_be("value"))))));
+
+        let mut header = String::new();
+        paths_to_str(&packet.paths(), &ColumnNaming::default(), &mut header);
+        assert_eq!(header, "tlm.group1.status,tlm.group1.count,tlm.group2.status,\
+                            tlm.group2.rate[0].value,tlm.group2.rate[1].value,\n");
+
+        let mut header = String::new();
+        paths_to_str(&packet.paths(), &ColumnNaming::Full("/".to_string()), &mut header);
+        assert!(header.starts_with("tlm/group1/status,"));
+
+        let mut written = Vec::new();
+        assert!(layoutpacket_csvheader(&packet, &ColumnNaming::default(), &mut written).is_ok());
+        assert!(written.starts_with(b"tlm.group1.status,"));
+
+        let var_packet = seq("tlm".to_string(),
+                             vec!(u8_be("count"),
+                                  array_var("samples".to_string(), "count".to_string(), u16_be("value"))));
+        assert_eq!(var_packet.variable_arrays(), vec![vec!["tlm".to_string(), "samples[]".to_string()]]);
+        assert!(layoutpacket_csvheader(&var_packet, &ColumnNaming::default(), &mut Vec::new()).is_err());
+
+        let v = vec![0x01, 0x02, 0x03, 0x00, 0x04, 0x00, 0x05];
+        let map = decode_layoutpacket(&packet, &mut Cursor::new(v.as_slice())).unwrap();
+
+        let mut row = String::new();
+        valuemap_to_str(&map, &packet.paths(), &mut row);
+        assert_eq!(row, "1,2,3,4,5,\n");
+
+        let table = "sclk, utc\n# pass 1\n100, 2017-01-01T00:00:00Z\n200.5, 1483228900.5\n";
+        assert_eq!(correlation_points_csv(table.as_bytes()).unwrap(),
+                   vec![CorrelationPoint { sclk: 100.0, utc: 1483228800.0 },
+                        CorrelationPoint { sclk: 200.5, utc: 1483228900.5 }]);
+        assert!(correlation_points_csv("100, 1\nnow, 2\n".as_bytes()).is_err());
+    }
+}
@@ -153,7 +153,9 @@ pub fn decode_int(int_prim : &IntPrim, bytes : &mut Cursor<&[u8]>) -> Value {
 fn decode_bits(bits : &BitPrim, bytes : &mut Cursor<&[u8]>, map : &mut ValueMap) {
     let BitPrim{entries, num_bytes} = bits;
     {
-        let slice = bytes.get_mut();
+        // read from the cursor's position, so bit fields can follow other items
+        let position = bytes.position() as usize;
+        let slice = &bytes.get_ref()[position..];
         let mut reader = BitReader::new(slice);
         for (name, num_bits, int_prim) in entries.iter() {
             match int_prim.signedness {
@@ -249,7 +251,14 @@ fn decode_layout(layout : &Layout, bytes : &mut Cursor<&[u8]>, map : &mut ValueM
         },
 
         Layout::Array(name, size, layout) => {
+            let mut elements = Vec::with_capacity(*size as usize);
+            for _ in 0 .. *size {
+                let mut element = ValueMap::new(FnvHashMap::default());
+                decode_layout(layout, bytes, &mut element);
+                elements.push(element);
+            }
 
+            map.value_map.insert(name.to_string(), ValueEntry::Array(elements));
         }
         
         // NOTE - Bit fields currently do not support endianness choice
@@ -279,49 +288,101 @@ pub fn decode_loc_item(loc_item : &LocItem, bytes : &mut Cursor<&[u8]>) -> Point
     point
 }
 
+// Decode a packet into a map following its definition's structure. Subcom items
+// with values matching none of the subcom's branches are an error, as are array
+// counts that are missing, not whole numbers, or too large for the bytes left.
 pub fn decode_layoutpacket(layout_packet : &LayoutPacketDef,
-                           bytes         : &mut Cursor<&[u8]>) -> ValueMap {
-    let mut map = ValueMap::new(FnvHashMap::default());
+                           bytes         : &mut Cursor<&[u8]>) -> Result<ValueMap, String> {
+    let mut scopes = vec![ValueMap::new(FnvHashMap::default())];
 
-    decode_layoutpacket_helper(layout_packet, bytes, &mut map);
+    decode_layoutpacket_helper(layout_packet, bytes, &mut scopes)?;
 
-    map
+    Ok(scopes.pop().unwrap())
+}
+
+// Look up a name starting from the innermost section being decoded,
+// so items that control arrays and subcoms can be defined in any
+// enclosing section.
+// The number of elements of an array counted by an item, checking that the count was
+// decoded, is a whole number, and could fit in the bytes left given the smallest
+// size of an element.
+pub fn array_count(name          : &Name,
+                   count_name    : &Name,
+                   count         : Option<Value>,
+                   element_bytes : u64,
+                   bytes_left    : u64) -> Result<usize, String> {
+    let count: i128 = match count {
+        None =>
+            return Err(format!("array {} is counted by {}, which has not been decoded", name, count_name)),
+        Some(Value::F32(_)) | Some(Value::F64(_)) =>
+            return Err(format!("array {} is counted by {}, which is not an integer", name, count_name)),
+        Some(Value::Enum(_, count)) => count as i128,
+        Some(Value::U64(count)) => count as i128,
+        Some(count) => count.value() as i128,
+    };
+
+    if count < 0 {
+        return Err(format!("array {} has a negative count of {} from {}", name, count, count_name));
+    }
+    if count.checked_mul(element_bytes as i128).is_none_or(|needed| needed > bytes_left as i128) {
+        return Err(format!("array {} has {} elements of at least {} bytes, but only {} bytes are left",
+                           name, count, element_bytes, bytes_left));
+    }
+
+    Ok(count as usize)
+}
+
+// The fewest bytes a packet can take, counting variable arrays as empty and
+// subcoms as their smallest branch.
+fn min_num_bytes(packet : &LayoutPacketDef) -> u64 {
+    match packet {
+        PacketDef::Seq(_, packets) => packets.iter().map(min_num_bytes).sum(),
+
+        PacketDef::Subcom(_, _, branches, default) =>
+            branches.iter().map(|(_, packet)| packet)
+                    .chain(default.iter().map(|packet| &**packet))
+                    .map(min_num_bytes)
+                    .min()
+                    .unwrap_or(0),
+
+        PacketDef::Array(_, ArrSize::Fixed(num), packet) => *num as u64 * min_num_bytes(packet),
+
+        PacketDef::Array(_, ArrSize::Var(_), _) => 0,
+
+        PacketDef::Leaf(layout) => layout.num_bytes(),
+    }
+}
+
+fn lookup_scopes(scopes : &[ValueMap], name : &Name) -> Option<Value> {
+    scopes.iter().rev().filter_map(|map| map.lookup(name)).next()
 }
 
 pub fn decode_layoutpacket_helper(layout_packet : &LayoutPacketDef,
                                   bytes         : &mut Cursor<&[u8]>,
-                                  map           : &mut ValueMap) {
+                                  scopes        : &mut Vec<ValueMap>) -> Result<(), String> {
     match layout_packet {
         PacketDef::Seq(name, packets) => {
+            scopes.push(ValueMap::new(FnvHashMap::default()));
             for packet in packets {
-                decode_layoutpacket_helper(packet, bytes, map);
+                decode_layoutpacket_helper(packet, bytes, scopes)?;
             }
+            let section = scopes.pop().unwrap();
+
+            scopes.last_mut().unwrap()
+                  .value_map.insert(name.clone(), ValueEntry::Section(section));
         },
 
-        PacketDef::Subcom(name, item, subcom) => {
-            let entry = map.value_map[&item.name].clone();
-
-            match entry {
-               ValueEntry::Leaf(value) => {
-                  for (item_key, packet) in subcom {
-                      let subcom_entry = map.value_map[&item_key.name].clone();
-                      match subcom_entry {
-                         ValueEntry::Leaf(subcom_value) => {
-                             if value == subcom_value {
-                                 decode_layoutpacket_helper(packet, bytes, map);
-                                 break;
-                             }
-
-                         }
-
-                         _ => (),
-                      }
-                  }
-               },
-
-               // NOTE if the name refers to something else then a value, it is skipped
-               _ => ()
+        PacketDef::Subcom(name, items, branches, default) => {
+            let mut values = Vec::with_capacity(items.len());
+            for item in items {
+                match lookup_scopes(scopes, &item.name) {
+                    Some(value) => values.push(value),
+                    None => return Err(format!("subcom {} switches on {}, which has not been decoded", name, item.name)),
+                }
             }
+
+            let packet = select_branch(name, branches, default, &values)?;
+            decode_layoutpacket_helper(packet, bytes, scopes)?;
         },
 
         PacketDef::Array(name, size, packet) => {
@@ -331,26 +392,38 @@ pub fn decode_layoutpacket_helper(layout_packet : &LayoutPacketDef,
                     num_elements = *num;
                 }
 
-                ArrSize::Var(name) => {
-                    // TODO this should search the full map recursively.
-                    // an optimization would be to preprocess the packet and keep track of
+                ArrSize::Var(var_name) => {
+                    // NOTE an optimization would be to preprocess the packet and keep track of
                     // a map of names that need to be used like this.
-                    num_elements = map.lookup(&name.to_string()).unwrap().value() as usize;
+                    let bytes_left = (bytes.get_ref().len() as u64).saturating_sub(bytes.position());
+                    num_elements = array_count(name, var_name, lookup_scopes(scopes, var_name),
+                                               min_num_bytes(packet), bytes_left)?;
                 }
             }
+
+            // NOTE the count comes from the packet, so elements are not allocated up front
+            let mut elements = Vec::new();
             for _ in 0..num_elements {
-              decode_layoutpacket_helper(packet, bytes, map);
+                scopes.push(ValueMap::new(FnvHashMap::default()));
+                decode_layoutpacket_helper(packet, bytes, scopes)?;
+                elements.push(scopes.pop().unwrap());
             }
+
+            scopes.last_mut().unwrap()
+                  .value_map.insert(name.clone(), ValueEntry::Array(elements));
         }
 
         PacketDef::Leaf(item) => {
             let prim = decode_prim(&item.typ, bytes);
             #[cfg(feature = "profile")] flame::start("insert prim");
-            map.value_map.insert(item.name.clone(),
-                                 ValueEntry::Leaf(prim));
+            scopes.last_mut().unwrap()
+                  .value_map.insert(item.name.clone(),
+                                    ValueEntry::Leaf(prim));
             #[cfg(feature = "profile")] flame::end("insert prim");
         },
     }
+
+    Ok(())
 }
 
 
@@ -404,7 +477,7 @@ mod test_decode {
       assert!(value_all1 == Value::U32(0x12345678));
 
       let value_all2 = value_map.lookup(&"all2".to_string()).unwrap();
-      assert!(value_all0 == Value::U8(0x12));
+      assert!(value_all2 == Value::U8(0x12));
 
       let value_prim0 = value_map.lookup(&"prim0".to_string()).unwrap();
       assert!(value_prim0 == Value::U8(0xAA));
@@ -509,5 +582,92 @@ mod test_decode {
       assert!(long_be == Value::U64(0x1122334455667788));
       assert!(long_le == Value::U64(0x1122334455667788));
     }
-}
 
+    #[test]
+    fn test_decode_array_count() {
+        use ::*;
+
+        let packet = |count: LayoutPacketDef| -> LayoutPacketDef {
+            seq("tlm".to_string(),
+                vec![count, array_var("samples".to_string(), "count".to_string(), u16_be("sample"))])
+        };
+        let decode = |packet: &LayoutPacketDef, bytes: &[u8]| decode_layoutpacket(packet, &mut Cursor::new(bytes));
+
+        let counted = packet(u8_be("count"));
+        assert!(decode(&counted, &[0x02, 0x00, 0x10, 0x00, 0x20]).is_ok());
+        // a count past the end of the packet
+        assert_eq!(decode(&counted, &[0xFF, 0x00, 0x10]),
+                   Err("array samples has 255 elements of at least 2 bytes, but only 2 bytes are left".to_string()));
+
+        assert!(decode(&packet(i8_be("count")), &[0xFE]).is_err());
+        assert!(decode(&packet(f32_be("count")), &[0x3F, 0x80, 0x00, 0x00, 0x00, 0x10]).is_err());
+        assert!(decode(&packet(u8_be("other")), &[0x01, 0x00, 0x10]).is_err());
+
+        // located definitions check their counts the same way
+        let count_item = LocItem::new(vec!["count".to_string()], Prim::Int(IntPrim::u8_be()), 0);
+        let sample_item = LocItem::new(vec!["sample".to_string()], Prim::Int(IntPrim::u16_be()), 1);
+        let loc_packet : LocPacketDef =
+            seq("tlm".to_string(),
+                vec![leaf(count_item), array_var("samples".to_string(), "count".to_string(), leaf(sample_item))]);
+        assert!(identify_locpacket(&loc_packet, &mut Cursor::new(&[0x01, 0x00, 0x10][..])).is_ok());
+        assert!(identify_locpacket(&loc_packet, &mut Cursor::new(&[0xFF, 0x00, 0x10][..])).is_err());
+    }
+
+    #[test]
+    fn test_decode_subcom() {
+        use ::*;
+
+        // switch on a type and mode pair, with a range of modes and a default for other types
+        let packet : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec![u8_be("type"), u16_be("mode"),
+                     PacketDef::Subcom("payload".to_string(),
+                                       vec![item("type", Prim::Int(IntPrim::u8_be())),
+                                            item("mode", Prim::Int(IntPrim::u16_be()))],
+                                       vec![(vec![SubcomKey::Value(val_u8(1)), SubcomKey::Value(val_u8(0))], u8_be("idle")),
+                                            (vec![SubcomKey::Value(val_u8(1)), SubcomKey::Range(val_u8(1), val_u8(9))], u16_be("rate")),
+                                            (vec![SubcomKey::Value(val_u8(2)), SubcomKey::Any], u8_be("status"))],
+                                       Some(Box::new(u8_be("raw"))))]);
+
+        let decode = |bytes: &[u8]| decode_layoutpacket(&packet, &mut Cursor::new(bytes));
+        let lookup = |map: &ValueMap, name: &str| map.lookup_path(&["tlm".to_string(), name.to_string()]).cloned();
+
+        let map = decode(&[0x01, 0x00, 0x00, 0x07]).unwrap();
+        assert_eq!(lookup(&map, "idle"), Some(Value::U8(7)));
+
+        let map = decode(&[0x01, 0x00, 0x09, 0x01, 0x02]).unwrap();
+        assert_eq!(lookup(&map, "rate"), Some(Value::U16(0x0102)));
+        assert_eq!(lookup(&map, "idle"), None);
+
+        let map = decode(&[0x02, 0xFF, 0xFF, 0x05]).unwrap();
+        assert_eq!(lookup(&map, "status"), Some(Value::U8(5)));
+
+        let map = decode(&[0x03, 0x00, 0x00, 0x06]).unwrap();
+        assert_eq!(lookup(&map, "raw"), Some(Value::U8(6)));
+
+        // without a default, values matching no branch are an error
+        let strict : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec![u8_be("type"),
+                     PacketDef::Subcom("payload".to_string(),
+                                       vec![item("type", Prim::Int(IntPrim::u8_be()))],
+                                       vec![(vec![SubcomKey::Value(val_u8(1))], u8_be("idle"))],
+                                       None)]);
+        assert_eq!(decode_layoutpacket(&strict, &mut Cursor::new(&[0x04, 0x00][..])),
+                   Err("subcom payload has no branch for (4)".to_string()));
+
+        // located definitions take the same branches
+        let type_item = LocItem::new(vec!["type".to_string()], Prim::Int(IntPrim::u8_be()), 0);
+        let loc_packet : LocPacketDef =
+            seq("tlm".to_string(),
+                vec![leaf(type_item.clone()),
+                     PacketDef::Subcom("payload".to_string(),
+                                       vec![type_item.clone()],
+                                       vec![(vec![SubcomKey::Range(val_i32(1), val_i32(3))],
+                                             leaf(LocItem::new(vec!["idle".to_string()], Prim::Int(IntPrim::u8_be()), 1)))],
+                                       None)]);
+        let loc_layout = identify_locpacket(&loc_packet, &mut Cursor::new(&[0x02, 0x00][..])).unwrap();
+        assert_eq!(loc_layout.loc_items.len(), 2);
+        assert!(identify_locpacket(&loc_packet, &mut Cursor::new(&[0x05, 0x00][..])).is_err());
+    }
+}
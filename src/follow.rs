@@ -0,0 +1,234 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::fs;
+use std::fs::File;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use framing::*;
+
+
+// Follow a capture file as it is written, like tail -f. Packets are framed as
+// bytes are appended, and at the end of the file the source waits for more.
+// A partial packet at the end of the file is kept until the rest arrives.
+//
+// If the file shrinks it was truncated, and it is read again from the start.
+// If the path now names a different file it was rotated, so the rest of the old
+// file is read and then the new file is followed from its start. Either way the
+// partial packet, if any, is dropped.
+//
+// NOTE truncation is only noticed if the file is shorter then what was already
+// read, so a file truncated and then quickly refilled past that point is missed.
+pub struct FollowSource<F> {
+    path: String,
+    file: File,
+    position: u64,
+    framer: F,
+    chunk: Vec<u8>,
+    poll_interval: Duration,
+    idle_timeout: Option<Duration>,
+    last_data: Instant,
+    truncations: usize,
+    rotations: usize,
+    error: Option<io::Error>,
+}
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+impl<F : Framer> FollowSource<F> {
+    pub fn open(path: &str, framer: F) -> io::Result<FollowSource<F>> {
+        Ok(FollowSource { path: path.to_string(),
+                          file: File::open(path)?,
+                          position: 0,
+                          framer,
+                          chunk: vec![0; READ_CHUNK_SIZE],
+                          poll_interval: Duration::from_millis(200),
+                          idle_timeout: None,
+                          last_data: Instant::now(),
+                          truncations: 0,
+                          rotations: 0,
+                          error: None })
+    }
+
+    // how long to wait at the end of the file before checking for more
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> FollowSource<F> {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // end the stream once the file has not grown for this long
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> FollowSource<F> {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn framer(&self) -> &F {
+        &self.framer
+    }
+
+    pub fn truncations(&self) -> usize {
+        self.truncations
+    }
+
+    pub fn rotations(&self) -> usize {
+        self.rotations
+    }
+
+    // the error that ended the stream, if it did not end from the idle timeout
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // At the end of the file, check whether it was truncated or rotated,
+    // returning true if there may be more to read.
+    fn check_file(&mut self) -> io::Result<bool> {
+        if self.file.metadata()?.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.position = 0;
+            self.framer.clear();
+            self.truncations += 1;
+            return Ok(true);
+        }
+
+        // NOTE the path may be missing for a moment during rotation
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if !same_file(&self.file.metadata()?, &metadata) {
+                // finish anything written to the old file before switching
+                let num_bytes = self.file.read(&mut self.chunk)?;
+                if num_bytes > 0 {
+                    self.framer.push(&self.chunk[..num_bytes]);
+                    self.position += num_bytes as u64;
+                    self.last_data = Instant::now();
+                    return Ok(true);
+                }
+
+                self.file = File::open(&self.path)?;
+                self.position = 0;
+                self.framer.clear();
+                self.rotations += 1;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(unix)]
+fn same_file(left: &fs::Metadata, right: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    left.dev() == right.dev() && left.ino() == right.ino()
+}
+
+// NOTE rotation is not detected on other platforms
+#[cfg(not(unix))]
+fn same_file(_left: &fs::Metadata, _right: &fs::Metadata) -> bool {
+    true
+}
+
+impl<F : Framer> Iterator for FollowSource<F> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(packet) = self.framer.next_packet() {
+                return Some(packet);
+            }
+
+            match self.file.read(&mut self.chunk) {
+                Ok(0) => {
+                    match self.check_file() {
+                        Ok(true) => continue,
+
+                        Ok(false) => (),
+
+                        Err(err) => {
+                            self.error = Some(err);
+                            return None;
+                        },
+                    }
+
+                    if self.idle_timeout.is_some_and(|timeout| self.last_data.elapsed() >= timeout) {
+                        return None;
+                    }
+
+                    thread::sleep(self.poll_interval);
+                },
+
+                Ok(num_bytes) => {
+                    self.framer.push(&self.chunk[..num_bytes]);
+                    self.position += num_bytes as u64;
+                    self.last_data = Instant::now();
+                },
+
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_follow {
+    use super::*;
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn append(path: &str, bytes: &[u8]) {
+        OpenOptions::new().append(true).create(true).open(path).unwrap().write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_follow_file() {
+        let path = env::temp_dir().join(format!("gasworks_follow_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let rotated = format!("{}.1", path);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+
+        append(&path, &[1, 1, 1, 1, 2, 2]);
+
+        // each change to the file is made between packets, so the source sees them in order
+        let mut source = FollowSource::open(&path, FixedFramer::new(4)).unwrap();
+        assert_eq!(source.next(), Some(vec![1, 1, 1, 1]));
+
+        // finish the partial packet, leaving another partial packet behind
+        append(&path, &[2, 2, 9]);
+        assert_eq!(source.next(), Some(vec![2, 2, 2, 2]));
+
+        // truncate, dropping the partial packet
+        fs::write(&path, [3, 3, 3, 3]).unwrap();
+        assert_eq!(source.next(), Some(vec![3, 3, 3, 3]));
+
+        // rotate, with a last packet written to the old file
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, &[4, 4, 4, 4]);
+        append(&path, &[5, 5, 5, 5]);
+        assert_eq!(source.next(), Some(vec![4, 4, 4, 4]));
+        assert_eq!(source.next(), Some(vec![5, 5, 5, 5]));
+
+        // with no time left to wait, the stream ends at the end of the file
+        let mut source = source.with_idle_timeout(Duration::from_millis(0));
+        assert_eq!(source.next(), None);
+
+        assert_eq!(source.truncations(), 1);
+        assert_eq!(source.rotations(), 1);
+        assert!(source.take_error().is_none());
+
+        fs::remove_file(&rotated).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::io;
+use std::io::Read;
+
+use self::byteorder::{BigEndian, LittleEndian, ByteOrder};
+
+use types::*;
+use prim::*;
+
+
+// Framers split a stream of bytes into packets. Bytes are pushed in as they
+// are received, in whatever size chunks they arrive, and complete packets are
+// taken out. Any partial packet is kept until the rest of it is pushed.
+pub trait Framer {
+    fn push(&mut self, bytes: &[u8]);
+
+    fn next_packet(&mut self) -> Option<Vec<u8>>;
+
+    // number of bytes received but not yet returned in a packet
+    fn buffered(&self) -> usize;
+
+    // drop any partial packet, such as when a stream is restarted
+    fn clear(&mut self);
+}
+
+// Frame packets of a single fixed size, laid end to end.
+#[derive(Debug, Clone)]
+pub struct FixedFramer {
+    size: usize,
+    buffer: Vec<u8>,
+}
+
+impl FixedFramer {
+    pub fn new(size: usize) -> FixedFramer {
+        assert!(size > 0, "fixed size packets must have at least one byte");
+        FixedFramer { size, buffer: Vec::new() }
+    }
+}
+
+impl Framer for FixedFramer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() >= self.size {
+            Some(self.buffer.drain(..self.size).collect())
+        } else {
+            None
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+// A packet length field within a packet's header, such as the CCSDS packet
+// data length. The full packet length is the field's value plus the adjustment,
+// so for CCSDS the field is a u16 at offset 4 with an adjustment of 7.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct LengthField {
+    pub offset: usize,
+    pub int_prim: IntPrim,
+    pub adjust: i64,
+}
+
+impl LengthField {
+    pub fn new(offset: usize, int_prim: IntPrim, adjust: i64) -> LengthField {
+        LengthField { offset, int_prim, adjust }
+    }
+
+    // number of bytes needed before the length is known
+    pub fn header_bytes(&self) -> usize {
+        self.offset + self.int_prim.num_bytes() as usize
+    }
+
+    // The full packet length, if enough of the header is present. The field is
+    // read as unsigned regardless of the prim's signedness.
+    pub fn packet_length(&self, bytes: &[u8]) -> Option<i64> {
+        if bytes.len() < self.header_bytes() {
+            return None;
+        }
+
+        let field = &bytes[self.offset..];
+        let value = match (&self.int_prim.size, &self.int_prim.endianness) {
+            (IntSize::Bits8,  _)                         => field[0] as u64,
+            (IntSize::Bits16, Endianness::BigEndian)     => BigEndian::read_u16(field) as u64,
+            (IntSize::Bits16, Endianness::LittleEndian)  => LittleEndian::read_u16(field) as u64,
+            (IntSize::Bits32, Endianness::BigEndian)     => BigEndian::read_u32(field) as u64,
+            (IntSize::Bits32, Endianness::LittleEndian)  => LittleEndian::read_u32(field) as u64,
+            (IntSize::Bits64, Endianness::BigEndian)     => BigEndian::read_u64(field),
+            (IntSize::Bits64, Endianness::LittleEndian)  => LittleEndian::read_u64(field),
+        };
+
+        Some(value as i64 + self.adjust)
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum FrameLength {
+    Fixed(usize),
+    Field(LengthField),
+}
+
+// Frame packets that start with a sync marker, such as the 0xFA of a VN200 binary
+// message. Bytes before a marker are discarded, and a packet's length is either
+// fixed or read from a length field counted from the start of the marker.
+//
+// With no marker this is a length prefixed framer, where each packet is expected
+// right after the last. If a length is out of range the framer has lost sync, so
+// it drops a byte and tries again from the next position.
+#[derive(Debug, Clone)]
+pub struct SyncFramer {
+    marker: Vec<u8>,
+    length: FrameLength,
+    max_length: usize,
+    buffer: Vec<u8>,
+    discarded: usize,
+}
+
+const DEFAULT_MAX_LENGTH: usize = 65536 + 7;
+
+impl SyncFramer {
+    pub fn new(marker: Vec<u8>, length: FrameLength) -> SyncFramer {
+        if let FrameLength::Fixed(size) = length {
+            assert!(size > 0 && size >= marker.len(), "fixed size packets must hold at least the sync marker");
+        }
+
+        SyncFramer { marker, length, max_length: DEFAULT_MAX_LENGTH, buffer: Vec::new(), discarded: 0 }
+    }
+
+    pub fn length_prefixed(field: LengthField) -> SyncFramer {
+        SyncFramer::new(Vec::new(), FrameLength::Field(field))
+    }
+
+    // lengths above this are taken as a loss of sync
+    pub fn with_max_length(mut self, max_length: usize) -> SyncFramer {
+        self.max_length = max_length;
+        self
+    }
+
+    // number of bytes dropped while searching for sync
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    fn discard(&mut self, num_bytes: usize) {
+        self.buffer.drain(..num_bytes);
+        self.discarded += num_bytes;
+    }
+}
+
+impl Framer for SyncFramer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            // line up the buffer with the next marker, keeping any partial marker at the end
+            match find_marker(&self.buffer, &self.marker) {
+                Some(position) => self.discard(position),
+
+                None => {
+                    let keep = (self.marker.len() - 1).min(self.buffer.len());
+                    let position = self.buffer.len() - keep;
+                    self.discard(position);
+                    return None;
+                },
+            }
+
+            let length = match self.length {
+                FrameLength::Fixed(size) => size as i64,
+
+                FrameLength::Field(ref field) => {
+                    let min_length = field.header_bytes().max(self.marker.len()) as i64;
+
+                    match field.packet_length(&self.buffer) {
+                        Some(length) if length >= min_length && length <= self.max_length as i64 => length,
+
+                        // out of range, so this was not the start of a packet
+                        Some(_) => {
+                            self.discard(1);
+                            continue;
+                        },
+
+                        None => return None,
+                    }
+                },
+            } as usize;
+
+            if self.buffer.len() < length {
+                return None;
+            }
+
+            return Some(self.buffer.drain(..length).collect());
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+// position of the first marker, or of the start of the buffer for an empty marker
+fn find_marker(bytes: &[u8], marker: &[u8]) -> Option<usize> {
+    if marker.is_empty() {
+        return Some(0);
+    }
+
+    bytes.windows(marker.len()).position(|window| window == marker)
+}
+
+// Read packets from a byte stream, such as a file, socket, or serial port,
+// by running the bytes through a framer.
+pub struct FrameReader<R, F> {
+    reader: R,
+    framer: F,
+    chunk: Vec<u8>,
+    error: Option<io::Error>,
+}
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+impl<R : Read, F : Framer> FrameReader<R, F> {
+    pub fn new(reader: R, framer: F) -> FrameReader<R, F> {
+        FrameReader { reader, framer, chunk: vec![0; READ_CHUNK_SIZE], error: None }
+    }
+
+    pub fn framer(&self) -> &F {
+        &self.framer
+    }
+
+    pub fn framer_mut(&mut self) -> &mut F {
+        &mut self.framer
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    // the error that ended the stream, if it did not end cleanly
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+impl<R : Read, F : Framer> Iterator for FrameReader<R, F> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(packet) = self.framer.next_packet() {
+                return Some(packet);
+            }
+
+            match self.reader.read(&mut self.chunk) {
+                // end of stream. any partial packet is left in the framer.
+                Ok(0) => return None,
+
+                Ok(num_bytes) => self.framer.push(&self.chunk[..num_bytes]),
+
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+
+                Err(err) => {
+                    self.error = Some(err);
+                    return None;
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_framing {
+    use super::*;
+
+    // a reader that returns its bytes a few at a time
+    struct Trickle {
+        bytes: Vec<u8>,
+        position: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let num_bytes = 3.min(buf.len()).min(self.bytes.len() - self.position);
+            buf[..num_bytes].copy_from_slice(&self.bytes[self.position .. self.position + num_bytes]);
+            self.position += num_bytes;
+            Ok(num_bytes)
+        }
+    }
+
+    #[test]
+    fn test_fixed_frame_reader() {
+        let reader = Trickle { bytes: (0..10).collect(), position: 0 };
+
+        let mut frames = FrameReader::new(reader, FixedFramer::new(4));
+        assert_eq!(frames.next(), Some(vec![0, 1, 2, 3]));
+        assert_eq!(frames.next(), Some(vec![4, 5, 6, 7]));
+        assert_eq!(frames.next(), None);
+        assert_eq!(frames.framer().buffered(), 2);
+    }
+
+    #[test]
+    fn test_sync_framer() {
+        // garbage, a packet split across reads, stray bytes including a partial marker,
+        // a marker with a length too short to be a packet, and a second packet
+        let bytes = vec![0x00, 0x1A, 0xCF, 0x05, 0x01,
+                         0x02, 0xFF, 0x1A, 0x1A, 0xCF,
+                         0x01, 0x1A, 0xCF, 0x04, 0x04];
+        let field = LengthField::new(2, IntPrim::u8_be(), 0);
+        let framer = SyncFramer::new(vec![0x1A, 0xCF], FrameLength::Field(field));
+
+        let mut frames = FrameReader::new(Trickle { bytes, position: 0 }, framer);
+        assert_eq!(frames.next(), Some(vec![0x1A, 0xCF, 0x05, 0x01, 0x02]));
+        assert_eq!(frames.next(), Some(vec![0x1A, 0xCF, 0x04, 0x04]));
+        assert_eq!(frames.next(), None);
+        assert_eq!(frames.framer().discarded(), 1 + 2 + 3);
+
+        // CCSDS style length prefix, where the field is the data length minus one
+        let mut framer = SyncFramer::length_prefixed(LengthField::new(4, IntPrim::u16_be(), 7));
+        framer.push(&[0x08, 0x01, 0xC0, 0x00, 0x00, 0x01, 0xAA]);
+        assert_eq!(framer.next_packet(), None);
+        framer.push(&[0xBB, 0x08]);
+        assert_eq!(framer.next_packet(), Some(vec![0x08, 0x01, 0xC0, 0x00, 0x00, 0x01, 0xAA, 0xBB]));
+        assert_eq!(framer.buffered(), 1);
+    }
+}
@@ -0,0 +1,347 @@
+extern crate ron;
+extern crate fnv;
+
+use std::hash::Hasher;
+
+use serde::Serialize;
+
+use self::fnv::FnvHasher;
+
+use types::*;
+use prim::*;
+use layout::*;
+use value::*;
+use packet::*;
+use loclayout::*;
+
+
+// A content hash for definitions, so the hash can be stored alongside data and
+// compared against the definitions in use when the data is read back.
+//
+// This does not use the standard Hash trait, whose output depends on the platform's
+// integer sizes and endianness. Instead each definition writes a tag for each variant,
+// and lengths and numbers as little endian u64s, into an FNV-1a hash. Enum maps are
+// BTreeMaps, so their entries are always visited in order.
+pub trait DefHash {
+    fn def_hash(&self, hasher: &mut FnvHasher);
+}
+
+pub fn definition_hash<T : DefHash>(definition : &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+
+    definition.def_hash(&mut hasher);
+
+    hasher.finish()
+}
+
+pub fn definition_ron<T : Serialize>(definition : &T) -> String {
+    ron::ser::to_string(definition).unwrap()
+}
+
+fn hash_u64(value: u64, hasher: &mut FnvHasher) {
+    hasher.write(&value.to_le_bytes());
+}
+
+fn hash_tag(tag: u8, hasher: &mut FnvHasher) {
+    hasher.write(&[tag]);
+}
+
+impl DefHash for str {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        hash_u64(self.len() as u64, hasher);
+        hasher.write(self.as_bytes());
+    }
+}
+
+impl DefHash for String {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        self.as_str().def_hash(hasher);
+    }
+}
+
+impl<T : DefHash> DefHash for Vec<T> {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        hash_u64(self.len() as u64, hasher);
+        for elem in self.iter() {
+            elem.def_hash(hasher);
+        }
+    }
+}
+
+impl DefHash for Endianness {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            Endianness::BigEndian    => hash_tag(0, hasher),
+            Endianness::LittleEndian => hash_tag(1, hasher),
+        }
+    }
+}
+
+impl DefHash for IntSize {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            IntSize::Bits8  => hash_tag(0, hasher),
+            IntSize::Bits16 => hash_tag(1, hasher),
+            IntSize::Bits32 => hash_tag(2, hasher),
+            IntSize::Bits64 => hash_tag(3, hasher),
+        }
+    }
+}
+
+impl DefHash for Signedness {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            Signedness::Unsigned => hash_tag(0, hasher),
+            Signedness::Signed   => hash_tag(1, hasher),
+        }
+    }
+}
+
+impl DefHash for IntPrim {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        self.size.def_hash(hasher);
+        self.signedness.def_hash(hasher);
+        self.endianness.def_hash(hasher);
+    }
+}
+
+impl DefHash for FloatPrim {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            FloatPrim::F32(endianness) => {
+                hash_tag(0, hasher);
+                endianness.def_hash(hasher);
+            },
+
+            FloatPrim::F64(endianness) => {
+                hash_tag(1, hasher);
+                endianness.def_hash(hasher);
+            },
+        }
+    }
+}
+
+impl DefHash for BitPrim {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        hash_u64(self.entries.len() as u64, hasher);
+        for (name, num_bits, int_prim) in self.entries.iter() {
+            name.def_hash(hasher);
+            hash_u64(*num_bits as u64, hasher);
+            int_prim.def_hash(hasher);
+        }
+        hash_u64(self.num_bytes, hasher);
+    }
+}
+
+impl DefHash for Enum {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        hash_u64(self.map.len() as u64, hasher);
+        for (value, name) in self.map.iter() {
+            hash_u64(*value as u64, hasher);
+            name.def_hash(hasher);
+        }
+        self.int_prim.def_hash(hasher);
+    }
+}
+
+impl DefHash for Prim {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            Prim::Int(int_prim) => {
+                hash_tag(0, hasher);
+                int_prim.def_hash(hasher);
+            },
+
+            Prim::Float(float_prim) => {
+                hash_tag(1, hasher);
+                float_prim.def_hash(hasher);
+            },
+
+            Prim::Enum(enum_prim) => {
+                hash_tag(2, hasher);
+                enum_prim.def_hash(hasher);
+            },
+        }
+    }
+}
+
+// conversions and limits do not change how data is decoded, so they are not hashed
+impl DefHash for Item {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        self.name.def_hash(hasher);
+        self.typ.def_hash(hasher);
+    }
+}
+
+impl DefHash for LocItem {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        self.name.def_hash(hasher);
+        self.typ.def_hash(hasher);
+        hash_u64(self.loc, hasher);
+    }
+}
+
+impl DefHash for LocLayout {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        self.loc_items.def_hash(hasher);
+    }
+}
+
+impl DefHash for ArrSize {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            ArrSize::Fixed(num_elements) => {
+                hash_tag(0, hasher);
+                hash_u64(*num_elements as u64, hasher);
+            },
+
+            ArrSize::Var(name) => {
+                hash_tag(1, hasher);
+                name.def_hash(hasher);
+            },
+        }
+    }
+}
+
+impl DefHash for Layout {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            Layout::Prim(item) => {
+                hash_tag(0, hasher);
+                item.def_hash(hasher);
+            },
+
+            Layout::Seq(name, layouts) => {
+                hash_tag(1, hasher);
+                name.def_hash(hasher);
+                layouts.def_hash(hasher);
+            },
+
+            Layout::All(name, layouts) => {
+                hash_tag(2, hasher);
+                name.def_hash(hasher);
+                layouts.def_hash(hasher);
+            },
+
+            Layout::Array(name, size, layout) => {
+                hash_tag(3, hasher);
+                name.def_hash(hasher);
+                hash_u64(*size, hasher);
+                layout.def_hash(hasher);
+            },
+
+            Layout::Bits(bit_prim) => {
+                hash_tag(4, hasher);
+                bit_prim.def_hash(hasher);
+            },
+        }
+    }
+}
+
+impl DefHash for Value {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            Value::U8(value)  => { hash_tag(0, hasher); hash_u64(*value as u64, hasher); },
+            Value::U16(value) => { hash_tag(1, hasher); hash_u64(*value as u64, hasher); },
+            Value::U32(value) => { hash_tag(2, hasher); hash_u64(*value as u64, hasher); },
+            Value::U64(value) => { hash_tag(3, hasher); hash_u64(*value, hasher); },
+            Value::I8(value)  => { hash_tag(4, hasher); hash_u64(*value as u64, hasher); },
+            Value::I16(value) => { hash_tag(5, hasher); hash_u64(*value as u64, hasher); },
+            Value::I32(value) => { hash_tag(6, hasher); hash_u64(*value as u64, hasher); },
+            Value::I64(value) => { hash_tag(7, hasher); hash_u64(*value as u64, hasher); },
+            Value::F32(value) => { hash_tag(8, hasher); hash_u64(value.to_bits() as u64, hasher); },
+            Value::F64(value) => { hash_tag(9, hasher); hash_u64(value.to_bits(), hasher); },
+            Value::Enum(name, value) => {
+                hash_tag(10, hasher);
+                name.def_hash(hasher);
+                hash_u64(*value as u64, hasher);
+            },
+        }
+    }
+}
+
+impl DefHash for SubcomKey {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            SubcomKey::Value(value) => {
+                hash_tag(0, hasher);
+                value.def_hash(hasher);
+            },
+
+            SubcomKey::Range(low, high) => {
+                hash_tag(1, hasher);
+                low.def_hash(hasher);
+                high.def_hash(hasher);
+            },
+
+            SubcomKey::Any => hash_tag(2, hasher),
+        }
+    }
+}
+
+impl<T : DefHash> DefHash for PacketDef<T> {
+    fn def_hash(&self, hasher: &mut FnvHasher) {
+        match self {
+            PacketDef::Seq(name, packets) => {
+                hash_tag(0, hasher);
+                name.def_hash(hasher);
+                packets.def_hash(hasher);
+            },
+
+            PacketDef::Subcom(name, items, pairs, default) => {
+                hash_tag(1, hasher);
+                name.def_hash(hasher);
+                items.def_hash(hasher);
+                hash_u64(pairs.len() as u64, hasher);
+                for (keys, packet) in pairs.iter() {
+                    keys.def_hash(hasher);
+                    packet.def_hash(hasher);
+                }
+                match default {
+                    Some(packet) => {
+                        hash_tag(1, hasher);
+                        packet.def_hash(hasher);
+                    },
+                    None => hash_tag(0, hasher),
+                }
+            },
+
+            PacketDef::Array(name, size, packet) => {
+                hash_tag(2, hasher);
+                name.def_hash(hasher);
+                size.def_hash(hasher);
+                packet.def_hash(hasher);
+            },
+
+            PacketDef::Leaf(item) => {
+                hash_tag(3, hasher);
+                item.def_hash(hasher);
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_hash {
+    use super::*;
+    use *;
+
+    #[test]
+    fn test_definition_hash() {
+        let packet = |temp_name : &str| -> LayoutPacketDef {
+            seq("tlm".to_string(), vec!(u16_be("seq"), f32_le(temp_name)))
+        };
+
+        assert_eq!(definition_hash(&packet("temp")), definition_hash(&packet("temp")));
+        assert!(definition_hash(&packet("temp")) != definition_hash(&packet("temp2")));
+
+        // the same structure in a different position hashes differently
+        let left : LayoutPacketDef = seq("a".to_string(), vec!(seq("b".to_string(), vec!()), u8_be("c")));
+        let right : LayoutPacketDef = seq("a".to_string(), vec!(u8_be("c"), seq("b".to_string(), vec!())));
+        assert!(definition_hash(&left) != definition_hash(&right));
+
+        // the hash is recorded in files, so it must not change between builds or platforms
+        assert_eq!(definition_hash(&Prim::Int(IntPrim::u16_be())), 0x447b_fb7f_98e6_154a);
+    }
+}
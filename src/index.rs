@@ -0,0 +1,288 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::io;
+use std::io::{Cursor, Read, Write, Seek, SeekFrom};
+
+use self::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use types::*;
+use prim::*;
+use value::*;
+use decode::*;
+
+
+// A packet index is a side file for a raw capture, recording where each packet
+// is along with the items needed to find it again: an identifier, such as an
+// APID, a sequence count, and a time tag. The hash of the definitions used to
+// build the index is recorded so a change in definitions can be detected.
+//
+// The file is a header followed by fixed size entries, all little endian:
+//   magic "GWIX", version u16, definition hash u64, number of entries u64
+//   offset u64, length u32, flags u8, id u64, seq u64, time f64
+
+const INDEX_MAGIC: &[u8; 4] = b"GWIX";
+const INDEX_VERSION: u16 = 1;
+
+const HAS_ID: u8   = 0x01;
+const HAS_SEQ: u8  = 0x02;
+const HAS_TIME: u8 = 0x04;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct IndexEntry {
+    pub offset: u64,
+    pub length: u32,
+    pub id: Option<u64>,
+    pub seq: Option<u64>,
+    pub time: Option<f64>,
+}
+
+impl IndexEntry {
+    // The entry's packet, or None if it runs past the end of the capture, as it
+    // does when the index was built from another capture.
+    pub fn bytes<'a>(&self, capture: &'a [u8]) -> Option<&'a [u8]> {
+        let end = self.offset.checked_add(self.length as u64)?;
+        if end > capture.len() as u64 {
+            return None;
+        }
+
+        Some(&capture[self.offset as usize .. end as usize])
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct PacketIndex {
+    pub definition_hash: u64,
+    pub entries: Vec<IndexEntry>,
+}
+
+// An item to record in the index, masked to the bits of interest,
+// such as the 11 bit APID or 14 bit sequence count of a CCSDS header.
+// Items must be integers or enums, so floats are rejected.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct IndexItem {
+    pub loc_item: LocItem,
+    pub mask: u64,
+}
+
+impl IndexItem {
+    pub fn new(loc_item: LocItem) -> Result<IndexItem, String> {
+        IndexItem::masked(loc_item, !0)
+    }
+
+    pub fn masked(loc_item: LocItem, mask: u64) -> Result<IndexItem, String> {
+        match loc_item.typ {
+            Prim::Int(_) | Prim::Enum(_) => Ok(IndexItem { loc_item, mask }),
+            Prim::Float(_) => Err(format!("{} is a float, not an integer item", loc_item.name.join("."))),
+        }
+    }
+
+    pub fn decode(&self, bytes: &mut Cursor<&[u8]>) -> u64 {
+        let int = match decode_loc_item(&self.loc_item, bytes).val {
+            Value::Enum(_, int) => int,
+            value => value.value(),
+        };
+
+        (int as u64) & self.mask
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct IndexConfig {
+    pub id: Option<IndexItem>,
+    pub seq: Option<IndexItem>,
+    pub time: Option<LocItem>,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct IndexQuery {
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+    pub ids: Option<Vec<u64>>,
+}
+
+impl IndexQuery {
+    // Time bounds are inclusive. Entries without a time tag do not match
+    // a query with time bounds, and likewise for ids.
+    pub fn matches(&self, entry: &IndexEntry) -> bool {
+        if self.start_time.is_some() || self.end_time.is_some() {
+            match entry.time {
+                Some(time) => {
+                    if self.start_time.is_some_and(|start| time < start) ||
+                       self.end_time.is_some_and(|end| time > end) {
+                        return false;
+                    }
+                },
+
+                None => return false,
+            }
+        }
+
+        if let Some(ref ids) = self.ids {
+            match entry.id {
+                Some(id) => {
+                    if !ids.contains(&id) {
+                        return false;
+                    }
+                },
+
+                None => return false,
+            }
+        }
+
+        true
+    }
+}
+
+// Build an index from packets and their byte offsets within a capture.
+pub fn build_index<'a, I>(packets: I, config: &IndexConfig, definition_hash: u64) -> PacketIndex
+    where I: Iterator<Item=(u64, &'a [u8])> {
+    let mut entries = Vec::new();
+
+    for (offset, packet) in packets {
+        let mut bytes = Cursor::new(packet);
+
+        let id = config.id.as_ref().map(|item| item.decode(&mut bytes));
+        let seq = config.seq.as_ref().map(|item| item.decode(&mut bytes));
+        let time = config.time.as_ref().map(|loc_item| decode_loc_item(loc_item, &mut bytes).val.as_f64());
+
+        entries.push(IndexEntry {
+            offset,
+            length: packet.len() as u32,
+            id,
+            seq,
+            time,
+        });
+    }
+
+    PacketIndex { definition_hash, entries }
+}
+
+impl PacketIndex {
+    pub fn matches_definition(&self, definition_hash: u64) -> bool {
+        self.definition_hash == definition_hash
+    }
+
+    pub fn query(&self, query: &IndexQuery) -> Vec<&IndexEntry> {
+        self.entries.iter().filter(|entry| query.matches(entry)).collect()
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u16::<LittleEndian>(INDEX_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.definition_hash)?;
+        writer.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+
+        for entry in self.entries.iter() {
+            let mut flags = 0;
+            if entry.id.is_some()   { flags |= HAS_ID; }
+            if entry.seq.is_some()  { flags |= HAS_SEQ; }
+            if entry.time.is_some() { flags |= HAS_TIME; }
+
+            writer.write_u64::<LittleEndian>(entry.offset)?;
+            writer.write_u32::<LittleEndian>(entry.length)?;
+            writer.write_u8(flags)?;
+            writer.write_u64::<LittleEndian>(entry.id.unwrap_or(0))?;
+            writer.write_u64::<LittleEndian>(entry.seq.unwrap_or(0))?;
+            writer.write_f64::<LittleEndian>(entry.time.unwrap_or(0.0))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<PacketIndex> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gasworks index file"));
+        }
+
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version != INDEX_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("unsupported index version {}", version)));
+        }
+
+        let definition_hash = reader.read_u64::<LittleEndian>()?;
+        let num_entries = reader.read_u64::<LittleEndian>()?;
+
+        let mut entries = Vec::new();
+        for _ in 0 .. num_entries {
+            let offset = reader.read_u64::<LittleEndian>()?;
+            let length = reader.read_u32::<LittleEndian>()?;
+            let flags = reader.read_u8()?;
+            let id = reader.read_u64::<LittleEndian>()?;
+            let seq = reader.read_u64::<LittleEndian>()?;
+            let time = reader.read_f64::<LittleEndian>()?;
+
+            entries.push(IndexEntry {
+                offset,
+                length,
+                id:   if flags & HAS_ID != 0   { Some(id) }   else { None },
+                seq:  if flags & HAS_SEQ != 0  { Some(seq) }  else { None },
+                time: if flags & HAS_TIME != 0 { Some(time) } else { None },
+            });
+        }
+
+        Ok(PacketIndex { definition_hash, entries })
+    }
+}
+
+// Read the packets for the given entries from a capture, seeking to each one
+// rather then scanning the capture.
+pub fn extract_packets<R: Read + Seek>(reader: &mut R, entries: &[&IndexEntry]) -> io::Result<Vec<Vec<u8>>> {
+    let mut packets = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let mut packet = vec![0; entry.length as usize];
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        reader.read_exact(&mut packet)?;
+        packets.push(packet);
+    }
+
+    Ok(packets)
+}
+
+
+#[cfg(test)]
+mod test_index {
+    use super::*;
+
+    #[test]
+    fn test_index_query() {
+        let id_item = LocItem::new(vec!["apid".to_string()], Prim::Int(IntPrim::u16_be()), 0);
+        let time_item = LocItem::new(vec!["time".to_string()], Prim::Int(IntPrim::u32_be()), 2);
+        let float_item = LocItem::new(vec!["temp".to_string()], Prim::Float(FloatPrim::f32_be()), 2);
+        assert!(IndexItem::new(float_item).is_err());
+
+        let config = IndexConfig { id: Some(IndexItem::masked(id_item, 0x07FF).unwrap()),
+                                   seq: None,
+                                   time: Some(time_item) };
+
+        let capture = vec![0x08, 0x01, 0x00, 0x00, 0x00, 0x0A,
+                           0x08, 0x02, 0x00, 0x00, 0x00, 0x14,
+                           0x08, 0x01, 0x00, 0x00, 0x00, 0x1E];
+        let packets = capture.chunks(6).enumerate().map(|(index, packet)| ((index * 6) as u64, packet));
+        let index = build_index(packets, &config, 0x1234);
+
+        let mut file = Vec::new();
+        index.write(&mut file).unwrap();
+        let index = PacketIndex::read(&mut Cursor::new(file)).unwrap();
+        assert!(index.matches_definition(0x1234));
+
+        let query = IndexQuery { start_time: Some(15.0), end_time: None, ids: Some(vec![1]) };
+        let entries = index.query(&query);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].offset, 12);
+        assert_eq!(entries[0].seq, None);
+
+        let packets = extract_packets(&mut Cursor::new(&capture), &entries).unwrap();
+        assert_eq!(packets, vec![vec![0x08, 0x01, 0x00, 0x00, 0x00, 0x1E]]);
+    }
+}
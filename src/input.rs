@@ -0,0 +1,162 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pcap::*;
+
+
+// Where packets come from. Inputs are given as a file path, or as a url like
+// spec for live sources:
+//   udp://0.0.0.0:5000                  receive datagrams on a local address
+//   tcp://host:5000                     connect to a server sending packets
+//   tcp-listen://0.0.0.0:5000           accept connections sending packets
+//   serial:///dev/ttyUSB0?baud=115200   read from a serial port, at 115200 baud by default
+//   pcap://capture.pcapng?port=5000     read udp payloads from a network capture. options are
+//                                       protocol (udp or tcp), port, and host.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum InputSpec {
+    File(String),
+    Udp(String),
+    TcpClient(String),
+    TcpServer(String),
+    Serial(String, u32),
+    Pcap(String, PcapFilter),
+}
+
+const DEFAULT_BAUD: u32 = 115200;
+
+impl InputSpec {
+    pub fn parse(spec: &str) -> Result<InputSpec, String> {
+        let pos = match spec.find("://") {
+            Some(pos) => pos,
+            None => return Ok(InputSpec::File(spec.to_string())),
+        };
+
+        let scheme = &spec[..pos];
+        let (addr, options) = match spec[pos + 3..].find('?') {
+            Some(option_pos) => (&spec[pos + 3 .. pos + 3 + option_pos], &spec[pos + 4 + option_pos ..]),
+            None => (&spec[pos + 3..], ""),
+        };
+
+        if addr.is_empty() {
+            return Err(format!("no address given in input '{}'", spec));
+        }
+
+        let mut baud = DEFAULT_BAUD;
+        let mut filter = PcapFilter::default();
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.find('=') {
+                Some(eq_pos) => (&option[..eq_pos], &option[eq_pos + 1..]),
+                None => return Err(format!("expected key=value for option '{}' in input '{}'", option, spec)),
+            };
+            let invalid = || format!("invalid {} '{}' in input '{}'", key, value, spec);
+
+            match (scheme, key) {
+                ("serial", "baud") => baud = value.parse().map_err(|_| invalid())?,
+
+                ("pcap", "protocol") => {
+                    filter.protocol = match value {
+                        "udp" => Protocol::Udp,
+                        "tcp" => Protocol::Tcp,
+                        _ => return Err(invalid()),
+                    };
+                },
+
+                ("pcap", "port") => filter.port = Some(value.parse().map_err(|_| invalid())?),
+
+                ("pcap", "host") => filter.host = Some(value.parse().map_err(|_| invalid())?),
+
+                _ => return Err(format!("unknown option '{}' in input '{}'", option, spec)),
+            }
+        }
+
+        let addr = addr.to_string();
+        match scheme {
+            "file"       => Ok(InputSpec::File(addr)),
+            "udp"        => Ok(InputSpec::Udp(addr)),
+            "tcp"        => Ok(InputSpec::TcpClient(addr)),
+            "tcp-listen" => Ok(InputSpec::TcpServer(addr)),
+            "serial"     => Ok(InputSpec::Serial(addr, baud)),
+            "pcap"       => Ok(InputSpec::Pcap(addr, filter)),
+            _ => Err(format!("unknown input type '{}', expected udp, tcp, tcp-listen, serial, or pcap", scheme)),
+        }
+    }
+
+    pub fn is_live(&self) -> bool {
+        !matches!(self, InputSpec::File(_) | InputSpec::Pcap(..))
+    }
+}
+
+// A packet along with the time it was received, such as its capture timestamp,
+// in seconds since the unix epoch.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TimedPacket {
+    pub time: f64,
+    pub bytes: Vec<u8>,
+}
+
+impl AsRef<[u8]> for TimedPacket {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+// the current time in seconds since the unix epoch, for stamping live packets
+pub fn unix_time_now() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9,
+        Err(_) => 0.0,
+    }
+}
+
+// The receive time of a packet from a source, for sources that record one.
+pub trait ReceiveTime {
+    fn receive_time(&self) -> Option<f64>;
+}
+
+impl ReceiveTime for &[u8] {
+    fn receive_time(&self) -> Option<f64> {
+        None
+    }
+}
+
+impl ReceiveTime for Vec<u8> {
+    fn receive_time(&self) -> Option<f64> {
+        None
+    }
+}
+
+impl ReceiveTime for TimedPacket {
+    fn receive_time(&self) -> Option<f64> {
+        Some(self.time)
+    }
+}
+
+
+#[cfg(test)]
+mod test_input {
+    use super::*;
+
+    #[test]
+    fn test_input_spec() {
+        assert_eq!(InputSpec::parse("data/capture.bin"), Ok(InputSpec::File("data/capture.bin".to_string())));
+        assert_eq!(InputSpec::parse("udp://0.0.0.0:5000"), Ok(InputSpec::Udp("0.0.0.0:5000".to_string())));
+        assert_eq!(InputSpec::parse("tcp://host:5000"), Ok(InputSpec::TcpClient("host:5000".to_string())));
+        assert_eq!(InputSpec::parse("tcp-listen://0.0.0.0:5000"), Ok(InputSpec::TcpServer("0.0.0.0:5000".to_string())));
+        assert_eq!(InputSpec::parse("serial:///dev/ttyUSB0"), Ok(InputSpec::Serial("/dev/ttyUSB0".to_string(), 115200)));
+        assert_eq!(InputSpec::parse("serial:///dev/ttyS1?baud=9600"), Ok(InputSpec::Serial("/dev/ttyS1".to_string(), 9600)));
+        assert!(InputSpec::parse("udp://0.0.0.0:5000?baud=9600").is_err());
+
+        let filter = PcapFilter { protocol: Protocol::Tcp, port: Some(7000), host: Some("10.0.0.2".parse().unwrap()) };
+        assert_eq!(InputSpec::parse("pcap://test.pcap?protocol=tcp&port=7000&host=10.0.0.2"),
+                   Ok(InputSpec::Pcap("test.pcap".to_string(), filter)));
+        assert!(InputSpec::parse("pcap://test.pcap?port=http").is_err());
+        assert!(InputSpec::parse("ftp://host").is_err());
+        assert!(InputSpec::parse("udp://").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate serde_json;
+
+use std::io::Write;
+
+use self::serde_json::{Map, Number};
+use self::serde_json::Value as Json;
+
+use value::*;
+
+
+// JSON Lines output keeps the structure of a decoded packet: sections
+// become objects, arrays become lists of objects, and enums are written
+// with their names.
+// NOTE serde_json is built without its preserve_order feature, so the keys of
+// each object are written in alphabetical order rather then definition order.
+
+pub fn value_json(value : &Value) -> Json {
+    match value {
+        Value::U8(value)  => Json::from(*value),
+        Value::U16(value) => Json::from(*value),
+        Value::U32(value) => Json::from(*value),
+        Value::U64(value) => Json::from(*value),
+        Value::I8(value)  => Json::from(*value),
+        Value::I16(value) => Json::from(*value),
+        Value::I32(value) => Json::from(*value),
+        Value::I64(value) => Json::from(*value),
+        // NOTE NaN and infinity have no JSON representation, so they become null
+        Value::F32(value) => Number::from_f64(*value as f64).map_or(Json::Null, Json::Number),
+        Value::F64(value) => Number::from_f64(*value).map_or(Json::Null, Json::Number),
+        Value::Enum(name, _) => Json::String(name.clone()),
+    }
+}
+
+pub fn valuemap_json(map : &ValueMap) -> Json {
+    let mut object = Map::new();
+
+    for (name, value_entry) in map.value_map.iter() {
+        let json = match value_entry {
+            ValueEntry::Leaf(value) => value_json(value),
+
+            ValueEntry::Section(value_map) => valuemap_json(value_map),
+
+            ValueEntry::Array(array) => {
+                Json::Array(array.iter().map(valuemap_json).collect())
+            },
+        };
+
+        object.insert(name.clone(), json);
+    }
+
+    Json::Object(object)
+}
+
+// Write a decoded packet as a single line of JSON. The packet name, timestamp,
+// UTC time tag, and time correlation fields are only included when provided.
+pub fn valuemap_jsonl(map         : &ValueMap,
+                      packet_name : Option<&str>,
+                      timestamp   : Option<f64>,
+                      utc         : Option<&str>,
+                      correlation : Option<&str>,
+                      line        : &mut String) {
+    let mut json = valuemap_json(map);
+
+    if let Json::Object(ref mut object) = json {
+        if let Some(packet_name) = packet_name {
+            object.insert("packet".to_string(), Json::String(packet_name.to_string()));
+        }
+
+        if let Some(timestamp) = timestamp {
+            object.insert("timestamp".to_string(),
+                          Number::from_f64(timestamp).map_or(Json::Null, Json::Number));
+        }
+
+        if let Some(utc) = utc {
+            object.insert("utc".to_string(), Json::String(utc.to_string()));
+        }
+
+        if let Some(correlation) = correlation {
+            object.insert("correlation".to_string(), Json::String(correlation.to_string()));
+        }
+    }
+
+    line.clear();
+    line.push_str(&json.to_string());
+    line.push('\n');
+}
+
+pub fn write_valuemap_jsonl<W : Write>(map         : &ValueMap,
+                                       packet_name : Option<&str>,
+                                       timestamp   : Option<f64>,
+                                       utc         : Option<&str>,
+                                       correlation : Option<&str>,
+                                       writer      : &mut W) -> std::io::Result<()> {
+    let mut line = String::new();
+
+    valuemap_jsonl(map, packet_name, timestamp, utc, correlation, &mut line);
+
+    writer.write_all(line.as_bytes())
+}
+
+
+#[cfg(test)]
+mod test_jsonl {
+    use super::*;
+    use std::io::Cursor;
+    use decode::*;
+    use packet::*;
+    use *;
+
+    #[test]
+    fn test_jsonl_structure() {
+        let mut enum_map = BTreeMap::new();
+        enum_map.insert(0, "Off".to_string());
+        enum_map.insert(1, "On".to_string());
+        let mode = item("mode", Prim::Enum(Enum{map : enum_map, int_prim : IntPrim::u8_be()}));
+
+        let packet : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec!(seq("header".to_string(), vec!(u8_be("count"), leaf(mode))),
+                     array_var("samples".to_string(), "count".to_string(), u16_be("sample"))));
+
+        let v = vec![0x02, 0x01, 0x00, 0x10, 0x00, 0x20];
+        let map = decode_layoutpacket(&packet, &mut Cursor::new(v.as_slice())).unwrap();
+
+        let mut line = String::new();
+        valuemap_jsonl(&map, Some("tlm"), Some(1.5), Some("2017-01-01T00:00:00.000000Z"), None, &mut line);
+
+        assert_eq!(line,
+                   "{\"packet\":\"tlm\",\"timestamp\":1.5,\"tlm\":{\"header\":{\"count\":2,\"mode\":\"On\"},\
+                    \"samples\":[{\"sample\":16},{\"sample\":32}]},\"utc\":\"2017-01-01T00:00:00.000000Z\"}\n");
+    }
+}
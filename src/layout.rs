@@ -69,13 +69,14 @@ impl NumBytes for Layout {
 
 impl Layout {
   // NOTE the section/all/array name is not inserted here, only
-  // primitives get added.
-  pub fn names(&self) -> HashSet<&Name> {
-    let mut names: HashSet<&Name> = HashSet::new();
+  // primitives get added. Names are given in definition order,
+  // keeping the first occurrence of each name.
+  pub fn names(&self) -> Vec<&Name> {
+    let mut names: Vec<&Name> = Vec::new();
 
     match self {
-      Layout::Prim(Item{name, typ: _}) => {
-        names.insert(name);
+      Layout::Prim(Item{name, ..}) => {
+        names.push(name);
       }
 
       Layout::Seq(_, layouts) => {
@@ -96,14 +97,65 @@ impl Layout {
 
       Layout::Bits(bit_prims) => {
         for bit_prim in bit_prims.entries.iter() {
-          names.insert(&bit_prim.0);
+          names.push(&bit_prim.0);
         }
       },
     }
 
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(*name));
+
     names
   }
 
+  // The full path to each primitive, in definition order. The paths follow
+  // the structure of the ValueMap produced by decoding this layout, with
+  // array elements named "name[index]".
+  pub fn paths(&self) -> Vec<LocPath> {
+    let mut paths = Vec::new();
+    let path = Vec::new();
+    self.paths_helper(&path, &mut paths);
+
+    paths
+  }
+
+  fn paths_helper(&self, path: &LocPath, paths: &mut Vec<LocPath>) {
+    match self {
+      Layout::Prim(item) => {
+        let mut item_path = path.to_vec();
+        item_path.push(item.name.to_string());
+        paths.push(item_path);
+      }
+
+      Layout::Seq(name, layouts) | Layout::All(name, layouts) => {
+        let mut seq_path = path.to_vec();
+        seq_path.push(name.to_string());
+
+        for layout in layouts.iter() {
+          layout.paths_helper(&seq_path, paths);
+        }
+      },
+
+      Layout::Array(name, size, layout) => {
+        for index in 0 .. *size {
+          let mut array_path = path.to_vec();
+          array_path.push(format!("{}[{}]", name, index));
+
+          layout.paths_helper(&array_path, paths);
+        }
+      }
+
+      // bit fields are decoded into the enclosing section
+      Layout::Bits(bit_prims) => {
+        for bit_prim in bit_prims.entries.iter() {
+          let mut bits_path = path.to_vec();
+          bits_path.push(bit_prim.0.to_string());
+          paths.push(bits_path);
+        }
+      },
+    }
+  }
+
   pub fn locate(&self) -> LocLayout {
     let mut loc = 0;
     let mut loc_items = Vec::new();
@@ -5,6 +5,7 @@ extern crate bytes;
 extern crate byteorder;
 extern crate ron;
 extern crate fnv;
+#[macro_use] extern crate crossbeam_channel;
 
 #[allow(unused_imports)]
 use std::collections::HashMap;
@@ -43,8 +44,59 @@ use packet::*;
 pub mod loclayout;
 use loclayout::*;
 
+pub mod validate;
+
+pub mod report;
+
 pub mod csv;
 
+pub mod jsonl;
+
+pub mod hash;
+
+pub mod registry;
+
+pub mod sqlite;
+
+pub mod index;
+
+pub mod ccsds;
+
+pub mod timetag;
+
+pub mod correlation;
+
+pub mod tmframe;
+
+pub mod segment;
+
+pub mod continuity;
+
+pub mod commutation;
+
+pub mod framing;
+
+pub mod stuffing;
+
+pub mod net;
+
+pub mod input;
+
+pub mod sink;
+
+pub mod follow;
+
+pub mod pcap;
+
+pub mod capture;
+
+pub mod replay;
+
+pub mod merge;
+
+#[cfg(unix)]
+pub mod serial;
+
 
 /* Convienence functions for creating data definitions.  */
 // Creating Items
@@ -104,77 +156,100 @@ pub fn array_var<T>(name : Name, var_name : Name, packet : PacketDef<T>) -> Pack
     PacketDef::Array(name, ArrSize::Var(var_name), Box::new(packet))
 }
 
-pub fn identify_locpacket(packet : &LocPacketDef, bytes : &mut Cursor<&[u8]>) -> LocLayout {
+// Find the items present in a packet, following the subcom branches and array
+// sizes given by its values. Subcom items with values matching none of the
+// subcom's branches are an error, as are array counts that are missing, not
+// whole numbers, or too large for the packet.
+pub fn identify_locpacket(packet : &LocPacketDef, bytes : &mut Cursor<&[u8]>) -> Result<LocLayout, String> {
     let locs = Vec::new();
 
     let mut loc_layout = LocLayout{ loc_items : locs};
 
-    identify_locpacket_helper(packet, bytes, &mut loc_layout);
+    identify_locpacket_helper(packet, bytes, &mut loc_layout)?;
 
-    loc_layout
+    Ok(loc_layout)
 }
 
 fn identify_locpacket_helper(packet : &LocPacketDef, 
                              bytes : &mut Cursor<&[u8]>,
-                             loc_layout : &mut LocLayout) {
+                             loc_layout : &mut LocLayout) -> Result<(), String> {
     match packet {
         PacketDef::Seq(name, packets) => {
             for packet in packets {
-                identify_locpacket_helper(packet, bytes, loc_layout);
+                identify_locpacket_helper(packet, bytes, loc_layout)?;
             }
         },
 
-        PacketDef::Subcom(name, item, subcom) => {
+        PacketDef::Subcom(name, items, branches, default) => {
             // NOTE we are decoding items here and throwing them away. the assumption is that
             // we don't decode many items, and don't need to keep our work.
-            // we re-decode items even if they are used in other iterations of this loop!
-            let value = decode_loc_item(&item, bytes);
-
-            for (item_key, packet_value) in subcom {
-                let item_value = decode_loc_item(item_key, bytes);
+            let values: Vec<Value> = items.iter().map(|item| decode_loc_item(item, bytes).val).collect();
 
-                if value == item_value {
-                    identify_locpacket_helper(packet_value, bytes, loc_layout);
-                    break;
-                }
-            }
+            let packet = select_branch(name, branches, default, &values)?;
+            identify_locpacket_helper(packet, bytes, loc_layout)?;
         },
 
         // NOTE an optimization here would be to use a hashmap, or
         // to keep only values used in decisions, determined beforehand.
         PacketDef::Array(name, size, packet) => {
-            let mut num_elements : usize = 0;
-            match size {
-                ArrSize::Fixed(num) =>
-                    num_elements = *num,
-
-                ArrSize::Var(name)  => {
-                    for elem in loc_layout.loc_items.iter() {
-                        // NOTE matches the first item with the name
-                        // might need better matching
-                        if name == &elem.name[elem.name.len() - 1] {
-                            let point = decode_loc_item(&elem, bytes);
-                            num_elements = point.val.value() as usize;
-                            break;
-                        }
-                    }
+            let num_elements = match size {
+                ArrSize::Fixed(num) => *num,
+
+                ArrSize::Var(var_name)  => {
+                    // NOTE matches the first item with the name
+                    // might need better matching
+                    let count = loc_layout.loc_items.iter()
+                                          .find(|elem| var_name == &elem.name[elem.name.len() - 1])
+                                          .map(|elem| decode_loc_item(elem, bytes).val);
+
+                    // items are located from the start of the packet, so elements are
+                    // checked against the whole packet
+                    array_count(name, var_name, count, loc_span(packet), bytes.get_ref().len() as u64)?
                 }
-            }
+            };
 
-            // NOTE If Var, and name is not found, the array will be skipped
             for _ in 0..num_elements {
-                identify_locpacket_helper(packet, bytes, loc_layout);
+                identify_locpacket_helper(packet, bytes, loc_layout)?;
             }
         }
 
         PacketDef::Leaf(layer_loc_layout) => {
-         }
- 
-         PacketDef::Leaf(layer_loc_layout) => {
             // NOTE use of clone
             loc_layout.loc_items.push(layer_loc_layout.clone());
         },
     }
+
+    Ok(())
+}
+
+// The bytes spanned by the items always present in a located packet, leaving out
+// variable arrays and subcom branches.
+fn loc_span(packet : &LocPacketDef) -> u64 {
+    let mut start = u64::MAX;
+    let mut end = 0;
+
+    loc_span_helper(packet, &mut start, &mut end);
+
+    end.saturating_sub(start)
+}
+
+fn loc_span_helper(packet : &LocPacketDef, start : &mut u64, end : &mut u64) {
+    match packet {
+        PacketDef::Seq(_, packets) => {
+            for packet in packets {
+                loc_span_helper(packet, start, end);
+            }
+        },
+
+        PacketDef::Array(_, ArrSize::Fixed(num), packet) if *num > 0 => loc_span_helper(packet, start, end),
+
+        PacketDef::Leaf(loc_item) => {
+            *start = (*start).min(loc_item.loc);
+            *end = (*end).max(loc_item.loc + loc_item.num_bytes());
+        },
+
+        _ => (),
+    }
 }
 
 // Find all locations in a packet where a choice is made based
@@ -198,11 +273,16 @@ pub fn choice_points_helper(packet: &LayoutPacketDef, map: &mut ChoicePoints) {
             }
         },
 
-        PacketDef::Subcom(name, item, subcom) => {
-            map.insert(item.name.clone(), None);
+        PacketDef::Subcom(name, items, subcom, default) => {
+            for item in items {
+                map.insert(item.name.clone(), None);
+            }
             for pair in subcom {
                 choice_points_helper(&pair.1, map);
             }
+            if let Some(packet) = default {
+                choice_points_helper(packet, map);
+            }
         },
 
         PacketDef::Array(name, size, packet) => {
@@ -0,0 +1,211 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate revord;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::hash_map::Entry;
+
+use self::revord::RevOrd;
+
+use input::*;
+
+
+// Merge packets from several inputs, such as captures from recorders covering
+// overlapping periods, into one stream ordered by time tag. Each input is expected
+// to be in time order already, so only the next packet of each input is held,
+// and the earliest of those is taken each time. Packets with equal time tags keep
+// the order of their inputs.
+//
+// A packet with the same time tag, sequence count, and bytes as one already merged
+// from another input is a duplicate, such as the same packet received by two recorders,
+// and is dropped. Comparing the bytes keeps packets from different streams, such as
+// other APIDs with the same sequence count, and packets without a sequence count from
+// being taken as duplicates by their time tag alone. Packets from the same input are
+// never duplicates of each other.
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct MergePacket {
+    pub time: f64,
+    pub seq: Option<u64>,
+    // the index of the input the packet came from
+    pub input: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl AsRef<[u8]> for MergePacket {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl ReceiveTime for MergePacket {
+    fn receive_time(&self) -> Option<f64> {
+        Some(self.time)
+    }
+}
+
+// the order packets are merged in: by time tag, then by input
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct MergeKey {
+    time: f64,
+    input: usize,
+}
+
+impl Eq for MergeKey {}
+
+impl PartialOrd for MergeKey {
+    fn partial_cmp(&self, other: &MergeKey) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergeKey {
+    fn cmp(&self, other: &MergeKey) -> Ordering {
+        self.time.total_cmp(&other.time).then(self.input.cmp(&other.input))
+    }
+}
+
+pub struct Merge<I> {
+    inputs: Vec<I>,
+    // the next packet of each input that has one
+    heads: Vec<Option<MergePacket>>,
+    queue: BinaryHeap<RevOrd<MergeKey>>,
+    // the sequence counts and bytes merged at the latest time tag, with the input each came from
+    latest: Option<f64>,
+    seen: HashMap<(Option<u64>, Vec<u8>), usize>,
+    duplicates: u64,
+    out_of_order: u64,
+}
+
+impl<I> Merge<I>
+    where I: Iterator<Item=MergePacket> {
+    pub fn new(inputs: Vec<I>) -> Merge<I> {
+        let heads = inputs.iter().map(|_| None).collect();
+        let mut merge = Merge { inputs,
+                                heads,
+                                queue: BinaryHeap::new(),
+                                latest: None,
+                                seen: HashMap::new(),
+                                duplicates: 0,
+                                out_of_order: 0 };
+
+        for input in 0..merge.inputs.len() {
+            merge.advance(input);
+        }
+
+        merge
+    }
+
+    // the number of duplicate packets dropped
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    // The number of packets timed before a packet merged ahead of them, because their
+    // input was not in time order. They are passed on as they are found.
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    // queue the next packet from an input
+    fn advance(&mut self, input: usize) {
+        if let Some(mut packet) = self.inputs[input].next() {
+            packet.input = input;
+            self.queue.push(RevOrd(MergeKey { time: packet.time, input }));
+            self.heads[input] = Some(packet);
+        }
+    }
+}
+
+impl<I> Iterator for Merge<I>
+    where I: Iterator<Item=MergePacket> {
+    type Item = MergePacket;
+
+    fn next(&mut self) -> Option<MergePacket> {
+        loop {
+            let RevOrd(key) = self.queue.pop()?;
+            let packet = self.heads[key.input].take().unwrap();
+            self.advance(key.input);
+
+            match self.latest {
+                Some(latest) if packet.time == latest => {
+                    match self.seen.entry((packet.seq, packet.bytes.clone())) {
+                        Entry::Occupied(entry) => {
+                            if *entry.get() != packet.input {
+                                self.duplicates += 1;
+                                continue;
+                            }
+                        },
+
+                        Entry::Vacant(entry) => {
+                            entry.insert(packet.input);
+                        },
+                    }
+                },
+
+                Some(latest) if packet.time < latest => {
+                    self.out_of_order += 1;
+                },
+
+                _ => {
+                    self.latest = Some(packet.time);
+                    self.seen.clear();
+                    self.seen.insert((packet.seq, packet.bytes.clone()), packet.input);
+                },
+            }
+
+            return Some(packet);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_merge {
+    use super::*;
+
+    fn packet(time: f64, seq: u64, byte: u8) -> MergePacket {
+        MergePacket { time, seq: Some(seq), input: 0, bytes: vec![byte] }
+    }
+
+    #[test]
+    fn test_merge_inputs() {
+        let first = vec![packet(1.0, 1, 1), packet(3.0, 3, 3), packet(4.0, 4, 4), packet(6.0, 6, 6)];
+        let second = vec![packet(2.0, 2, 2), packet(3.0, 3, 3), packet(3.0, 9, 9), packet(5.0, 5, 5)];
+        let third = vec![packet(0.5, 0, 0), packet(4.0, 4, 4)];
+
+        let mut merge = Merge::new(vec![first.into_iter(), second.into_iter(), third.into_iter()]);
+        let merged: Vec<(u8, usize)> = merge.by_ref().map(|packet| (packet.bytes[0], packet.input)).collect();
+
+        assert_eq!(merged, vec![(0, 2), (1, 0), (2, 1), (3, 0), (9, 1), (4, 0), (5, 1), (6, 0)]);
+        assert_eq!(merge.duplicates(), 2);
+        assert_eq!(merge.out_of_order(), 0);
+
+        // an input out of time order is passed through and counted
+        let mut merge = Merge::new(vec![vec![packet(2.0, 2, 2), packet(1.0, 1, 1)].into_iter()]);
+        assert_eq!(merge.by_ref().count(), 2);
+        assert_eq!(merge.out_of_order(), 1);
+
+        // packets with the same time tag from one input are all kept, even without sequence counts,
+        // and packets from other inputs are only duplicates if their bytes match
+        let untagged = |byte: u8| MergePacket { time: 1.0, seq: None, input: 0, bytes: vec![byte] };
+        let mut merge = Merge::new(vec![vec![untagged(1), untagged(2)].into_iter(),
+                                        vec![untagged(1), untagged(3)].into_iter()]);
+        let merged: Vec<(u8, usize)> = merge.by_ref().map(|packet| (packet.bytes[0], packet.input)).collect();
+        assert_eq!(merged, vec![(1, 0), (2, 0), (3, 1)]);
+        assert_eq!(merge.duplicates(), 1);
+
+        // packets of other streams that share a time tag and sequence count are kept
+        let apid = |apid: u8| MergePacket { time: 1.0, seq: Some(7), input: 0, bytes: vec![apid, 7] };
+        let mut merge = Merge::new(vec![vec![apid(0x10)].into_iter(), vec![apid(0x11), apid(0x10)].into_iter()]);
+        let merged: Vec<(u8, usize)> = merge.by_ref().map(|packet| (packet.bytes[0], packet.input)).collect();
+        assert_eq!(merged, vec![(0x10, 0), (0x11, 1)]);
+        assert_eq!(merge.duplicates(), 1);
+    }
+}
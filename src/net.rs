@@ -0,0 +1,160 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::io;
+use std::net::{UdpSocket, TcpListener, TcpStream, SocketAddr, ToSocketAddrs};
+
+use framing::*;
+
+
+// Network packet sources. UDP datagrams are each taken as a single packet,
+// while TCP streams are split into packets with a framer.
+
+// largest possible UDP payload
+const MAX_DATAGRAM: usize = 65535;
+
+pub struct UdpSource {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl UdpSource {
+    pub fn bind<A : ToSocketAddrs>(addr: A) -> io::Result<UdpSource> {
+        Ok(UdpSource::from_socket(UdpSocket::bind(addr)?))
+    }
+
+    pub fn from_socket(socket: UdpSocket) -> UdpSource {
+        UdpSource { socket, buffer: vec![0; MAX_DATAGRAM] }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Receive the next datagram, along with the address it was sent from.
+    pub fn recv(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
+        let (num_bytes, addr) = self.socket.recv_from(&mut self.buffer)?;
+        Ok((self.buffer[..num_bytes].to_vec(), addr))
+    }
+}
+
+impl Iterator for UdpSource {
+    type Item = Vec<u8>;
+
+    // NOTE the stream ends on a socket error, such as a read timeout
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.recv() {
+                Ok((packet, _)) => return Some(packet),
+
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+// Connect to a TCP server and frame the packets it sends.
+pub fn tcp_client<A : ToSocketAddrs, F : Framer>(addr: A, framer: F) -> io::Result<FrameReader<TcpStream, F>> {
+    Ok(FrameReader::new(TcpStream::connect(addr)?, framer))
+}
+
+// Accept TCP connections one at a time, framing the packets from each
+// connection until it closes and then waiting for the next one.
+pub struct TcpServerSource<F> {
+    listener: TcpListener,
+    framer: F,
+    connection: Option<FrameReader<TcpStream, F>>,
+}
+
+impl<F : Framer + Clone> TcpServerSource<F> {
+    pub fn bind<A : ToSocketAddrs>(addr: A, framer: F) -> io::Result<TcpServerSource<F>> {
+        Ok(TcpServerSource { listener: TcpListener::bind(addr)?, framer, connection: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl<F : Framer + Clone> Iterator for TcpServerSource<F> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(ref mut connection) = self.connection {
+                if let Some(packet) = connection.next() {
+                    return Some(packet);
+                }
+            }
+
+            // the current connection closed, so start fresh with the next one
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let mut framer = self.framer.clone();
+                    framer.clear();
+                    self.connection = Some(FrameReader::new(stream, framer));
+                },
+
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_net {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn test_udp_source() {
+        let mut source = UdpSource::bind("127.0.0.1:0").unwrap();
+        let addr = source.local_addr().unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&[1, 2, 3], addr).unwrap();
+        sender.send_to(&[4, 5], addr).unwrap();
+
+        assert_eq!(source.next(), Some(vec![1, 2, 3]));
+        assert_eq!(source.next(), Some(vec![4, 5]));
+    }
+
+    #[test]
+    fn test_tcp_sources() {
+        let mut server = TcpServerSource::bind("127.0.0.1:0", FixedFramer::new(4)).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            // packets are split across writes
+            stream.write_all(&[0, 1, 2]).unwrap();
+            stream.flush().unwrap();
+            stream.write_all(&[3, 4, 5, 6, 7, 8]).unwrap();
+        });
+
+        assert_eq!(server.next(), Some(vec![0, 1, 2, 3]));
+        assert_eq!(server.next(), Some(vec![4, 5, 6, 7]));
+        client.join().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[9, 8, 7, 6, 5]).unwrap();
+        });
+
+        let packets: Vec<Vec<u8>> = tcp_client(addr, FixedFramer::new(2)).unwrap().collect();
+        assert_eq!(packets, vec![vec![9, 8], vec![7, 6]]);
+        server.join().unwrap();
+    }
+}
@@ -11,6 +11,7 @@ use std::collections::BTreeMap;
 use std::option;
 
 use std::cmp;
+use std::io::Cursor;
 
 
 #[allow(unused_imports)]
@@ -18,21 +19,29 @@ use self::bytes::{Bytes, Buf};
 
 use prim::*;
 use types::*;
+use value::*;
 use loclayout::*;
+use decode::*;
 
 
+// Split a capture of packets laid end to end. Packets of a fixed size are taken
+// that many bytes at a time, and variable sized packets are measured by following
+// their definition. A partial packet at the end, or a packet that can not be
+// measured, ends the stream.
 #[derive(Debug)]
 pub struct PacketStream<'a> {
     bytes: &'a Vec<u8>,
     position: usize,
-    num_bytes: usize,
+    packet: &'a LayoutPacketDef,
+    num_bytes: Option<usize>,
 }
 
 impl<'a> PacketStream<'a> {
-    pub fn new(packet: LayoutPacketDef, bytes: &'a Vec<u8>) -> PacketStream {
+    pub fn new(packet: &'a LayoutPacketDef, bytes: &'a Vec<u8>) -> PacketStream<'a> {
         PacketStream { bytes: bytes,
                        position: 0,
-                       num_bytes: packet.num_bytes() as usize,
+                       packet,
+                       num_bytes: packet.size().fixed().map(|num_bytes| num_bytes as usize),
         }
     }
 }
@@ -41,28 +50,96 @@ impl<'a> Iterator for PacketStream<'a> {
     type Item = &'a[u8];
 
     fn next(&mut self) -> Option<&'a[u8]> {
-        if (self.position + self.num_bytes) < self.bytes.len() {
+        let num_bytes = match self.num_bytes {
+            Some(num_bytes) => num_bytes,
+            None => self.packet.packet_length(&self.bytes[self.position..])? as usize,
+        };
+
+        if (self.position + num_bytes) <= self.bytes.len() {
             let prev_position = self.position;
 
-            self.position += self.num_bytes;
+            self.position += num_bytes;
 
-            Some(&self.bytes[prev_position..(prev_position + self.num_bytes)])
+            Some(&self.bytes[prev_position..(prev_position + num_bytes)])
         } else {
             None
         }
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Deserialize, Serialize)]
+#[derive(PartialEq, Debug, Deserialize, Serialize)]
 pub enum PacketDef<T> {
     Seq(Name, Vec<PacketDef<T>>),
-    // NOTE add back in multiple items here when needed. removed for simplicity.
-    // Subcom(HashMap<Vec<Item>, PacketDef>),
-    Subcom(Name, T, Vec<(T, PacketDef<T>)>),
+    // Switch on the values of one or more items decoded earlier in the packet. The first
+    // branch whose keys match the items, one key per item, is taken, then the default
+    // branch if there is one. Values matching no branch are an error.
+    Subcom(Name, Vec<T>, Vec<(Vec<SubcomKey>, PacketDef<T>)>, Option<Box<PacketDef<T>>>),
     Array(Name, ArrSize, Box<PacketDef<T>>),
     Leaf(T),
 }
 
+// A literal matched against one item of a subcom. Values are compared by number,
+// so U8(3) matches an item decoded as a U16.
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
+pub enum SubcomKey {
+    Value(Value),
+    // an inclusive range of values
+    Range(Value, Value),
+    Any,
+}
+
+impl SubcomKey {
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            SubcomKey::Value(key) => value.compare(key) == Some(cmp::Ordering::Equal),
+
+            SubcomKey::Range(low, high) => {
+                value.compare(low).is_some_and(|ordering| ordering != cmp::Ordering::Less) &&
+                value.compare(high).is_some_and(|ordering| ordering != cmp::Ordering::Greater)
+            },
+
+            SubcomKey::Any => true,
+        }
+    }
+}
+
+// Select the branch of a subcom for the values of its items.
+pub fn select_branch<'a, T>(name:     &Name,
+                            branches: &'a [(Vec<SubcomKey>, PacketDef<T>)],
+                            default:  &'a Option<Box<PacketDef<T>>>,
+                            values:   &[Value]) -> Result<&'a PacketDef<T>, String> {
+    for (keys, packet) in branches {
+        if keys.len() != values.len() {
+            return Err(format!("subcom {} has a branch with {} keys for {} items", name, keys.len(), values.len()));
+        }
+
+        if keys.iter().zip(values.iter()).all(|(key, value)| key.matches(value)) {
+            return Ok(packet);
+        }
+    }
+
+    match default {
+        Some(packet) => Ok(packet),
+        None => {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            Err(format!("subcom {} has no branch for ({})", name, values.join(", ")))
+        },
+    }
+}
+
+impl<T> PacketDef<T> {
+    // Leaves are named by their item, so only the structural nodes
+    // have a name of their own.
+    pub fn name(&self) -> Option<&Name> {
+        match self {
+            PacketDef::Seq(name, _)       => Some(name),
+            PacketDef::Subcom(name, _, _, _) => Some(name),
+            PacketDef::Array(name, _, _)  => Some(name),
+            PacketDef::Leaf(_)            => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Deserialize, Serialize)]
 pub struct Packet {
     packet: LayoutPacketDef,
@@ -76,72 +153,172 @@ pub type LayoutPacketDef = PacketDef<Item>;
 
 pub type LocPacketDef = PacketDef<LocItem>;
 
-impl NumBytes for LayoutPacketDef {
-    fn num_bytes(&self) -> u64 {
-        let mut num_bytes: u64 = 0;
+// The size of a packet, from its definition. Subcoms range from their smallest to
+// their largest branch, and variable arrays from no elements to as many as the
+// type of their count item can hold.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PacketSize {
+    pub min: u64,
+    // None when there is no limit, such as an array counted by a float
+    pub max: Option<u64>,
+    // the size of the largest item, which a naturally aligned layout is aligned to
+    pub alignment: u64,
+    // items at a fixed offset that is not a multiple of their size, with the offset
+    pub misaligned: Vec<(LocPath, u64)>,
+    // For packets of a fixed size, the bytes a naturally aligned layout of the same
+    // items would add, such as a C struct, including padding at the end.
+    pub padding: Option<u64>,
+}
+
+impl PacketSize {
+    pub fn fixed(&self) -> Option<u64> {
+        if self.max == Some(self.min) {
+            Some(self.min)
+        } else {
+            None
+        }
+    }
+}
 
+impl LayoutPacketDef {
+    // Names of the packet's items in definition order, keeping the first
+    // occurrence of each name.
+    pub fn names(&self) -> Vec<&Name> {
+        let mut names: Vec<&Name> = Vec::new();
         match self {
+            PacketDef::Seq(_, packets) => {
+                for packet in packets {
+                    names.extend(packet.names());
+                }
+            },
+
+            PacketDef::Subcom(_, _, pairs, default) => {
+                for (_, packet) in pairs {
+                   names.extend(packet.names());
+                }
+                if let Some(packet) = default {
+                   names.extend(packet.names());
+                }
+            },
+
+            PacketDef::Array(_, _, packet) => {
+               names.extend(packet.names());
+            },
+
+            PacketDef::Leaf(item) => {
+               names.push(&item.name);
+            },
+        }
+
+        let mut seen = HashSet::new();
+        names.retain(|name| seen.insert(*name));
+
+        names
+    }
+
+    // The full path to each item, in definition order, following the structure
+    // of the ValueMap produced by decode_layoutpacket. Every subcom branch is
+    // included, and fixed array elements are named "name[index]".
+    // NOTE variable size arrays have no fixed set of paths, so they are skipped.
+    // variable_arrays lists them, for outputs that need a column for every item.
+    pub fn paths(&self) -> Vec<LocPath> {
+        let mut paths = Vec::new();
+        let mut loc_path = LocPath::new();
+
+        LayoutPacketDef::paths_helper(self, &mut loc_path, &mut paths);
+
+        let mut seen = HashSet::new();
+        paths.retain(|path| seen.insert(path.clone()));
+
+        paths
+    }
+
+    fn paths_helper(packet:   &LayoutPacketDef,
+                    loc_path: &mut LocPath,
+                    paths:    &mut Vec<LocPath>) {
+        match packet {
             PacketDef::Seq(name, packets) => {
+                loc_path.push(name.to_string());
                 for packet in packets {
-                    num_bytes += packet.num_bytes();
+                    LayoutPacketDef::paths_helper(packet, loc_path, paths);
                 }
+                loc_path.pop();
             },
 
-            PacketDef::Subcom(name, item, pairs) => {
-                let mut subcom_bytes:u64 = 0;
+            PacketDef::Subcom(_, _, pairs, default) => {
                 for (_, packet) in pairs {
-                    cmp::max(subcom_bytes, packet.num_bytes());
+                    LayoutPacketDef::paths_helper(packet, loc_path, paths);
+                }
+                if let Some(packet) = default {
+                    LayoutPacketDef::paths_helper(packet, loc_path, paths);
                 }
-                num_bytes += subcom_bytes;
             },
 
             PacketDef::Array(name, size, packet) => {
-                match size {
-                    ArrSize::Var(name) => {
-                        panic!("can't statically determine num_bytes for variable size array!");
-                    },
-
-                    ArrSize::Fixed(num_elements) => {
-                        num_bytes += packet.num_bytes() * (*num_elements as u64);
+                if let ArrSize::Fixed(num_elements) = size {
+                    for index in 0 .. *num_elements {
+                        loc_path.push(format!("{}[{}]", name, index));
+                        LayoutPacketDef::paths_helper(packet, loc_path, paths);
+                        loc_path.pop();
                     }
                 }
             },
 
             PacketDef::Leaf(item) => {
-               num_bytes += item.num_bytes();
+                loc_path.push(item.name.clone());
+                paths.push(loc_path.clone());
+                loc_path.pop();
             },
         }
+    }
+
+    // The path to each variable size array, named "name[]", in definition order.
+    pub fn variable_arrays(&self) -> Vec<LocPath> {
+        let mut arrays = Vec::new();
+        let mut loc_path = LocPath::new();
+
+        LayoutPacketDef::variable_arrays_helper(self, &mut loc_path, &mut arrays);
 
-        num_bytes
+        arrays
     }
-}
 
-impl LayoutPacketDef {
-    pub fn names(&self) -> HashSet<&Name> {
-        let mut names: HashSet<&Name> = HashSet::new();
-        match self {
+    fn variable_arrays_helper(packet:   &LayoutPacketDef,
+                              loc_path: &mut LocPath,
+                              arrays:   &mut Vec<LocPath>) {
+        match packet {
             PacketDef::Seq(name, packets) => {
+                loc_path.push(name.to_string());
                 for packet in packets {
-                    names.extend(packet.names());
+                    LayoutPacketDef::variable_arrays_helper(packet, loc_path, arrays);
                 }
+                loc_path.pop();
             },
 
-            PacketDef::Subcom(_, item, pairs) => {
+            PacketDef::Subcom(_, _, pairs, default) => {
                 for (_, packet) in pairs {
-                   names.extend(packet.names());
+                    LayoutPacketDef::variable_arrays_helper(packet, loc_path, arrays);
+                }
+                if let Some(packet) = default {
+                    LayoutPacketDef::variable_arrays_helper(packet, loc_path, arrays);
                 }
             },
 
-            PacketDef::Array(_, _, packet) => {
-               names.extend(packet.names());
+            PacketDef::Array(name, ArrSize::Fixed(num_elements), packet) => {
+                for index in 0 .. *num_elements {
+                    loc_path.push(format!("{}[{}]", name, index));
+                    LayoutPacketDef::variable_arrays_helper(packet, loc_path, arrays);
+                    loc_path.pop();
+                }
             },
 
-            PacketDef::Leaf(item) => {
-               names.insert(&item.name);
+            PacketDef::Array(name, ArrSize::Var(_), _) => {
+                loc_path.push(format!("{}[]", name));
+                arrays.push(loc_path.clone());
+                loc_path.pop();
             },
-        }
 
-        names
+            PacketDef::Leaf(_) => (),
+        }
     }
 
     // NOTE this function does not work! it does not create the 
@@ -187,7 +364,7 @@ impl LayoutPacketDef {
                 }
             },
 
-            PacketDef::Subcom(_, _, _) => {
+            PacketDef::Subcom(_, _, _, _) => {
                 result = false;
             },
 
@@ -210,3 +387,273 @@ impl LayoutPacketDef {
         result
     }
 }
+
+// the largest value of an item that sets an array's size, if it is limited
+fn count_max(typ: &Prim) -> Option<u64> {
+    match typ {
+        Prim::Int(int_prim) => {
+            let bits = 8 * int_prim.num_bytes() as u32;
+            match int_prim.signedness {
+                Signedness::Unsigned => Some(u64::MAX >> (64 - bits)),
+                Signedness::Signed   => Some(u64::MAX >> (65 - bits)),
+            }
+        },
+
+        Prim::Enum(enum_prim) => Some(enum_prim.map.keys().last().map_or(0, |key| cmp::max(*key, 0) as u64)),
+
+        Prim::Float(_) => None,
+    }
+}
+
+fn add_size(size: Option<u64>, other: Option<u64>) -> Option<u64> {
+    size.and_then(|size| other.and_then(|other| size.checked_add(other)))
+}
+
+fn round_up(offset: u64, alignment: u64) -> u64 {
+    offset.div_ceil(alignment) * alignment
+}
+
+// what is found while walking a definition for its size
+struct SizeState {
+    // the largest value of each item seen so far, for the arrays they count
+    counts: HashMap<Name, Option<u64>>,
+    alignment: u64,
+    misaligned: Vec<(LocPath, u64)>,
+    loc_path: LocPath,
+}
+
+impl LayoutPacketDef {
+    pub fn size(&self) -> PacketSize {
+        let mut state = SizeState { counts: HashMap::new(),
+                                    alignment: 1,
+                                    misaligned: Vec::new(),
+                                    loc_path: LocPath::new() };
+
+        let (min, max) = LayoutPacketDef::size_helper(self, (0, Some(0)), &mut state);
+
+        let padding = if max == Some(min) {
+            Some(round_up(LayoutPacketDef::aligned_end(self, 0), state.alignment) - min)
+        } else {
+            None
+        };
+
+        PacketSize { min, max, alignment: state.alignment, misaligned: state.misaligned, padding }
+    }
+
+    // The smallest and largest size of a part of a packet, starting within the given
+    // range of offsets. Items are only checked for alignment at fixed offsets.
+    fn size_helper(packet: &LayoutPacketDef,
+                   start:  (u64, Option<u64>),
+                   state:  &mut SizeState) -> (u64, Option<u64>) {
+        match packet {
+            PacketDef::Seq(name, packets) => {
+                let mut size = (0, Some(0));
+
+                state.loc_path.push(name.to_string());
+                for packet in packets {
+                    let offset = (start.0 + size.0, add_size(start.1, size.1));
+                    let part = LayoutPacketDef::size_helper(packet, offset, state);
+                    size = (size.0 + part.0, add_size(size.1, part.1));
+                }
+                state.loc_path.pop();
+
+                size
+            },
+
+            PacketDef::Subcom(_, _, pairs, default) => {
+                let branches: Vec<(u64, Option<u64>)> =
+                    pairs.iter().map(|(_, packet)| packet)
+                         .chain(default.iter().map(|packet| &**packet))
+                         .map(|packet| LayoutPacketDef::size_helper(packet, start, state))
+                         .collect();
+
+                if branches.is_empty() {
+                    return (0, Some(0));
+                }
+
+                let min = branches.iter().map(|size| size.0).min().unwrap();
+                let max = branches.iter().map(|size| size.1).collect::<Option<Vec<u64>>>()
+                                  .map(|maxes| maxes.into_iter().max().unwrap());
+                (min, max)
+            },
+
+            PacketDef::Array(name, ArrSize::Fixed(num_elements), packet) => {
+                let mut size = (0, Some(0));
+
+                for index in 0 .. *num_elements {
+                    state.loc_path.push(format!("{}[{}]", name, index));
+                    let offset = (start.0 + size.0, add_size(start.1, size.1));
+                    let element = LayoutPacketDef::size_helper(packet, offset, state);
+                    size = (size.0 + element.0, add_size(size.1, element.1));
+                    state.loc_path.pop();
+                }
+
+                size
+            },
+
+            PacketDef::Array(name, ArrSize::Var(count_name), packet) => {
+                // NOTE the count is taken from the last item with its name, which may not
+                // be the one in scope when decoding
+                let count = state.counts.get(count_name).cloned().unwrap_or(None);
+
+                state.loc_path.push(format!("{}[0]", name));
+                let element = LayoutPacketDef::size_helper(packet, start, state);
+                state.loc_path.pop();
+
+                (0, element.1.and_then(|max| count.and_then(|count| max.checked_mul(count))))
+            },
+
+            PacketDef::Leaf(item) => {
+                let num_bytes = item.num_bytes();
+                state.alignment = cmp::max(state.alignment, num_bytes);
+
+                if start.1 == Some(start.0) && !start.0.is_multiple_of(num_bytes) {
+                    let mut loc_path = state.loc_path.clone();
+                    loc_path.push(item.name.clone());
+                    state.misaligned.push((loc_path, start.0));
+                }
+
+                state.counts.insert(item.name.clone(), count_max(&item.typ));
+
+                (num_bytes, Some(num_bytes))
+            },
+        }
+    }
+
+    // where a part of a packet of fixed size would end if each item were naturally aligned
+    fn aligned_end(packet: &LayoutPacketDef, offset: u64) -> u64 {
+        match packet {
+            PacketDef::Seq(_, packets) => {
+                packets.iter().fold(offset, |offset, packet| LayoutPacketDef::aligned_end(packet, offset))
+            },
+
+            PacketDef::Subcom(_, _, pairs, default) => {
+                pairs.iter().map(|(_, packet)| packet)
+                     .chain(default.iter().map(|packet| &**packet))
+                     .map(|packet| LayoutPacketDef::aligned_end(packet, offset))
+                     .max()
+                     .unwrap_or(offset)
+            },
+
+            PacketDef::Array(_, ArrSize::Fixed(num_elements), packet) => {
+                (0 .. *num_elements).fold(offset, |offset, _| LayoutPacketDef::aligned_end(packet, offset))
+            },
+
+            PacketDef::Array(_, ArrSize::Var(_), _) => offset,
+
+            PacketDef::Leaf(item) => round_up(offset, item.num_bytes()) + item.num_bytes(),
+        }
+    }
+
+    // The length of the packet at the start of the given bytes, following its subcoms
+    // and variable arrays, or None if the bytes end first or a subcom has no branch
+    // for its values.
+    pub fn packet_length(&self, bytes: &[u8]) -> Option<u64> {
+        let mut cursor = Cursor::new(bytes);
+        let mut values = HashMap::new();
+
+        LayoutPacketDef::length_helper(self, &mut cursor, &mut values)?;
+
+        Some(cursor.position())
+    }
+
+    // NOTE items are remembered by name alone, so the latest item with a name
+    // controls subcoms and arrays
+    fn length_helper(packet: &LayoutPacketDef,
+                     bytes:  &mut Cursor<&[u8]>,
+                     values: &mut HashMap<Name, Value>) -> Option<()> {
+        match packet {
+            PacketDef::Seq(_, packets) => {
+                for packet in packets {
+                    LayoutPacketDef::length_helper(packet, bytes, values)?;
+                }
+            },
+
+            PacketDef::Subcom(name, items, pairs, default) => {
+                let keys: Option<Vec<Value>> = items.iter().map(|item| values.get(&item.name).cloned()).collect();
+                let packet = select_branch(name, pairs, default, &keys?).ok()?;
+                LayoutPacketDef::length_helper(packet, bytes, values)?;
+            },
+
+            PacketDef::Array(_, size, packet) => {
+                let num_elements = match size {
+                    ArrSize::Fixed(num_elements) => *num_elements as u64,
+                    ArrSize::Var(count_name) => match values.get(count_name)? {
+                        Value::F32(_) | Value::F64(_) => return None,
+                        Value::Enum(_, count) => *count as u64,
+                        count => count.value() as u64,
+                    },
+                };
+
+                for _ in 0 .. num_elements {
+                    LayoutPacketDef::length_helper(packet, bytes, values)?;
+                }
+            },
+
+            PacketDef::Leaf(item) => {
+                if bytes.position() + item.num_bytes() > bytes.get_ref().len() as u64 {
+                    return None;
+                }
+
+                // enums are kept as their integer, so values missing from their map can still be measured
+                let value = match item.typ {
+                    Prim::Enum(ref enum_prim) => {
+                        let int = decode_int(&enum_prim.int_prim, bytes).value();
+                        Value::Enum(String::new(), int)
+                    },
+                    ref typ => decode_prim(typ, bytes),
+                };
+                values.insert(item.name.clone(), value);
+            },
+        }
+
+        Some(())
+    }
+}
+
+
+#[cfg(test)]
+mod test_packet {
+    use super::*;
+    use *;
+
+    #[test]
+    fn test_packet_size() {
+        let fixed : LayoutPacketDef =
+            seq("tlm".to_string(), vec![u8_be("status"), u16_be("count"), array_fixed("rate".to_string(), 2, u32_be("value"))]);
+        let size = fixed.size();
+        assert_eq!(size.fixed(), Some(11));
+        assert_eq!(size.alignment, 4);
+        // aligned, a byte after status brings count to 2 and rate to 4
+        assert_eq!(size.padding, Some(1));
+        assert_eq!(size.misaligned, vec![(vec!["tlm".to_string(), "count".to_string()], 1),
+                                         (vec!["tlm".to_string(), "rate[0]".to_string(), "value".to_string()], 3),
+                                         (vec!["tlm".to_string(), "rate[1]".to_string(), "value".to_string()], 7)]);
+
+        // a subcom of one or two bytes, then up to 255 samples
+        let variable : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec![u8_be("type"), u8_be("count"),
+                     PacketDef::Subcom("payload".to_string(),
+                                       vec![item("type", Prim::Int(IntPrim::u8_be()))],
+                                       vec![(vec![SubcomKey::Value(val_u8(1))], u8_be("small"))],
+                                       Some(Box::new(u16_be("large")))),
+                     array_var("samples".to_string(), "count".to_string(), u16_be("sample"))]);
+        let size = variable.size();
+        assert_eq!((size.min, size.max), (3, Some(2 + 2 + 255 * 2)));
+        assert_eq!(size.fixed(), None);
+        assert_eq!(size.padding, None);
+
+        // counted by a float, there is no limit
+        let unlimited : LayoutPacketDef =
+            seq("tlm".to_string(), vec![f32_be("count"), array_var("samples".to_string(), "count".to_string(), u8_be("sample"))]);
+        assert_eq!(unlimited.size().max, None);
+
+        // variable packets are measured one at a time, ending at a partial packet
+        let bytes = vec![0x01, 0x01, 0xAA, 0x00, 0x01,
+                         0x02, 0x00, 0xBB, 0xBB,
+                         0x01, 0x03, 0xAA];
+        let packets: Vec<&[u8]> = super::PacketStream::new(&variable, &bytes).collect();
+        assert_eq!(packets, vec![&bytes[0..5], &bytes[5..9]]);
+    }
+}
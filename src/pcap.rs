@@ -0,0 +1,713 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::io;
+use std::io::Read;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use self::byteorder::{BigEndian, LittleEndian, ByteOrder};
+
+use framing::*;
+use input::*;
+
+
+// Read telemetry out of network captures, such as those recorded by tcpdump or
+// wireshark, in either pcap or pcapng format. UDP payloads are each taken as a
+// packet, and TCP payloads are put back in stream order for each direction of a
+// connection and split into packets with a framer. Each packet keeps the capture
+// timestamp of the frame it arrived in (for TCP, the frame that completed it).
+//
+// NOTE fragmented IP packets are skipped rather then reassembled.
+
+// A captured link layer frame, with its timestamp in seconds since the unix epoch.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Frame {
+    pub time: f64,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32  = 0xA1B2_3C4D;
+
+const PCAPNG_SECTION_HEADER: u32   = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE: u32        = 1;
+const PCAPNG_ENHANCED_PACKET: u32  = 6;
+const PCAPNG_TSRESOL_OPTION: u16   = 9;
+
+// the largest frame we expect to see, to catch corrupt lengths
+const MAX_FRAME: usize = 256 * 1024;
+
+#[derive(Debug, Clone)]
+enum CaptureFormat {
+    Pcap { big_endian: bool, ticks_per_second: f64, link_type: u32 },
+    Pcapng { big_endian: bool },
+}
+
+#[derive(Debug, Clone)]
+struct Interface {
+    link_type: u32,
+    ticks_per_second: f64,
+}
+
+fn read_u16(bytes: &[u8], big_endian: bool) -> u16 {
+    if big_endian { BigEndian::read_u16(bytes) } else { LittleEndian::read_u16(bytes) }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    if big_endian { BigEndian::read_u32(bytes) } else { LittleEndian::read_u32(bytes) }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Read frames from a pcap or pcapng capture, detecting the format from its header.
+pub struct PcapReader<R> {
+    reader: R,
+    format: CaptureFormat,
+    interfaces: Vec<Interface>,
+    error: Option<io::Error>,
+}
+
+impl<R : Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<PcapReader<R>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        let format = if BigEndian::read_u32(&magic) == PCAPNG_SECTION_HEADER {
+            CaptureFormat::Pcapng { big_endian: true }
+        } else {
+            let big_endian = match (BigEndian::read_u32(&magic), LittleEndian::read_u32(&magic)) {
+                (PCAP_MAGIC_MICROS, _) | (PCAP_MAGIC_NANOS, _) => true,
+                (_, PCAP_MAGIC_MICROS) | (_, PCAP_MAGIC_NANOS) => false,
+                _ => return Err(invalid("not a pcap or pcapng capture")),
+            };
+            let nanos = read_u32(&magic, big_endian) == PCAP_MAGIC_NANOS;
+
+            // version, time zone, sigfigs, snap length, and link type
+            let mut header = [0; 20];
+            reader.read_exact(&mut header)?;
+
+            CaptureFormat::Pcap { big_endian,
+                                  ticks_per_second: if nanos { 1e9 } else { 1e6 },
+                                  link_type: read_u32(&header[16..], big_endian) }
+        };
+
+        let mut pcap_reader = PcapReader { reader, format, interfaces: Vec::new(), error: None };
+
+        // the first section header's type was already read
+        if let CaptureFormat::Pcapng { .. } = pcap_reader.format {
+            let mut header = [0; 8];
+            pcap_reader.reader.read_exact(&mut header)?;
+            pcap_reader.read_section_header(header)?;
+        }
+
+        Ok(pcap_reader)
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Read the rest of a section header, given its length and byte order magic.
+    // Each section sets its own byte order.
+    fn read_section_header(&mut self, header: [u8; 8]) -> io::Result<()> {
+        let big_endian = match (BigEndian::read_u32(&header[4..]), LittleEndian::read_u32(&header[4..])) {
+            (PCAPNG_BYTE_ORDER_MAGIC, _) => true,
+            (_, PCAPNG_BYTE_ORDER_MAGIC) => false,
+            _ => return Err(invalid("bad pcapng byte order magic")),
+        };
+
+        let total_length = read_u32(&header, big_endian) as usize;
+        if !(28..=MAX_FRAME).contains(&total_length) {
+            return Err(invalid("bad pcapng section header length"));
+        }
+
+        // skip the rest of the block, up to and including the trailing length
+        let mut rest = vec![0; total_length - 12];
+        self.reader.read_exact(&mut rest)?;
+
+        self.format = CaptureFormat::Pcapng { big_endian };
+        self.interfaces.clear();
+
+        Ok(())
+    }
+
+    // Read the next frame, or None at the end of the capture.
+    fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        match self.format {
+            CaptureFormat::Pcap { big_endian, ticks_per_second, link_type } => {
+                let mut header = [0; 16];
+                if !read_or_end(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+
+                let seconds = read_u32(&header, big_endian) as f64;
+                let ticks = read_u32(&header[4..], big_endian) as f64;
+                let length = read_u32(&header[8..], big_endian) as usize;
+                if length > MAX_FRAME {
+                    return Err(invalid("bad pcap record length"));
+                }
+
+                let mut data = vec![0; length];
+                self.reader.read_exact(&mut data)?;
+
+                Ok(Some(Frame { time: seconds + ticks / ticks_per_second, link_type, data }))
+            },
+
+            CaptureFormat::Pcapng { big_endian } => {
+                loop {
+                    let mut header = [0; 8];
+                    if !read_or_end(&mut self.reader, &mut header)? {
+                        return Ok(None);
+                    }
+
+                    let block_type = read_u32(&header, big_endian);
+                    if block_type == PCAPNG_SECTION_HEADER {
+                        let mut section_header = [0; 8];
+                        section_header[..4].copy_from_slice(&header[4..]);
+                        self.reader.read_exact(&mut section_header[4..])?;
+                        self.read_section_header(section_header)?;
+                        return self.read_frame();
+                    }
+
+                    let total_length = read_u32(&header[4..], big_endian) as usize;
+                    if !(12..=MAX_FRAME).contains(&total_length) || !total_length.is_multiple_of(4) {
+                        return Err(invalid("bad pcapng block length"));
+                    }
+
+                    let mut body = vec![0; total_length - 12];
+                    self.reader.read_exact(&mut body)?;
+                    let mut trailer = [0; 4];
+                    self.reader.read_exact(&mut trailer)?;
+
+                    match block_type {
+                        PCAPNG_INTERFACE => {
+                            if body.len() < 8 {
+                                return Err(invalid("short pcapng interface block"));
+                            }
+                            let link_type = read_u16(&body, big_endian) as u32;
+                            let ticks_per_second = interface_resolution(&body[8..], big_endian);
+                            self.interfaces.push(Interface { link_type, ticks_per_second });
+                        },
+
+                        PCAPNG_ENHANCED_PACKET => {
+                            if body.len() < 20 {
+                                return Err(invalid("short pcapng packet block"));
+                            }
+                            let interface = match self.interfaces.get(read_u32(&body, big_endian) as usize) {
+                                Some(interface) => interface,
+                                None => return Err(invalid("pcapng packet for an undefined interface")),
+                            };
+
+                            let ticks = ((read_u32(&body[4..], big_endian) as u64) << 32) |
+                                        read_u32(&body[8..], big_endian) as u64;
+                            let length = read_u32(&body[12..], big_endian) as usize;
+                            if 20 + length > body.len() {
+                                return Err(invalid("bad pcapng packet length"));
+                            }
+
+                            return Ok(Some(Frame { time: ticks as f64 / interface.ticks_per_second,
+                                                   link_type: interface.link_type,
+                                                   data: body[20 .. 20 + length].to_vec() }));
+                        },
+
+                        // NOTE other blocks, including simple packet blocks which have no
+                        // timestamp, are skipped
+                        _ => (),
+                    }
+                }
+            },
+        }
+    }
+}
+
+// Find the if_tsresol option of an interface block, defaulting to microseconds.
+fn interface_resolution(mut options: &[u8], big_endian: bool) -> f64 {
+    while options.len() >= 4 {
+        let code = read_u16(options, big_endian);
+        let length = read_u16(&options[2..], big_endian) as usize;
+        let padded = length.div_ceil(4) * 4;
+        if options.len() < 4 + padded {
+            break;
+        }
+
+        if code == PCAPNG_TSRESOL_OPTION && length >= 1 {
+            let resolution = options[4];
+            let exponent = (resolution & 0x7F) as i32;
+            return if resolution & 0x80 == 0 { 10f64.powi(exponent) } else { 2f64.powi(exponent) };
+        }
+
+        // end of options
+        if code == 0 {
+            break;
+        }
+
+        options = &options[4 + padded..];
+    }
+
+    1e6
+}
+
+// Fill the buffer, returning false if the reader was already at its end.
+fn read_or_end<R : Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "capture ends within a record")),
+            Ok(num_bytes) => filled += num_bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(true)
+}
+
+impl<R : Read> Iterator for PcapReader<R> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        match self.read_frame() {
+            Ok(frame) => frame,
+
+            Err(err) => {
+                self.error = Some(err);
+                None
+            },
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+// A UDP datagram or TCP segment pulled out of a frame
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Segment<'a> {
+    pub protocol: Protocol,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub syn: bool,
+    pub payload: &'a [u8],
+}
+
+const LINKTYPE_NULL: u32      = 0;
+const LINKTYPE_ETHERNET: u32  = 1;
+const LINKTYPE_RAW_BSD: u32   = 12;
+const LINKTYPE_RAW: u32       = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32      = 228;
+const LINKTYPE_IPV6: u32      = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IP_TCP: u8 = 6;
+const IP_UDP: u8 = 17;
+
+// Parse a frame down to its UDP or TCP payload, if it has one.
+pub fn parse_frame(link_type: u32, data: &[u8]) -> Option<Segment<'_>> {
+    let ip = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = BigEndian::read_u16(data.get(offset .. offset + 2)?);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = BigEndian::read_u16(data.get(offset .. offset + 2)?);
+            }
+
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(offset + 2 ..)?,
+                _ => return None,
+            }
+        },
+
+        LINKTYPE_NULL => data.get(4..)?,
+
+        LINKTYPE_RAW | LINKTYPE_RAW_BSD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+
+        _ => return None,
+    };
+
+    let (protocol, src, dst, transport) = match ip.first()? >> 4 {
+        4 => {
+            let header_length = ((ip[0] & 0x0F) as usize) * 4;
+            let total_length = BigEndian::read_u16(ip.get(2..4)?) as usize;
+            if header_length < 20 || total_length < header_length {
+                return None;
+            }
+            let fragment = BigEndian::read_u16(ip.get(6..8)?);
+            // more fragments, or a fragment offset
+            if fragment & 0x3FFF != 0 {
+                return None;
+            }
+
+            // trim any link layer padding
+            let ip = ip.get(..total_length)?;
+            let src = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+            let dst = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+
+            (ip[9], IpAddr::V4(src), IpAddr::V4(dst), ip.get(header_length..)?)
+        },
+
+        6 => {
+            let payload_length = BigEndian::read_u16(ip.get(4..6)?) as usize;
+            let ip = ip.get(.. 40 + payload_length)?;
+
+            let mut src = [0; 16];
+            src.copy_from_slice(&ip[8..24]);
+            let mut dst = [0; 16];
+            dst.copy_from_slice(&ip[24..40]);
+
+            // skip extension headers
+            let mut next_header = ip[6];
+            let mut offset = 40;
+            loop {
+                match next_header {
+                    // hop by hop, routing, and destination options
+                    0 | 43 | 60 => {
+                        next_header = *ip.get(offset)?;
+                        offset += (*ip.get(offset + 1)? as usize + 1) * 8;
+                    },
+
+                    // fragment
+                    44 => return None,
+
+                    _ => break,
+                }
+            }
+
+            (next_header, IpAddr::V6(Ipv6Addr::from(src)), IpAddr::V6(Ipv6Addr::from(dst)), ip.get(offset..)?)
+        },
+
+        _ => return None,
+    };
+
+    let src_port = BigEndian::read_u16(transport.get(0..2)?);
+    let dst_port = BigEndian::read_u16(transport.get(2..4)?);
+    let src = SocketAddr::new(src, src_port);
+    let dst = SocketAddr::new(dst, dst_port);
+
+    match protocol {
+        IP_UDP => {
+            let length = BigEndian::read_u16(transport.get(4..6)?) as usize;
+            let payload = transport.get(8 .. length.max(8))?;
+
+            Some(Segment { protocol: Protocol::Udp, src, dst, seq: 0, syn: false, payload })
+        },
+
+        IP_TCP => {
+            let seq = BigEndian::read_u32(transport.get(4..8)?);
+            let header_length = ((*transport.get(12)? >> 4) as usize) * 4;
+            let syn = transport.get(13)? & 0x02 != 0;
+            let payload = transport.get(header_length..)?;
+
+            Some(Segment { protocol: Protocol::Tcp, src, dst, seq, syn, payload })
+        },
+
+        _ => None,
+    }
+}
+
+// Which traffic to take packets from. Ports and hosts match either end.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PcapFilter {
+    pub protocol: Protocol,
+    pub port: Option<u16>,
+    pub host: Option<IpAddr>,
+}
+
+impl Default for PcapFilter {
+    fn default() -> PcapFilter {
+        PcapFilter { protocol: Protocol::Udp, port: None, host: None }
+    }
+}
+
+impl PcapFilter {
+    pub fn matches(&self, segment: &Segment) -> bool {
+        segment.protocol == self.protocol &&
+        self.port.is_none_or(|port| segment.src.port() == port || segment.dst.port() == port) &&
+        self.host.is_none_or(|host| segment.src.ip() == host || segment.dst.ip() == host)
+    }
+}
+
+// one direction of a TCP connection
+struct Flow<F> {
+    next_seq: Option<u32>,
+    // segments received ahead of a gap, by sequence number
+    pending: BTreeMap<u32, Vec<u8>>,
+    framer: F,
+}
+
+// the most segments held waiting for a gap to fill before giving up on it
+const MAX_PENDING: usize = 1024;
+
+impl<F : Framer> Flow<F> {
+    fn push(&mut self, seq: u32, mut payload: &[u8]) {
+        let next_seq = *self.next_seq.get_or_insert(seq);
+
+        // distance from the next expected byte, allowing for wraparound
+        let offset = seq.wrapping_sub(next_seq) as i32;
+
+        if offset > 0 {
+            if self.pending.len() < MAX_PENDING {
+                self.pending.insert(seq, payload.to_vec());
+                return;
+            }
+
+            // NOTE the gap was never filled, so skip over it and lose sync with the stream
+            self.framer.clear();
+        } else if offset < 0 {
+            // retransmitted data, keeping any new bytes past what was already seen
+            let seen = (-offset) as usize;
+            if seen >= payload.len() {
+                return;
+            }
+            payload = &payload[seen..];
+        }
+
+        self.framer.push(payload);
+        let mut next_seq = next_seq.wrapping_add(offset.max(0) as u32).wrapping_add(payload.len() as u32);
+
+        // fill in with any segments that now follow on
+        while let Some(seq) = self.pending.keys().next().cloned() {
+            let offset = seq.wrapping_sub(next_seq) as i32;
+            if offset > 0 {
+                break;
+            }
+
+            let segment = self.pending.remove(&seq).unwrap();
+            let seen = (-offset) as usize;
+            if seen < segment.len() {
+                self.framer.push(&segment[seen..]);
+                next_seq = next_seq.wrapping_add((segment.len() - seen) as u32);
+            }
+        }
+
+        self.next_seq = Some(next_seq);
+    }
+}
+
+// Packets from a capture, timestamped with their capture time.
+pub struct PcapSource<R, F> {
+    frames: PcapReader<R>,
+    filter: PcapFilter,
+    framer: F,
+    flows: HashMap<(SocketAddr, SocketAddr), Flow<F>>,
+    ready: VecDeque<TimedPacket>,
+}
+
+impl<R : Read, F : Framer + Clone> PcapSource<R, F> {
+    // The framer splits TCP streams into packets, and is not used for UDP.
+    pub fn new(reader: R, filter: PcapFilter, framer: F) -> io::Result<PcapSource<R, F>> {
+        Ok(PcapSource { frames: PcapReader::new(reader)?,
+                        filter,
+                        framer,
+                        flows: HashMap::new(),
+                        ready: VecDeque::new() })
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.frames.take_error()
+    }
+}
+
+impl<R : Read, F : Framer + Clone> Iterator for PcapSource<R, F> {
+    type Item = TimedPacket;
+
+    fn next(&mut self) -> Option<TimedPacket> {
+        loop {
+            if let Some(packet) = self.ready.pop_front() {
+                return Some(packet);
+            }
+
+            let frame = self.frames.next()?;
+
+            let segment = match parse_frame(frame.link_type, &frame.data) {
+                Some(segment) => segment,
+                None => continue,
+            };
+
+            if !self.filter.matches(&segment) {
+                continue;
+            }
+
+            match segment.protocol {
+                Protocol::Udp => return Some(TimedPacket { time: frame.time, bytes: segment.payload.to_vec() }),
+
+                Protocol::Tcp => {
+                    let template = &self.framer;
+                    let flow = self.flows.entry((segment.src, segment.dst))
+                                         .or_insert_with(|| Flow { next_seq: None,
+                                                                   pending: BTreeMap::new(),
+                                                                   framer: template.clone() });
+
+                    // a new connection, whose data starts after the syn
+                    if segment.syn {
+                        flow.next_seq = Some(segment.seq.wrapping_add(1));
+                        flow.pending.clear();
+                        flow.framer.clear();
+                        continue;
+                    }
+
+                    if segment.payload.is_empty() {
+                        continue;
+                    }
+
+                    flow.push(segment.seq, segment.payload);
+
+                    while let Some(bytes) = flow.framer.next_packet() {
+                        self.ready.push_back(TimedPacket { time: frame.time, bytes });
+                    }
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_pcap {
+    use super::*;
+    use std::io::Cursor;
+
+    // an ethernet frame holding an IPv4 UDP or TCP packet
+    fn ethernet_frame(protocol: Protocol, src_port: u16, dst_port: u16, seq: u32, syn: bool, payload: &[u8]) -> Vec<u8> {
+        let mut transport = Vec::new();
+        transport.extend_from_slice(&src_port.to_be_bytes());
+        transport.extend_from_slice(&dst_port.to_be_bytes());
+        match protocol {
+            Protocol::Udp => {
+                transport.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+                transport.extend_from_slice(&[0, 0]);
+            },
+
+            Protocol::Tcp => {
+                transport.extend_from_slice(&seq.to_be_bytes());
+                transport.extend_from_slice(&[0, 0, 0, 0, 0x50, if syn { 0x02 } else { 0x18 }, 0xFF, 0xFF, 0, 0, 0, 0]);
+            },
+        }
+        transport.extend_from_slice(payload);
+
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&((20 + transport.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, if protocol == Protocol::Udp { IP_UDP } else { IP_TCP }, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&transport);
+        frame
+    }
+
+    #[test]
+    fn test_pcap_udp() {
+        // little endian pcap with microsecond timestamps
+        let mut capture = Vec::new();
+        capture.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+        capture.extend_from_slice(&[2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0, 0, 1, 0, 0, 0]);
+
+        let frames = vec![(ethernet_frame(Protocol::Udp, 1000, 5000, 0, false, &[1, 2, 3]), 500_000),
+                          (ethernet_frame(Protocol::Udp, 1000, 6000, 0, false, &[4, 5]), 600_000)];
+        for (frame, micros) in frames {
+            capture.extend_from_slice(&100u32.to_le_bytes());
+            capture.extend_from_slice(&(micros as u32).to_le_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&frame);
+        }
+
+        let filter = PcapFilter { port: Some(5000), ..PcapFilter::default() };
+        let packets: Vec<TimedPacket> = PcapSource::new(Cursor::new(&capture), filter, FixedFramer::new(1)).unwrap().collect();
+        assert_eq!(packets, vec![TimedPacket { time: 100.5, bytes: vec![1, 2, 3] }]);
+
+        let filter = PcapFilter { host: Some("10.0.0.2".parse().unwrap()), ..PcapFilter::default() };
+        assert_eq!(PcapSource::new(Cursor::new(&capture), filter, FixedFramer::new(1)).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_parse_truncated_ipv4() {
+        let frame = ethernet_frame(Protocol::Udp, 1000, 5000, 0, false, &[1, 2, 3]);
+        assert!(parse_frame(LINKTYPE_ETHERNET, &frame).is_some());
+
+        // a total length shorter then the IPv4 header
+        let mut truncated = frame.clone();
+        truncated[16..18].copy_from_slice(&[0, 10]);
+        assert!(parse_frame(LINKTYPE_ETHERNET, &truncated).is_none());
+
+        // a header length shorter then the fixed IPv4 header
+        let mut truncated = frame.clone();
+        truncated[14] = 0x44;
+        assert!(parse_frame(LINKTYPE_ETHERNET, &truncated).is_none());
+
+        assert!(parse_frame(LINKTYPE_RAW, &[0x45, 0, 0, 10, 0, 0, 0x40, 0, 64, IP_UDP]).is_none());
+    }
+
+    #[test]
+    fn test_pcapng_tcp() {
+        let block = |block_type: u32, body: &[u8]| -> Vec<u8> {
+            let length = (12 + body.len()) as u32;
+            let mut block = Vec::new();
+            block.extend_from_slice(&block_type.to_be_bytes());
+            block.extend_from_slice(&length.to_be_bytes());
+            block.extend_from_slice(body);
+            block.extend_from_slice(&length.to_be_bytes());
+            block
+        };
+
+        // big endian section, with an ethernet interface using millisecond timestamps
+        let mut capture = block(PCAPNG_SECTION_HEADER, &[0x1A, 0x2B, 0x3C, 0x4D, 0, 1, 0, 0,
+                                                         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        capture.extend(block(PCAPNG_INTERFACE, &[0, 1, 0, 0, 0, 0, 0xFF, 0xFF,
+                                                 0, 9, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0]));
+
+        // a connection whose stream is 4 byte packets, with the segments out of order
+        // and one retransmitted with more data
+        let segments = vec![(ethernet_frame(Protocol::Tcp, 4000, 7000, 99, true, &[]), 1000),
+                            (ethernet_frame(Protocol::Tcp, 4000, 7000, 106, false, &[7, 8, 9, 10]), 2000),
+                            (ethernet_frame(Protocol::Tcp, 4000, 7000, 100, false, &[1, 2, 3]), 3000),
+                            (ethernet_frame(Protocol::Tcp, 4000, 7000, 100, false, &[1, 2, 3, 4, 5, 6]), 4000),
+                            (ethernet_frame(Protocol::Udp, 4000, 7000, 0, false, &[0xEE]), 5000)];
+        for (mut frame, millis) in segments {
+            let mut body = Vec::new();
+            body.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+            body.extend_from_slice(&(millis as u32).to_be_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            body.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            while frame.len() % 4 != 0 {
+                frame.push(0);
+            }
+            body.extend_from_slice(&frame);
+            capture.extend(block(PCAPNG_ENHANCED_PACKET, &body));
+        }
+
+        let filter = PcapFilter { protocol: Protocol::Tcp, port: Some(7000), host: None };
+        let mut source = PcapSource::new(Cursor::new(&capture), filter, FixedFramer::new(4)).unwrap();
+        let packets: Vec<TimedPacket> = source.by_ref().collect();
+        assert!(source.take_error().is_none());
+
+        assert_eq!(packets, vec![TimedPacket { time: 4.0, bytes: vec![1, 2, 3, 4] },
+                                 TimedPacket { time: 4.0, bytes: vec![5, 6, 7, 8] }]);
+    }
+}
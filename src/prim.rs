@@ -9,9 +9,13 @@ use std::collections::HashMap;
 use std::collections::BTreeMap;
 #[allow(unused_imports)]
 use std::option;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use self::fnv::FnvHashMap;
 
+use serde::de::{self, Deserializer, Visitor, MapAccess};
+
 #[allow(unused_imports)]
 use self::bytes::{Bytes, Buf};
 
@@ -22,12 +26,19 @@ use types::{NumBytes, Name};
 pub struct Item {
     pub name: Name,
     pub typ: Prim,
+    // NOTE conversions and limits are documentation for now, and are not applied when decoding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<Conversion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
 }
 
 impl Clone for Item {
     fn clone(&self) -> Item {
         Item {name: self.name.clone(),
              typ: self.typ.clone(),
+             conversion: self.conversion.clone(),
+             limits: self.limits.clone(),
         }
     }
 }
@@ -40,10 +51,52 @@ impl NumBytes for Item {
 
 impl Item {
   pub fn new(name: Name, typ: Prim) -> Self {
-    Item{name: name, typ: typ}
+    Item{name: name, typ: typ, conversion: None, limits: None}
   }
 }
 
+// The conversion of an item's raw value to engineering units.
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
+pub enum Conversion {
+    // coefficients from the constant term up, so [c0, c1, c2] is c0 + c1 x + c2 x^2
+    Polynomial(Vec<f64>),
+}
+
+// The ranges an item's converted value is expected to stay within, as (low, high).
+// Leaving the warning range is unusual, and leaving the alarm range is a fault.
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
+pub struct Limits {
+    #[serde(default)]
+    pub warning: Option<(f64, f64)>,
+    #[serde(default)]
+    pub alarm: Option<(f64, f64)>,
+}
+
+// items are compared and hashed as definitions, so floats are compared by their bits
+impl Eq for Conversion {}
+
+impl Hash for Conversion {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Conversion::Polynomial(coefficients) => {
+                for coefficient in coefficients.iter() {
+                    coefficient.to_bits().hash(state);
+                }
+            },
+        }
+    }
+}
+
+impl Eq for Limits {}
+
+impl Hash for Limits {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for range in [self.warning, self.alarm].iter() {
+            range.map(|(low, high)| (low.to_bits(), high.to_bits())).hash(state);
+        }
+    }
+}
+
 
 #[derive(Eq, PartialEq, Debug, Hash, Clone, Deserialize, Serialize)]
 pub enum Endianness {
@@ -163,7 +216,7 @@ impl IntPrim {
   }
 
   pub fn i8_be() -> Self {
-    IntPrim::new(IntSize::Bits8, Signedness::Unsigned, Endianness::BigEndian)
+    IntPrim::new(IntSize::Bits8, Signedness::Signed, Endianness::BigEndian)
   }
 
   pub fn i8_le() -> Self {
@@ -212,6 +265,7 @@ impl NumBytes for BitPrim {
 
 #[derive(Eq, PartialEq, Debug, Hash, Clone, Deserialize, Serialize)]
 pub struct Enum {
+    #[serde(deserialize_with = "unique_states")]
     pub map: BTreeMap<i64, Name>,
     pub int_prim: IntPrim,
 }
@@ -222,6 +276,33 @@ impl NumBytes for Enum {
   }
 }
 
+// Read an enum's states, rejecting a value given more then once, which a map
+// would otherwise keep only the last state of.
+fn unique_states<'de, D>(deserializer: D) -> Result<BTreeMap<i64, Name>, D::Error>
+    where D: Deserializer<'de> {
+    struct StatesVisitor;
+
+    impl<'de> Visitor<'de> for StatesVisitor {
+        type Value = BTreeMap<i64, Name>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a map of enum values to state names")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<BTreeMap<i64, Name>, A::Error> {
+            let mut map = BTreeMap::new();
+            while let Some((value, state)) = access.next_entry::<i64, Name>()? {
+                if map.insert(value, state).is_some() {
+                    return Err(de::Error::custom(format!("enum value {} is given more then once", value)));
+                }
+            }
+            Ok(map)
+        }
+    }
+
+    deserializer.deserialize_map(StatesVisitor)
+}
+
 #[derive(Eq, PartialEq, Debug, Hash, Clone, Deserialize, Serialize)]
 pub enum Prim {
     Int(IntPrim),
@@ -240,3 +321,27 @@ impl NumBytes for Prim {
   }
 }
 
+
+
+#[cfg(test)]
+mod test_prim {
+    use super::*;
+
+    #[test]
+    fn test_int_prim_constructors() {
+        let signed = [IntPrim::i8_be(), IntPrim::i8_le(), IntPrim::i16_be(), IntPrim::i16_le(),
+                      IntPrim::i32_be(), IntPrim::i32_le(), IntPrim::i64_be(), IntPrim::i64_le()];
+        let unsigned = [IntPrim::u8_be(), IntPrim::u8_le(), IntPrim::u16_be(), IntPrim::u16_le(),
+                        IntPrim::u32_be(), IntPrim::u32_le(), IntPrim::u64_be(), IntPrim::u64_le()];
+
+        for (index, (signed, unsigned)) in signed.iter().zip(unsigned.iter()).enumerate() {
+            assert_eq!(signed.signedness, Signedness::Signed);
+            assert_eq!(unsigned.signedness, Signedness::Unsigned);
+
+            let endianness = if index % 2 == 0 { Endianness::BigEndian } else { Endianness::LittleEndian };
+            assert_eq!((&signed.endianness, &unsigned.endianness), (&endianness, &endianness));
+            assert_eq!(signed.num_bytes(), 1 << (index / 2));
+            assert_eq!(signed.size, unsigned.size);
+        }
+    }
+}
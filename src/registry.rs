@@ -0,0 +1,224 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::io::Cursor;
+
+use types::*;
+use prim::*;
+use value::*;
+use packet::*;
+use decode::*;
+use hash::*;
+
+
+// A registry of packet definitions that keeps every version of each packet,
+// so data can be decoded with the definitions in use when it was recorded.
+// Versions are found either by their content hash, as stored in an index or
+// archive, or by the time range they were valid for.
+//
+// The versions of a packet can be given in RON as a list, oldest first, such as:
+//   [(valid_until: Some(100.0), packet: Seq("tlm", [...])),
+//    (valid_from: Some(100.0), packet: Seq("tlm", [...]))]
+// where the valid times are in the units of the packet's time tag.
+
+#[derive(PartialEq, Debug)]
+pub struct DefinitionVersion<T> {
+    pub hash: u64,
+    // the version is valid from valid_from (inclusive) until valid_until (exclusive).
+    // a missing bound is open ended.
+    pub valid_from: Option<f64>,
+    pub valid_until: Option<f64>,
+    pub packet: PacketDef<T>,
+}
+
+impl<T> DefinitionVersion<T> {
+    pub fn valid_at(&self, time: f64) -> bool {
+        self.valid_from.is_none_or(|from| time >= from) &&
+        self.valid_until.is_none_or(|until| time < until)
+    }
+}
+
+// A version of a packet as written in a definitions file
+#[derive(PartialEq, Debug, Deserialize, Serialize)]
+pub struct VersionDef<T> {
+    #[serde(default)]
+    pub valid_from: Option<f64>,
+    #[serde(default)]
+    pub valid_until: Option<f64>,
+    pub packet: PacketDef<T>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum VersionSelector {
+    Hash(u64),
+    Time(f64),
+    Latest,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct DefinitionRegistry<T> {
+    versions: HashMap<Name, Vec<DefinitionVersion<T>>>,
+}
+
+impl<T> Default for DefinitionRegistry<T> {
+    fn default() -> DefinitionRegistry<T> {
+        DefinitionRegistry { versions: HashMap::new() }
+    }
+}
+
+impl<T : DefHash> DefinitionRegistry<T> {
+    pub fn new() -> DefinitionRegistry<T> {
+        DefinitionRegistry::default()
+    }
+
+    // A registry of the versions of one packet, in the order given.
+    pub fn from_versions(name: &str, versions: Vec<VersionDef<T>>) -> DefinitionRegistry<T> {
+        let mut registry = DefinitionRegistry::new();
+
+        for version in versions {
+            registry.add(name, version.packet, version.valid_from, version.valid_until);
+        }
+
+        registry
+    }
+
+    // Add a version of a packet, returning its hash. Versions added later take
+    // precedence when their valid times overlap.
+    pub fn add(&mut self,
+               name:        &str,
+               packet:      PacketDef<T>,
+               valid_from:  Option<f64>,
+               valid_until: Option<f64>) -> u64 {
+        let hash = definition_hash(&packet);
+
+        self.versions.entry(name.to_string())
+                     .or_default()
+                     .push(DefinitionVersion { hash, valid_from, valid_until, packet });
+
+        hash
+    }
+
+    pub fn names(&self) -> Vec<&Name> {
+        let mut names: Vec<&Name> = self.versions.keys().collect();
+        names.sort();
+        names
+    }
+
+    pub fn versions(&self, name: &str) -> &[DefinitionVersion<T>] {
+        match self.versions.get(name) {
+            Some(versions) => versions,
+            None => &[],
+        }
+    }
+
+    // Find a version of any packet by its hash. Packets with identical definitions
+    // share a hash, in which case the first by name is found.
+    pub fn by_hash(&self, hash: u64) -> Option<(&Name, &DefinitionVersion<T>)> {
+        for name in self.names() {
+            if let Some(version) = self.versions(name).iter().find(|version| version.hash == hash) {
+                return Some((name, version));
+            }
+        }
+
+        None
+    }
+
+    pub fn at_time(&self, name: &str, time: f64) -> Option<&DefinitionVersion<T>> {
+        self.versions(name).iter().rev().find(|version| version.valid_at(time))
+    }
+
+    pub fn latest(&self, name: &str) -> Option<&DefinitionVersion<T>> {
+        self.versions(name).last()
+    }
+
+    pub fn select(&self, name: &str, selector: &VersionSelector) -> Option<&DefinitionVersion<T>> {
+        match selector {
+            VersionSelector::Hash(hash) => {
+                self.versions(name).iter().find(|version| version.hash == *hash)
+            },
+
+            VersionSelector::Time(time) => self.at_time(name, *time),
+
+            VersionSelector::Latest => self.latest(name),
+        }
+    }
+}
+
+impl DefinitionRegistry<Item> {
+    // Decode a packet with the selected version of its definition.
+    pub fn decode(&self,
+                  name:     &str,
+                  selector: &VersionSelector,
+                  bytes:    &[u8]) -> Result<ValueMap, String> {
+        let version = match self.select(name, selector) {
+            Some(version) => version,
+            None => return Err(format!("no version of {} for {:?}", name, selector)),
+        };
+
+        decode_layoutpacket(&version.packet, &mut Cursor::new(bytes))
+    }
+
+    // Decode a packet with the version valid at its time tag. The time tag must be
+    // at the same location in every version, such as in a packet header.
+    pub fn decode_at_time(&self,
+                          name:      &str,
+                          time_item: &LocItem,
+                          bytes:     &[u8]) -> Result<ValueMap, String> {
+        let time = decode_loc_item(time_item, &mut Cursor::new(bytes)).val.as_f64();
+
+        self.decode(name, &VersionSelector::Time(time), bytes)
+    }
+}
+
+
+#[cfg(test)]
+mod test_registry {
+    use super::*;
+    use *;
+
+    #[test]
+    fn test_registry_versions() {
+        let mut registry = DefinitionRegistry::new();
+
+        let version1 = registry.add("tlm",
+                                    seq("tlm".to_string(), vec!(u16_be("time"), u16_be("temp"))),
+                                    None, Some(100.0));
+        let version2 = registry.add("tlm",
+                                    seq("tlm".to_string(), vec!(u16_be("time"), u8_be("temp"), u8_be("mode"))),
+                                    Some(100.0), None);
+        assert!(version1 != version2);
+
+        let time_item = LocItem::new(vec!["time".to_string()], Prim::Int(IntPrim::u16_be()), 0);
+
+        let old = registry.decode_at_time("tlm", &time_item, &[0x00, 0x10, 0x01, 0x02]).unwrap();
+        assert_eq!(old.lookup(&"temp".to_string()), Some(Value::U16(0x0102)));
+
+        let new = registry.decode_at_time("tlm", &time_item, &[0x00, 0x70, 0x01, 0x02]).unwrap();
+        assert_eq!(new.lookup(&"temp".to_string()), Some(Value::U8(0x01)));
+        assert_eq!(new.lookup(&"mode".to_string()), Some(Value::U8(0x02)));
+
+        let by_hash = registry.decode("tlm", &VersionSelector::Hash(version1), &[0x00, 0x70, 0x01, 0x02]).unwrap();
+        assert_eq!(by_hash.lookup(&"temp".to_string()), Some(Value::U16(0x0102)));
+
+        assert_eq!(registry.by_hash(version2).unwrap().0, "tlm");
+        assert!(registry.select("tlm", &VersionSelector::Hash(0)).is_none());
+
+        // the same definition under several names is always found under the first
+        for name in ["tlm_c", "tlm_a", "tlm_b"].iter() {
+            registry.add(name, seq("aux".to_string(), vec!(u8_be("flag"))), None, None);
+        }
+        let aux = definition_hash(&seq("aux".to_string(), vec!(u8_be("flag"))));
+        assert_eq!(registry.by_hash(aux).unwrap().0, "tlm_a");
+
+        let text = "[(valid_until: Some(100.0), packet: Leaf((name: \"temp\", typ: Int((size: Bits16, signedness: Unsigned, endianness: BigEndian))))),
+                     (valid_from: Some(100.0), packet: Leaf((name: \"temp\", typ: Int((size: Bits8, signedness: Unsigned, endianness: BigEndian)))))]";
+        let versions: Vec<VersionDef<Item>> = ron::de::from_str(text).unwrap();
+        let registry = DefinitionRegistry::from_versions("temp", versions);
+        assert_eq!(registry.versions("temp").len(), 2);
+        assert_eq!(registry.at_time("temp", 150.0).unwrap().valid_from, Some(100.0));
+    }
+}
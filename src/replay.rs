@@ -0,0 +1,135 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use input::*;
+use index::*;
+
+
+// Replay recorded packets with their original timing. Each packet is held back
+// until its time since the first packet, divided by the speed, has passed, so
+// gaps in the recording are kept. Packets without a receive time, or timed
+// before the packet they follow, are sent right away.
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ReplayRate {
+    Original,
+    // a speed of 2.0 replays twice as fast as recorded
+    Scaled(f64),
+    // as fast as possible, ignoring the recorded timing
+    Unpaced,
+}
+
+pub struct Replay<I> {
+    packets: I,
+    rate: ReplayRate,
+    // the first packet's time, and when it was sent
+    start: Option<(f64, Instant)>,
+}
+
+impl<I> Replay<I> {
+    pub fn new(packets: I, rate: ReplayRate) -> Replay<I> {
+        Replay { packets, rate, start: None }
+    }
+
+    // how long to wait at the instant now before sending a packet received at the given time
+    fn delay(&mut self, time: Option<f64>, now: Instant) -> Option<Duration> {
+        let speed = match self.rate {
+            ReplayRate::Original => 1.0,
+            ReplayRate::Scaled(speed) => speed,
+            ReplayRate::Unpaced => return None,
+        };
+
+        let time = time?;
+        let (first_time, started) = *self.start.get_or_insert((time, now));
+
+        let offset = (time - first_time) / speed;
+        if offset.is_nan() || offset <= 0.0 {
+            return None;
+        }
+
+        let due = started + Duration::from_secs_f64(offset);
+        if due > now { Some(due - now) } else { None }
+    }
+}
+
+impl<I, P> Iterator for Replay<I>
+    where I: Iterator<Item=P>,
+          P: ReceiveTime {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        let packet = self.packets.next()?;
+
+        if let Some(delay) = self.delay(packet.receive_time(), Instant::now()) {
+            thread::sleep(delay);
+        }
+
+        Some(packet)
+    }
+}
+
+// Packets from a raw capture found through its index, timed by their time tags.
+// An entry without a time tag is sent right after the packet before it.
+// None if an entry runs past the end of the capture.
+pub fn indexed_packets(capture: &[u8], entries: &[&IndexEntry]) -> Option<Vec<TimedPacket>> {
+    let mut time = entries.iter().find_map(|entry| entry.time).unwrap_or(0.0);
+
+    entries.iter().map(|entry| {
+        time = entry.time.unwrap_or(time);
+        entry.bytes(capture).map(|bytes| TimedPacket { time, bytes: bytes.to_vec() })
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod test_replay {
+    use super::*;
+
+    #[test]
+    fn test_replay_timing() {
+        let packets = || vec![TimedPacket { time: 100.0, bytes: vec![1] },
+                              TimedPacket { time: 100.1, bytes: vec![2] },
+                              // out of order, so sent right away
+                              TimedPacket { time: 99.0, bytes: vec![3] },
+                              TimedPacket { time: 100.2, bytes: vec![4] }];
+
+        // packets are replayed in their recorded order, however they are timed
+        let replayed: Vec<TimedPacket> = Replay::new(packets().into_iter(), ReplayRate::Unpaced).collect();
+        assert_eq!(replayed, packets());
+
+        let millis = |delay: Option<Duration>| delay.map(|delay| (delay.as_secs_f64() * 1000.0).round() as u64);
+
+        let started = Instant::now();
+        let mut replay = Replay::new(packets().into_iter(), ReplayRate::Scaled(2.0));
+        assert_eq!(millis(replay.delay(Some(100.0), started)), None);
+        assert_eq!(millis(replay.delay(Some(100.1), started)), Some(50));
+        assert_eq!(millis(replay.delay(Some(100.2), started + Duration::from_millis(30))), Some(70));
+        assert_eq!(millis(replay.delay(Some(99.0), started)), None);
+        // already due
+        assert_eq!(millis(replay.delay(Some(100.2), started + Duration::from_millis(200))), None);
+        assert_eq!(millis(replay.delay(None, started)), None);
+
+        let mut replay = Replay::new(packets().into_iter(), ReplayRate::Original);
+        assert_eq!(millis(replay.delay(Some(100.0), started)), None);
+        assert_eq!(millis(replay.delay(Some(100.1), started)), Some(100));
+
+        let mut replay = Replay::new(packets().into_iter(), ReplayRate::Unpaced);
+        assert_eq!(millis(replay.delay(Some(100.0), started)), None);
+        assert_eq!(millis(replay.delay(Some(100.1), started)), None);
+
+        let entries = [IndexEntry { offset: 0, length: 2, id: None, seq: None, time: None },
+                           IndexEntry { offset: 2, length: 1, id: None, seq: None, time: Some(5.0) }];
+        let entries: Vec<&IndexEntry> = entries.iter().collect();
+        assert_eq!(indexed_packets(&[7, 8, 9], &entries),
+                   Some(vec![TimedPacket { time: 5.0, bytes: vec![7, 8] }, TimedPacket { time: 5.0, bytes: vec![9] }]));
+        // an index of a longer capture
+        assert_eq!(indexed_packets(&[7, 8], &entries), None);
+    }
+}
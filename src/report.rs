@@ -0,0 +1,494 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::cmp;
+
+use types::*;
+use prim::*;
+use layout::*;
+use packet::*;
+
+
+// Interface control document style reports of definitions, with a table of each
+// item's location, type, states, conversion and limits, and a summary of the
+// packet's size. Items follow the paths of the decoded ValueMap. Every subcom
+// branch is listed, with the values that select it, and variable array elements
+// are named "name[]".
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ReportRow {
+    pub path: LocPath,
+    // None for items after a part of the packet whose size varies
+    pub byte_offset: Option<u64>,
+    // where a bit field starts within its byte, counting from the most significant bit
+    pub bit_offset: u32,
+    pub bits: u64,
+    pub typ: String,
+    // None for single bytes and bit fields, which have no byte order
+    pub endianness: Option<Endianness>,
+    pub states: Vec<(i64, Name)>,
+    pub conversion: Option<Conversion>,
+    pub limits: Option<Limits>,
+    // the subcom branches the item is in, such as "mode = 2"
+    pub conditions: Vec<String>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Report {
+    pub title: String,
+    pub rows: Vec<ReportRow>,
+    pub size: PacketSize,
+}
+
+struct ReportState {
+    rows: Vec<ReportRow>,
+    loc_path: LocPath,
+    conditions: Vec<String>,
+}
+
+impl ReportState {
+    fn new() -> ReportState {
+        ReportState { rows: Vec::new(), loc_path: LocPath::new(), conditions: Vec::new() }
+    }
+
+    fn push_item(&mut self, item: &Item, byte_offset: Option<u64>) {
+        let typ = &item.typ;
+
+        let mut path = self.loc_path.clone();
+        path.push(item.name.clone());
+
+        let states = match typ {
+            Prim::Enum(enum_prim) => enum_prim.map.iter().map(|(value, state)| (*value, state.clone())).collect(),
+            _ => Vec::new(),
+        };
+
+        let endianness = match typ {
+            _ if typ.num_bytes() == 1 => None,
+            Prim::Int(int_prim) => Some(int_prim.endianness.clone()),
+            Prim::Float(FloatPrim::F32(endianness)) | Prim::Float(FloatPrim::F64(endianness)) => Some(endianness.clone()),
+            Prim::Enum(enum_prim) => Some(enum_prim.int_prim.endianness.clone()),
+        };
+
+        self.rows.push(ReportRow { path,
+                                   byte_offset,
+                                   bit_offset: 0,
+                                   bits: 8 * typ.num_bytes(),
+                                   typ: type_name(typ),
+                                   endianness,
+                                   states,
+                                   conversion: item.conversion.clone(),
+                                   limits: item.limits.clone(),
+                                   conditions: self.conditions.clone() });
+    }
+}
+
+pub fn report_packet(title: &str, packet: &LayoutPacketDef) -> Report {
+    let mut state = ReportState::new();
+
+    packet_rows(packet, Some(0), &mut state);
+
+    Report { title: title.to_string(), rows: state.rows, size: packet.size() }
+}
+
+// A layout has a fixed size, so it is never padded when reported. Its alignment
+// and misaligned items are found from its rows.
+pub fn report_layout(title: &str, layout: &Layout) -> Report {
+    let mut state = ReportState::new();
+
+    layout_rows(layout, 0, &mut state);
+
+    let mut alignment = 1;
+    let mut misaligned = Vec::new();
+    for row in state.rows.iter().filter(|row| row.bits % 8 == 0 && row.bit_offset == 0) {
+        let num_bytes = row.bits / 8;
+        alignment = cmp::max(alignment, num_bytes);
+
+        if let Some(offset) = row.byte_offset {
+            if !offset.is_multiple_of(num_bytes) {
+                misaligned.push((row.path.clone(), offset));
+            }
+        }
+    }
+
+    let num_bytes = layout.num_bytes();
+    let size = PacketSize { min: num_bytes, max: Some(num_bytes), alignment, misaligned, padding: None };
+
+    Report { title: title.to_string(), rows: state.rows, size }
+}
+
+// Add the rows for a part of a packet starting at the given offset, returning
+// where it ends, or None if that varies.
+fn packet_rows(packet: &LayoutPacketDef, offset: Option<u64>, state: &mut ReportState) -> Option<u64> {
+    match packet {
+        PacketDef::Seq(name, packets) => {
+            state.loc_path.push(name.clone());
+            let end = packets.iter().fold(offset, |offset, packet| packet_rows(packet, offset, state));
+            state.loc_path.pop();
+
+            end
+        },
+
+        PacketDef::Subcom(_, items, branches, default) => {
+            let mut ends = Vec::new();
+
+            for (keys, packet) in branches {
+                state.conditions.push(branch_condition(items, keys));
+                ends.push(packet_rows(packet, offset, state));
+                state.conditions.pop();
+            }
+
+            if let Some(packet) = default {
+                state.conditions.push("otherwise".to_string());
+                ends.push(packet_rows(packet, offset, state));
+                state.conditions.pop();
+            }
+
+            // the subcom only has a fixed end if every branch ends in the same place
+            match ends.first() {
+                Some(first) if ends.iter().all(|end| end == first) => *first,
+                Some(_) => None,
+                None => offset,
+            }
+        },
+
+        PacketDef::Array(name, ArrSize::Fixed(num_elements), packet) => {
+            let mut offset = offset;
+            for index in 0 .. *num_elements {
+                state.loc_path.push(format!("{}[{}]", name, index));
+                offset = packet_rows(packet, offset, state);
+                state.loc_path.pop();
+            }
+
+            offset
+        },
+
+        PacketDef::Array(name, ArrSize::Var(count), packet) => {
+            state.loc_path.push(format!("{}[]", name));
+            state.conditions.push(format!("repeated {} times", count));
+            packet_rows(packet, None, state);
+            state.conditions.pop();
+            state.loc_path.pop();
+
+            None
+        },
+
+        PacketDef::Leaf(item) => {
+            state.push_item(item, offset);
+
+            offset.map(|offset| offset + item.num_bytes())
+        },
+    }
+}
+
+fn layout_rows(layout: &Layout, offset: u64, state: &mut ReportState) -> u64 {
+    match layout {
+        Layout::Prim(item) => {
+            state.push_item(item, Some(offset));
+
+            offset + item.num_bytes()
+        },
+
+        Layout::Seq(name, layouts) => {
+            state.loc_path.push(name.clone());
+            let end = layouts.iter().fold(offset, |offset, layout| layout_rows(layout, offset, state));
+            state.loc_path.pop();
+
+            end
+        },
+
+        Layout::All(name, layouts) => {
+            state.loc_path.push(name.clone());
+            for layout in layouts.iter() {
+                layout_rows(layout, offset, state);
+            }
+            state.loc_path.pop();
+
+            offset + layout.num_bytes()
+        },
+
+        Layout::Array(name, num_elements, layout) => {
+            let mut offset = offset;
+            for index in 0 .. *num_elements {
+                state.loc_path.push(format!("{}[{}]", name, index));
+                offset = layout_rows(layout, offset, state);
+                state.loc_path.pop();
+            }
+
+            offset
+        },
+
+        // bit fields are read most significant bit first
+        Layout::Bits(bit_prim) => {
+            let mut bit = 0;
+            for (name, num_bits, int_prim) in bit_prim.entries.iter() {
+                let mut path = state.loc_path.clone();
+                path.push(name.clone());
+
+                state.rows.push(ReportRow { path,
+                                            byte_offset: Some(offset + bit / 8),
+                                            bit_offset: (bit % 8) as u32,
+                                            bits: *num_bits as u64,
+                                            typ: type_name(&Prim::Int(int_prim.clone())),
+                                            endianness: None,
+                                            states: Vec::new(),
+                                            conversion: None,
+                                            limits: None,
+                                            conditions: state.conditions.clone() });
+                bit += *num_bits as u64;
+            }
+
+            offset + bit_prim.num_bytes
+        },
+    }
+}
+
+fn branch_condition(items: &[Item], keys: &[SubcomKey]) -> String {
+    let conditions: Vec<String> =
+        items.iter().zip(keys.iter())
+             .filter_map(|(item, key)| match key {
+                 SubcomKey::Value(value) => Some(format!("{} = {}", item.name, value)),
+                 SubcomKey::Range(low, high) => Some(format!("{} <= {} <= {}", low, item.name, high)),
+                 SubcomKey::Any => None,
+             })
+             .collect();
+
+    if conditions.is_empty() {
+        "always".to_string()
+    } else {
+        conditions.join(" and ")
+    }
+}
+
+pub fn type_name(typ: &Prim) -> String {
+    match typ {
+        Prim::Int(int_prim) => int_name(int_prim),
+        Prim::Float(FloatPrim::F32(_)) => "f32".to_string(),
+        Prim::Float(FloatPrim::F64(_)) => "f64".to_string(),
+        Prim::Enum(enum_prim) => format!("enum {}", int_name(&enum_prim.int_prim)),
+    }
+}
+
+// a conversion as a formula of the raw value x, such as "0.5 + 0.01 x"
+pub fn conversion_text(conversion: &Conversion) -> String {
+    match conversion {
+        Conversion::Polynomial(coefficients) => {
+            let terms: Vec<String> =
+                coefficients.iter()
+                            .enumerate()
+                            .filter(|(_, coefficient)| **coefficient != 0.0)
+                            .map(|(power, coefficient)| match power {
+                                0 => format!("{}", coefficient),
+                                1 => format!("{} x", coefficient),
+                                _ => format!("{} x^{}", coefficient, power),
+                            })
+                            .collect();
+
+            if terms.is_empty() { "0".to_string() } else { terms.join(" + ") }
+        },
+    }
+}
+
+pub fn limits_text(limits: &Limits) -> String {
+    let ranges: Vec<String> =
+        [("warning", limits.warning), ("alarm", limits.alarm)].iter()
+            .filter_map(|(kind, range)| range.map(|(low, high)| format!("{} {} to {}", kind, low, high)))
+            .collect();
+
+    if ranges.is_empty() { "-".to_string() } else { ranges.join(", ") }
+}
+
+fn int_name(int_prim: &IntPrim) -> String {
+    let sign = match int_prim.signedness {
+        Signedness::Unsigned => "u",
+        Signedness::Signed   => "i",
+    };
+
+    format!("{}{}", sign, 8 * int_prim.num_bytes())
+}
+
+// A line for each fact about a packet's size.
+pub fn size_summary(size: &PacketSize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    lines.push(match (size.fixed(), size.max) {
+        (Some(fixed), _) => format!("size {} bytes", fixed),
+        (None, Some(max)) => format!("size {} to {} bytes", size.min, max),
+        (None, None) => format!("size at least {} bytes, with no limit", size.min),
+    });
+
+    match size.padding {
+        Some(padding) => lines.push(format!("alignment {}, {} bytes of padding when naturally aligned", size.alignment, padding)),
+        None => lines.push(format!("alignment {}", size.alignment)),
+    }
+
+    for (loc_path, offset) in size.misaligned.iter() {
+        lines.push(format!("misaligned {} at byte {}", loc_path.join("."), offset));
+    }
+
+    lines
+}
+
+const HEADERS: [&str; 10] = ["Path", "Byte", "Bit", "Bits", "Type", "Endianness",
+                             "States", "Conversion", "Limits", "Present When"];
+
+// the text of each column of a row
+fn columns(row: &ReportRow) -> Vec<String> {
+    let endianness = match row.endianness {
+        Some(Endianness::BigEndian) => "big",
+        Some(Endianness::LittleEndian) => "little",
+        None => "-",
+    };
+
+    let states: Vec<String> = row.states.iter().map(|(value, state)| format!("{} = {}", value, state)).collect();
+
+    vec![row.path.join("."),
+         row.byte_offset.map_or("variable".to_string(), |offset| offset.to_string()),
+         row.bit_offset.to_string(),
+         row.bits.to_string(),
+         row.typ.clone(),
+         endianness.to_string(),
+         if states.is_empty() { "-".to_string() } else { states.join(", ") },
+         row.conversion.as_ref().map_or("-".to_string(), conversion_text),
+         row.limits.as_ref().map_or("-".to_string(), limits_text),
+         if row.conditions.is_empty() { "always".to_string() } else { row.conditions.join(", ") }]
+}
+
+pub fn markdown(reports: &[Report]) -> String {
+    let mut text = String::new();
+
+    text.push_str("# Interface Control Document\n");
+
+    for report in reports.iter() {
+        text.push_str(&format!("\n## {}\n\n", report.title));
+
+        for line in size_summary(&report.size) {
+            text.push_str(&format!("* {}\n", line));
+        }
+        text.push('\n');
+
+        text.push_str(&format!("| {} |\n", HEADERS.join(" | ")));
+        text.push_str(&format!("|{}\n", "---|".repeat(HEADERS.len())));
+        for row in report.rows.iter() {
+            let cells: Vec<String> = columns(row).iter().map(|cell| cell.replace('|', "\\|")).collect();
+            text.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+    }
+
+    text
+}
+
+pub fn html(reports: &[Report]) -> String {
+    let mut text = String::new();
+
+    text.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    text.push_str("<title>Interface Control Document</title>\n");
+    text.push_str("<style>table { border-collapse: collapse; } th, td { border: 1px solid #888; padding: 2px 6px; }</style>\n");
+    text.push_str("</head>\n<body>\n<h1>Interface Control Document</h1>\n");
+
+    for report in reports.iter() {
+        text.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape_html(&report.title)));
+        for line in size_summary(&report.size) {
+            text.push_str(&format!("<li>{}</li>\n", escape_html(&line)));
+        }
+        text.push_str("</ul>\n<table>\n<tr>");
+
+        for header in HEADERS.iter() {
+            text.push_str(&format!("<th>{}</th>", header));
+        }
+        text.push_str("</tr>\n");
+
+        for row in report.rows.iter() {
+            text.push_str("<tr>");
+            for cell in columns(row) {
+                text.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+            }
+            text.push_str("</tr>\n");
+        }
+        text.push_str("</table>\n");
+    }
+
+    text.push_str("</body>\n</html>\n");
+
+    text
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+#[cfg(test)]
+mod test_report {
+    use super::*;
+    use *;
+
+    #[test]
+    fn test_report_packet() {
+        let mut map = BTreeMap::new();
+        map.insert(0, "OFF".to_string());
+        map.insert(1, "ON".to_string());
+        let power = leaf(item("power", Prim::Enum(Enum { map, int_prim: IntPrim::u8_be() })));
+
+        let mut volts = item("volts", Prim::Int(IntPrim::u16_le()));
+        volts.conversion = Some(Conversion::Polynomial(vec![0.5, 0.01]));
+        volts.limits = Some(Limits { warning: Some((4.5, 5.5)), alarm: Some((4.0, 6.0)) });
+
+        let packet = seq("pkt".to_string(),
+                         vec![u8_be("mode"),
+                              PacketDef::Subcom("by_mode".to_string(),
+                                                vec![item("mode", Prim::Int(IntPrim::u8_be()))],
+                                                vec![(vec![SubcomKey::Value(Value::U8(1))], leaf(volts))],
+                                                Some(Box::new(power))),
+                              u8_be("count"),
+                              array_var("samples".to_string(), "count".to_string(), i16_be("sample")),
+                              f32_be("temp")]);
+
+        let report = report_packet("pkt", &packet);
+        let paths: Vec<String> = report.rows.iter().map(|row| row.path.join(".")).collect();
+        assert_eq!(paths, vec!["pkt.mode", "pkt.volts", "pkt.power", "pkt.count", "pkt.samples[].sample", "pkt.temp"]);
+
+        let offsets: Vec<Option<u64>> = report.rows.iter().map(|row| row.byte_offset).collect();
+        assert_eq!(offsets, vec![Some(0), Some(1), Some(1), None, None, None]);
+
+        let types: Vec<(String, Option<Endianness>)> = report.rows.iter().map(|row| (row.typ.clone(), row.endianness.clone())).collect();
+        assert_eq!(types, vec![("u8".to_string(), None),
+                               ("u16".to_string(), Some(Endianness::LittleEndian)),
+                               ("enum u8".to_string(), None),
+                               ("u8".to_string(), None),
+                               ("i16".to_string(), Some(Endianness::BigEndian)),
+                               ("f32".to_string(), Some(Endianness::BigEndian))]);
+
+        let conditions: Vec<String> = report.rows.iter().map(|row| row.conditions.join(", ")).collect();
+        assert_eq!(conditions, vec!["", "mode = 1", "otherwise", "", "repeated count times", ""]);
+        assert_eq!(report.rows[2].states, vec![(0, "OFF".to_string()), (1, "ON".to_string())]);
+
+        let text = markdown(&[report]);
+        assert!(text.contains("| pkt.power | 1 | 0 | 8 | enum u8 | - | 0 = OFF, 1 = ON | - | - | otherwise |"));
+        assert!(text.contains("| pkt.volts | 1 | 0 | 16 | u16 | little | - | 0.5 + 0.01 x | warning 4.5 to 5.5, alarm 4 to 6 | mode = 1 |"));
+        assert!(text.contains("* size 7 to 518 bytes"));
+
+        // bit fields within a layout
+        let layout = Layout::Seq("hdr".to_string(),
+                                 vec![Layout::Prim(item("sync", Prim::Int(IntPrim::u8_be()))),
+                                      Layout::Bits(BitPrim { entries: vec![("version".to_string(), 3, IntPrim::u8_be()),
+                                                                           ("apid".to_string(), 11, IntPrim::u16_be()),
+                                                                           ("flag".to_string(), 2, IntPrim::u8_be())],
+                                                             num_bytes: 2 }),
+                                      Layout::Prim(item("length", Prim::Int(IntPrim::u16_be())))]);
+
+        let report = report_layout("hdr", &layout);
+        let bits: Vec<(Option<u64>, u32, u64)> = report.rows.iter().map(|row| (row.byte_offset, row.bit_offset, row.bits)).collect();
+        assert_eq!(bits, vec![(Some(0), 0, 8), (Some(1), 0, 3), (Some(1), 3, 11), (Some(2), 6, 2), (Some(3), 0, 16)]);
+        assert_eq!(report.size.fixed(), Some(5));
+        assert_eq!(report.size.misaligned, vec![(vec!["hdr".to_string(), "length".to_string()], 3)]);
+
+        assert!(html(&[report]).contains("<td>hdr.apid</td><td>1</td><td>3</td><td>11</td><td>u16</td>"));
+    }
+}
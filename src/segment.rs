@@ -0,0 +1,341 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::collections::VecDeque;
+use std::io::Cursor;
+
+use value::*;
+use packet::*;
+use decode::*;
+use input::*;
+use ccsds::*;
+
+
+// Reassemble data products that are split across space packets using the
+// sequence flags. A product starts with a first segment, continues with any
+// number of continuation segments, and ends with a last segment, all on one
+// APID with consecutive sequence counts. Unsegmented packets pass straight through.
+//
+// Segments are joined in sequence count order, so a product is put together
+// even if its segments arrive out of order, as long as its first segment arrives
+// before the rest. A product missing segments is dropped when the next product
+// on its APID starts.
+
+const SEQ_COUNT_MODULUS: u16 = 0x4000;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum SegmentProblem {
+    // a product that was never completed, with the sequence counts known to be
+    // missing. if its last segment never arrived, the segments after the highest
+    // one received are missing as well.
+    Incomplete { apid: u16, seq_count: u16, missing: Vec<u16> },
+    // a segment arrived after one with a later sequence count
+    OutOfOrder { apid: u16, seq_count: u16 },
+    // a continuation or last segment arrived without a first segment before it
+    Orphan { apid: u16, seq_count: u16 },
+}
+
+// A reassembled product, as a single unsegmented space packet: the primary header
+// of its first segment followed by the data fields of all its segments.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Reassembled {
+    pub apid: u16,
+    // the sequence count of the first segment
+    pub seq_count: u16,
+    pub segments: usize,
+    // the receive time of the segment that completed the product, if known
+    pub time: Option<f64>,
+    pub bytes: Vec<u8>,
+}
+
+impl Reassembled {
+    // the product's data, after the primary header
+    pub fn data(&self) -> &[u8] {
+        &self.bytes[PRIMARY_HEADER_BYTES..]
+    }
+
+    // Decode the product's data with a definition for the whole product.
+    pub fn decode(&self, packet: &LayoutPacketDef) -> Result<ValueMap, String> {
+        let min_length = packet.size().min as usize;
+        if self.data().len() < min_length {
+            return Err(format!("product on APID {} has {} bytes of data, but its definition needs at least {}",
+                               self.apid, self.data().len(), min_length));
+        }
+
+        decode_layoutpacket(packet, &mut Cursor::new(self.data()))
+    }
+}
+
+impl AsRef<[u8]> for Reassembled {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl ReceiveTime for Reassembled {
+    fn receive_time(&self) -> Option<f64> {
+        self.time
+    }
+}
+
+// a product in progress
+#[derive(Debug, Clone)]
+struct Group {
+    header: PrimaryHeader,
+    // data fields by their sequence count offset from the first segment
+    segments: BTreeMap<u16, Vec<u8>>,
+    // the offset of the last segment, once it has arrived
+    last: Option<u16>,
+}
+
+impl Group {
+    fn highest(&self) -> u16 {
+        *self.segments.keys().next_back().unwrap_or(&0)
+    }
+
+    fn missing(&self) -> Vec<u16> {
+        let end = self.last.unwrap_or_else(|| self.highest());
+        (1..end).filter(|offset| !self.segments.contains_key(offset))
+                .map(|offset| self.header.seq_count.wrapping_add(offset) % SEQ_COUNT_MODULUS)
+                .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Reassembler {
+    groups: HashMap<u16, Group>,
+    header_bytes: usize,
+    problems: Vec<SegmentProblem>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    // The number of bytes at the start of each segment's data field that belong
+    // to the segment rather then the product, such as a secondary header. They
+    // are kept from the first segment only.
+    pub fn with_header_bytes(mut self, header_bytes: usize) -> Reassembler {
+        self.header_bytes = header_bytes;
+        self
+    }
+
+    // the problems found since last taken
+    pub fn take_problems(&mut self) -> Vec<SegmentProblem> {
+        self.problems.drain(..).collect()
+    }
+
+    // Take in the next space packet, returning the product it completes, if any.
+    // Packets too short for the length in their header are ignored.
+    pub fn push(&mut self, packet: &[u8], time: Option<f64>) -> Option<Reassembled> {
+        let header = PrimaryHeader::parse(packet)?;
+        if packet.len() < header.packet_length() {
+            return None;
+        }
+        let data = &packet[PRIMARY_HEADER_BYTES..header.packet_length()];
+
+        match header.seq_flags {
+            SeqFlags::Unsegmented => {
+                Some(Reassembled { apid: header.apid,
+                                   seq_count: header.seq_count,
+                                   segments: 1,
+                                   time,
+                                   bytes: packet[..header.packet_length()].to_vec() })
+            },
+
+            SeqFlags::First => {
+                if let Some(group) = self.groups.remove(&header.apid) {
+                    self.incomplete(group);
+                }
+
+                let mut segments = BTreeMap::new();
+                segments.insert(0, data.to_vec());
+                self.groups.insert(header.apid, Group { header, segments, last: None });
+
+                None
+            },
+
+            SeqFlags::Continuation | SeqFlags::Last => {
+                let complete = {
+                    let group = match self.groups.get_mut(&header.apid) {
+                        Some(group) => group,
+                        None => {
+                            self.problems.push(SegmentProblem::Orphan { apid: header.apid, seq_count: header.seq_count });
+                            return None;
+                        },
+                    };
+
+                    let offset = header.seq_count.wrapping_sub(group.header.seq_count) % SEQ_COUNT_MODULUS;
+                    // NOTE a repeated segment replaces the earlier copy
+                    if offset < group.highest() && !group.segments.contains_key(&offset) {
+                        self.problems.push(SegmentProblem::OutOfOrder { apid: header.apid, seq_count: header.seq_count });
+                    }
+
+                    let data = if data.len() >= self.header_bytes { &data[self.header_bytes..] } else { &[][..] };
+                    group.segments.insert(offset, data.to_vec());
+                    if header.seq_flags == SeqFlags::Last {
+                        group.last = Some(offset);
+                    }
+
+                    group.last.is_some_and(|last| group.segments.range(..=last).count() == last as usize + 1)
+                };
+
+                if !complete {
+                    return None;
+                }
+
+                let group = self.groups.remove(&header.apid).unwrap();
+                let last = group.last.unwrap();
+
+                let mut first = group.header;
+                first.seq_flags = SeqFlags::Unsegmented;
+                let mut bytes = first.to_bytes().to_vec();
+                for (_, data) in group.segments.range(..=last) {
+                    bytes.extend_from_slice(data);
+                }
+
+                // NOTE products of more then 65536 bytes do not fit the length field, which is left at its largest
+                let length = (bytes.len() - PRIMARY_HEADER_BYTES).saturating_sub(1).min(0xFFFF) as u16;
+                bytes[4] = (length >> 8) as u8;
+                bytes[5] = length as u8;
+
+                Some(Reassembled { apid: header.apid,
+                                   seq_count: group.header.seq_count,
+                                   segments: last as usize + 1,
+                                   time,
+                                   bytes })
+            },
+        }
+    }
+
+    // Drop any products still in progress, as at the end of the data, reporting them as incomplete.
+    pub fn finish(&mut self) {
+        let groups: Vec<Group> = self.groups.drain().map(|(_, group)| group).collect();
+        for group in groups {
+            self.incomplete(group);
+        }
+    }
+
+    fn incomplete(&mut self, group: Group) {
+        self.problems.push(SegmentProblem::Incomplete { apid: group.header.apid,
+                                                        seq_count: group.header.seq_count,
+                                                        missing: group.missing() });
+    }
+}
+
+// Reassembled products from a stream of space packets, such as the output of
+// a framer or TM frame demux.
+pub struct Reassembly<I> {
+    packets: I,
+    reassembler: Reassembler,
+    finished: bool,
+    ready: VecDeque<Reassembled>,
+}
+
+impl<I> Reassembly<I> {
+    pub fn new(packets: I, reassembler: Reassembler) -> Reassembly<I> {
+        Reassembly { packets, reassembler, finished: false, ready: VecDeque::new() }
+    }
+
+    pub fn reassembler_mut(&mut self) -> &mut Reassembler {
+        &mut self.reassembler
+    }
+}
+
+impl<I, P> Iterator for Reassembly<I>
+    where I: Iterator<Item=P>,
+          P: AsRef<[u8]> + ReceiveTime {
+    type Item = Reassembled;
+
+    fn next(&mut self) -> Option<Reassembled> {
+        loop {
+            if let Some(product) = self.ready.pop_front() {
+                return Some(product);
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            match self.packets.next() {
+                Some(packet) => {
+                    if let Some(product) = self.reassembler.push(packet.as_ref(), packet.receive_time()) {
+                        self.ready.push_back(product);
+                    }
+                },
+
+                None => {
+                    self.reassembler.finish();
+                    self.finished = true;
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_segment {
+    use super::*;
+    use *;
+
+    fn segment(apid: u16, seq_flags: SeqFlags, seq_count: u16, data: &[u8]) -> Vec<u8> {
+        let header = PrimaryHeader { version: 0,
+                                     packet_type: PacketType::Telemetry,
+                                     sec_header: false,
+                                     apid,
+                                     seq_flags,
+                                     seq_count,
+                                     length: data.len() as u16 - 1 };
+
+        let mut packet = header.to_bytes().to_vec();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn test_reassembler() {
+        let mut reassembler = Reassembler::new().with_header_bytes(1);
+
+        // segments arrive out of order and wrap the sequence count, interleaved with another APID
+        assert!(reassembler.push(&segment(5, SeqFlags::First, 0x3FFE, &[0xEE, 0x00, 0x01]), None).is_none());
+        assert!(reassembler.push(&segment(5, SeqFlags::Last, 0x0001, &[0xEE, 0x06]), None).is_none());
+        let single = reassembler.push(&segment(6, SeqFlags::Unsegmented, 7, &[0x09]), Some(1.0)).unwrap();
+        assert_eq!(single.data(), &[0x09]);
+        assert!(reassembler.push(&segment(5, SeqFlags::Continuation, 0x3FFF, &[0xEE, 0x02, 0x03]), None).is_none());
+        let product = reassembler.push(&segment(5, SeqFlags::Continuation, 0x0000, &[0xEE, 0x04, 0x05]), Some(2.0)).unwrap();
+
+        assert_eq!(product.segments, 4);
+        assert_eq!(product.seq_count, 0x3FFE);
+        assert_eq!(product.time, Some(2.0));
+        assert_eq!(product.data(), &[0xEE, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        let header = PrimaryHeader::parse(&product.bytes).unwrap();
+        assert_eq!(header.seq_flags, SeqFlags::Unsegmented);
+        assert_eq!(header.packet_length(), product.bytes.len());
+
+        let value_map = product.decode(&seq("dump".to_string(), vec![u8_be("id"), u32_be("word0")])).unwrap();
+        assert_eq!(value_map.lookup(&"word0".to_string()), Some(Value::U32(0x00010203)));
+        assert!(product.decode(&seq("dump".to_string(), vec![u8_be("id"), u64_be("word0")])).is_err());
+        assert_eq!(reassembler.take_problems(), vec![SegmentProblem::OutOfOrder { apid: 5, seq_count: 0x3FFF },
+                                                    SegmentProblem::OutOfOrder { apid: 5, seq_count: 0x0000 }]);
+
+        // missing and orphaned segments are reported
+        let packets = vec![segment(5, SeqFlags::Continuation, 10, &[0xEE, 1]),
+                           segment(5, SeqFlags::First, 20, &[0xEE, 1]),
+                           segment(5, SeqFlags::Continuation, 22, &[0xEE, 1]),
+                           segment(5, SeqFlags::First, 30, &[0xEE, 1]),
+                           segment(5, SeqFlags::Last, 31, &[0xEE, 1]),
+                           segment(5, SeqFlags::First, 40, &[0xEE, 1])];
+        let mut reassembly = Reassembly::new(packets.into_iter(), reassembler);
+        assert_eq!(reassembly.by_ref().map(|product| product.seq_count).collect::<Vec<u16>>(), vec![30]);
+        assert_eq!(reassembly.reassembler_mut().take_problems(),
+                   vec![SegmentProblem::Orphan { apid: 5, seq_count: 10 },
+                        SegmentProblem::Incomplete { apid: 5, seq_count: 20, missing: vec![21] },
+                        SegmentProblem::Incomplete { apid: 5, seq_count: 40, missing: vec![] }]);
+    }
+}
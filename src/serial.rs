@@ -0,0 +1,165 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate libc;
+
+use std::io;
+use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use framing::*;
+
+
+// Serial port input for UART links, such as from a microcontroller or an
+// instrument like the VN200. The port is put in raw mode at the given baud
+// rate, and reads block until at least one byte arrives. Reads return whatever
+// bytes are available, so packets are recovered with a framer.
+pub struct SerialPort {
+    file: File,
+}
+
+fn baud_rate(baud: u32) -> Option<libc::speed_t> {
+    let speed = match baud {
+        1200   => libc::B1200,
+        2400   => libc::B2400,
+        4800   => libc::B4800,
+        9600   => libc::B9600,
+        19200  => libc::B19200,
+        38400  => libc::B38400,
+        57600  => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        #[cfg(target_os = "linux")]
+        460800 => libc::B460800,
+        #[cfg(target_os = "linux")]
+        921600 => libc::B921600,
+        _ => return None,
+    };
+
+    Some(speed)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+impl SerialPort {
+    pub fn open(path: &str, baud: u32) -> io::Result<SerialPort> {
+        let speed = match baud_rate(baud) {
+            Some(speed) => speed,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              format!("unsupported baud rate {}", baud))),
+        };
+
+        // NOTE the port must not become our controlling terminal
+        let file = OpenOptions::new().read(true)
+                                     .write(true)
+                                     .custom_flags(libc::O_NOCTTY)
+                                     .open(path)?;
+        let fd = file.as_raw_fd();
+
+        unsafe {
+            let mut termios: libc::termios = mem::zeroed();
+            check(libc::tcgetattr(fd, &mut termios))?;
+
+            libc::cfmakeraw(&mut termios);
+            termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+
+            check(libc::cfsetispeed(&mut termios, speed))?;
+            check(libc::cfsetospeed(&mut termios, speed))?;
+            check(libc::tcsetattr(fd, libc::TCSANOW, &termios))?;
+
+            // drop anything received before the port was configured
+            check(libc::tcflush(fd, libc::TCIFLUSH))?;
+        }
+
+        Ok(SerialPort { file })
+    }
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// Open a serial port and frame the packets received on it.
+pub fn serial_source<F : Framer>(path: &str, baud: u32, framer: F) -> io::Result<FrameReader<SerialPort, F>> {
+    Ok(FrameReader::new(SerialPort::open(path, baud)?, framer))
+}
+
+
+#[cfg(test)]
+mod test_serial {
+    use super::*;
+    use std::ffi::CStr;
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+    use std::time::Duration;
+    use prim::*;
+
+    // open a pseudo terminal pair, returning the master side and the path of the slave side
+    fn open_pty() -> (File, String) {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            check(libc::grantpt(fd)).unwrap();
+            check(libc::unlockpt(fd)).unwrap();
+
+            let mut name = [0 as libc::c_char; 128];
+            check(libc::ptsname_r(fd, name.as_mut_ptr(), name.len())).unwrap();
+            let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().to_string();
+
+            (File::from_raw_fd(fd), path)
+        }
+    }
+
+    #[test]
+    fn test_serial_pty() {
+        let (mut master, path) = open_pty();
+
+        assert!(SerialPort::open(&path, 12345).is_err());
+
+        let framer = SyncFramer::new(vec![0xFA], FrameLength::Field(LengthField::new(1, IntPrim::u8_be(), 0)));
+        let mut frames = serial_source(&path, 115200, framer).unwrap();
+
+        // the second packet is split across writes, with line ending and
+        // control bytes that a terminal in cooked mode would change
+        let writer = thread::spawn(move || {
+            master.write_all(&[0x00, 0xFA, 0x04, 0x0A, 0x0D, 0xFA, 0x05]).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            master.write_all(&[0x03, 0x11, 0x13]).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            master
+        });
+
+        assert_eq!(frames.next(), Some(vec![0xFA, 0x04, 0x0A, 0x0D]));
+        assert_eq!(frames.next(), Some(vec![0xFA, 0x05, 0x03, 0x11, 0x13]));
+        assert_eq!(frames.framer().discarded(), 1);
+
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate crossbeam_channel;
+
+use std::io;
+use std::io::Write;
+use std::fs::File;
+use std::net::{UdpSocket, TcpListener, TcpStream, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use self::crossbeam_channel as channel;
+
+use types::*;
+
+
+// Output sinks forward packets to other programs over the network or to a file,
+// either as the raw packet bytes or as decoded lines of csv or json. Each sink runs
+// on its own thread behind a bounded queue. When a sink's queue is full its records
+// are dropped and counted, so a slow consumer never stalls decoding, unless the
+// router is lossless, in which case routing waits for room in the queue.
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SinkFormat {
+    Raw,
+    Csv,
+    Jsonl,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Destination {
+    // send each record as a datagram to this address
+    Udp(String),
+    // accept tcp clients on this address, sending each record to every client
+    TcpListen(String),
+    // connect to a tcp server at this address
+    Tcp(String),
+    // write each record to a file
+    File(String),
+}
+
+// A sink is given as a destination with optional settings, such as
+//   udp://10.0.0.2:6000
+//   tcp://10.0.0.2:6001
+//   tcp-listen://0.0.0.0:7000?format=jsonl&packets=hk,vn200
+//   udp://10.0.0.2:6002?ids=0x10,0x11
+//   file://replayed.bin
+// The format defaults to raw, and all packets are sent unless packets or ids are listed.
+// packets selects by the name of the packet's definition, and ids by the value of the
+// item identifying each packet, such as its APID, so packets sharing a definition can
+// be routed to different outputs.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SinkSpec {
+    pub destination: Destination,
+    pub format: SinkFormat,
+    pub packets: Option<Vec<Name>>,
+    pub ids: Option<Vec<u64>>,
+}
+
+impl SinkSpec {
+    pub fn parse(spec: &str) -> Result<SinkSpec, String> {
+        let (target, options) = match spec.find('?') {
+            Some(pos) => (&spec[..pos], &spec[pos + 1..]),
+            None => (spec, ""),
+        };
+
+        let destination = match target.find("://") {
+            Some(pos) => {
+                let addr = target[pos + 3..].to_string();
+                match &target[..pos] {
+                    "udp"        => Destination::Udp(addr),
+                    "tcp"        => Destination::Tcp(addr),
+                    "tcp-listen" => Destination::TcpListen(addr),
+                    "file"       => Destination::File(addr),
+                    scheme => return Err(format!("unknown output type '{}', expected udp, tcp, tcp-listen, or file", scheme)),
+                }
+            },
+
+            None => return Err(format!("output '{}' must start with udp://, tcp://, tcp-listen://, or file://", spec)),
+        };
+
+        let mut sink_spec = SinkSpec { destination, format: SinkFormat::Raw, packets: None, ids: None };
+
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.find('=') {
+                Some(pos) => (&option[..pos], &option[pos + 1..]),
+                None => return Err(format!("expected key=value in output option '{}'", option)),
+            };
+
+            match key {
+                "format" => {
+                    sink_spec.format = match value {
+                        "raw"   => SinkFormat::Raw,
+                        "csv"   => SinkFormat::Csv,
+                        "jsonl" => SinkFormat::Jsonl,
+                        _ => return Err(format!("unknown output format '{}', expected raw, csv, or jsonl", value)),
+                    };
+                },
+
+                "packets" => {
+                    sink_spec.packets = Some(value.split(',').map(|name| name.trim().to_string()).collect());
+                },
+
+                "ids" => {
+                    let mut ids = Vec::new();
+                    for id in value.split(',').map(|id| id.trim()) {
+                        let parsed = if id.starts_with("0x") || id.starts_with("0X") {
+                            u64::from_str_radix(&id[2..], 16)
+                        } else {
+                            id.parse()
+                        };
+
+                        match parsed {
+                            Ok(parsed) => ids.push(parsed),
+                            Err(_) => return Err(format!("invalid packet id '{}' in output option ids", id)),
+                        }
+                    }
+                    sink_spec.ids = Some(ids);
+                },
+
+                _ => return Err(format!("unknown output option '{}'", key)),
+            }
+        }
+
+        Ok(sink_spec)
+    }
+
+    // A record without an id is not accepted by a sink that lists ids.
+    pub fn accepts(&self, record: &SinkRecord) -> bool {
+        self.packets.as_ref().is_none_or(|packets| packets.contains(&record.packet)) &&
+        self.ids.as_ref().is_none_or(|ids| record.id.is_some_and(|id| ids.contains(&id)))
+    }
+}
+
+// A packet ready to be routed, with each of the forms sinks may ask for.
+// Lines are only present if they were rendered, see Router::needs.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SinkRecord {
+    pub packet: Name,
+    // the value of the item identifying the packet, if one was given
+    pub id: Option<u64>,
+    pub bytes: Arc<Vec<u8>>,
+    pub csv: Option<Arc<String>>,
+    pub jsonl: Option<Arc<String>>,
+}
+
+impl SinkRecord {
+    pub fn new(packet: &str, bytes: &[u8]) -> SinkRecord {
+        SinkRecord { packet: packet.to_string(), id: None, bytes: Arc::new(bytes.to_vec()), csv: None, jsonl: None }
+    }
+
+    pub fn payload(&self, format: SinkFormat) -> Option<&[u8]> {
+        match format {
+            SinkFormat::Raw   => Some(&self.bytes),
+            SinkFormat::Csv   => self.csv.as_ref().map(|line| line.as_bytes()),
+            SinkFormat::Jsonl => self.jsonl.as_ref().map(|line| line.as_bytes()),
+        }
+    }
+}
+
+struct RouteSink {
+    spec: SinkSpec,
+    sender: Option<channel::Sender<SinkRecord>>,
+    thread: Option<JoinHandle<()>>,
+    dropped: usize,
+    // the clients accepted by a tcp-listen sink
+    clients: Option<Arc<Mutex<Vec<TcpStream>>>>,
+    // the error that stopped a file sink writing
+    error: Option<Arc<Mutex<Option<io::Error>>>>,
+}
+
+pub struct Router {
+    queue_depth: usize,
+    lossless: bool,
+    sinks: Vec<RouteSink>,
+}
+
+// a client that stops reading is dropped after this long
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl Router {
+    pub fn new(queue_depth: usize) -> Router {
+        Router { queue_depth, lossless: false, sinks: Vec::new() }
+    }
+
+    // A router that waits for slow sinks rather then dropping records, such as
+    // when replaying a capture.
+    pub fn lossless(queue_depth: usize) -> Router {
+        Router { queue_depth, lossless: true, sinks: Vec::new() }
+    }
+
+    // Open a sink and start its thread, returning the local address of its socket
+    // for network sinks.
+    pub fn add(&mut self, spec: SinkSpec) -> io::Result<Option<SocketAddr>> {
+        let (sender, receiver) = channel::bounded::<SinkRecord>(self.queue_depth);
+        let format = spec.format;
+
+        let mut accepted_clients = None;
+        let mut write_error = None;
+
+        let (local_addr, thread) = match spec.destination {
+            Destination::Udp(ref addr) => {
+                let remote = resolve(addr)?;
+                let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(remote)?;
+                let local_addr = socket.local_addr()?;
+
+                let thread = thread::spawn(move || {
+                    while let Some(record) = receiver.recv() {
+                        if let Some(payload) = record.payload(format) {
+                            // NOTE send errors, such as no one listening, are ignored
+                            let _ = socket.send(payload);
+                        }
+                    }
+                });
+
+                (Some(local_addr), thread)
+            },
+
+            Destination::Tcp(ref addr) => {
+                let remote = resolve(addr)?;
+                let mut stream = Some(TcpStream::connect(remote)?);
+                let local_addr = stream.as_ref().and_then(|stream| stream.local_addr().ok());
+
+                let thread = thread::spawn(move || {
+                    while let Some(record) = receiver.recv() {
+                        if let Some(payload) = record.payload(format) {
+                            // reconnect if the server went away, dropping records until it is back
+                            if stream.is_none() {
+                                stream = TcpStream::connect(remote).ok();
+                            }
+
+                            let sent = stream.as_mut().is_some_and(|stream| stream.write_all(payload).is_ok());
+                            if !sent {
+                                stream = None;
+                            }
+                        }
+                    }
+                });
+
+                (local_addr, thread)
+            },
+
+            Destination::File(ref path) => {
+                let mut file = File::create(path)?;
+
+                let error = Arc::new(Mutex::new(None));
+                write_error = Some(error.clone());
+
+                // after a write error, such as a full disk, records are taken from the queue
+                // but not written, so a lossless router is not blocked
+                let thread = thread::spawn(move || {
+                    let mut failed = false;
+                    while let Some(record) = receiver.recv() {
+                        if let Some(payload) = record.payload(format) {
+                            if failed {
+                                continue;
+                            }
+
+                            if let Err(err) = file.write_all(payload) {
+                                *error.lock().unwrap() = Some(err);
+                                failed = true;
+                            }
+                        }
+                    }
+                });
+
+                (None, thread)
+            },
+
+            Destination::TcpListen(ref addr) => {
+                let listener = TcpListener::bind(addr.as_str())?;
+                let local_addr = listener.local_addr()?;
+
+                let clients: Arc<Mutex<Vec<TcpStream>>> = Arc::new(Mutex::new(Vec::new()));
+
+                // NOTE the accept thread is detached, and ends with the process
+                let accepted = clients.clone();
+                accepted_clients = Some(clients.clone());
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let _ = stream.set_nodelay(true);
+                        let _ = stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT));
+                        accepted.lock().unwrap().push(stream);
+                    }
+                });
+
+                let thread = thread::spawn(move || {
+                    while let Some(record) = receiver.recv() {
+                        if let Some(payload) = record.payload(format) {
+                            // drop any client that closed or stopped reading
+                            clients.lock().unwrap().retain(|mut client| client.write_all(payload).is_ok());
+                        }
+                    }
+                });
+
+                (Some(local_addr), thread)
+            },
+        };
+
+        self.sinks.push(RouteSink { spec,
+                                    sender: Some(sender),
+                                    thread: Some(thread),
+                                    dropped: 0,
+                                    clients: accepted_clients,
+                                    error: write_error });
+
+        Ok(local_addr)
+    }
+
+    // Whether any sink wants records in the given format, so lines are only
+    // rendered when they will be used.
+    pub fn needs(&self, format: SinkFormat) -> bool {
+        self.sinks.iter().any(|sink| sink.spec.format == format)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    // Queue a record for every sink that accepts its packet, without blocking
+    // unless the router is lossless.
+    pub fn route(&mut self, record: &SinkRecord) {
+        for sink in self.sinks.iter_mut() {
+            if !sink.spec.accepts(record) {
+                continue;
+            }
+
+            if let Some(ref sender) = sink.sender {
+                if self.lossless {
+                    sender.send(record.clone());
+                    continue;
+                }
+
+                select! {
+                    send(sender, record.clone()) => {},
+                    default => sink.dropped += 1,
+                }
+            }
+        }
+    }
+
+    // The number of records dropped by each sink because its queue was full
+    pub fn dropped(&self) -> Vec<(&SinkSpec, usize)> {
+        self.sinks.iter().map(|sink| (&sink.spec, sink.dropped)).collect()
+    }
+
+    // The number of clients connected to tcp-listen sinks
+    pub fn clients(&self) -> usize {
+        self.sinks.iter()
+                  .filter_map(|sink| sink.clients.as_ref())
+                  .map(|clients| clients.lock().unwrap().len())
+                  .sum()
+    }
+
+    // The error that stopped a file sink writing, if any, with the sink's destination.
+    pub fn take_error(&mut self) -> Option<(Destination, io::Error)> {
+        self.sinks.iter()
+                  .filter_map(|sink| sink.error.as_ref().map(|error| (sink, error)))
+                  .find_map(|(sink, error)| error.lock().unwrap().take().map(|err| (sink.spec.destination.clone(), err)))
+    }
+
+    // Send any queued records and stop the sink threads.
+    pub fn close(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.sender.take();
+        }
+
+        for sink in self.sinks.iter_mut() {
+            if let Some(thread) = sink.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    match addr.to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("could not resolve '{}'", addr))),
+    }
+}
+
+
+#[cfg(test)]
+mod test_sink {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_sink_spec() {
+        let spec = SinkSpec::parse("tcp-listen://0.0.0.0:7000?format=jsonl&packets=hk,vn200").unwrap();
+        assert_eq!(spec.destination, Destination::TcpListen("0.0.0.0:7000".to_string()));
+        assert_eq!(spec.format, SinkFormat::Jsonl);
+        assert!(spec.accepts(&SinkRecord::new("hk", &[])));
+        assert!(!spec.accepts(&SinkRecord::new("adcs", &[])));
+
+        let spec = SinkSpec::parse("udp://10.0.0.2:6000").unwrap();
+        assert_eq!(spec.format, SinkFormat::Raw);
+        assert!(spec.accepts(&SinkRecord::new("adcs", &[])));
+
+        // packets of one definition are told apart by their ids
+        let spec = SinkSpec::parse("udp://10.0.0.2:6000?ids=0x10,17").unwrap();
+        assert_eq!(spec.ids, Some(vec!(0x10, 0x11)));
+        let mut record = SinkRecord::new("ccsds", &[]);
+        assert!(!spec.accepts(&record));
+        record.id = Some(0x11);
+        assert!(spec.accepts(&record));
+        record.id = Some(0x12);
+        assert!(!spec.accepts(&record));
+
+        assert!(SinkSpec::parse("udp://10.0.0.2:6000?ids=0x10,apid").is_err());
+        assert!(SinkSpec::parse("udp://10.0.0.2:6000?format=xml").is_err());
+        assert!(SinkSpec::parse("10.0.0.2:6000").is_err());
+    }
+
+    #[test]
+    fn test_router() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp_addr = receiver.local_addr().unwrap();
+
+        let mut router = Router::new(10);
+        router.add(SinkSpec::parse(&format!("udp://{}?packets=hk", udp_addr)).unwrap()).unwrap();
+        let tcp_addr = router.add(SinkSpec::parse("tcp-listen://127.0.0.1:0?format=jsonl").unwrap()).unwrap().unwrap();
+        assert!(router.needs(SinkFormat::Jsonl));
+        assert!(!router.needs(SinkFormat::Csv));
+
+        let client = TcpStream::connect(tcp_addr).unwrap();
+        // wait for the client to be accepted before routing
+        while router.clients() == 0 {
+            thread::yield_now();
+        }
+
+        let mut hk = SinkRecord::new("hk", &[1, 2, 3]);
+        hk.jsonl = Some(Arc::new("{\"a\":1}\n".to_string()));
+        let mut adcs = SinkRecord::new("adcs", &[4, 5]);
+        adcs.jsonl = Some(Arc::new("{\"b\":2}\n".to_string()));
+
+        router.route(&hk);
+        router.route(&adcs);
+        router.close();
+
+        // only hk packets are forwarded over udp
+        let mut buffer = [0; 16];
+        let num_bytes = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..num_bytes], &[1, 2, 3]);
+
+        // every packet is forwarded to tcp clients as a json line
+        let lines: Vec<String> = BufReader::new(client).lines().take(2).map(|line| line.unwrap()).collect();
+        assert_eq!(lines, vec!["{\"a\":1}", "{\"b\":2}"]);
+
+        assert!(router.dropped().iter().all(|(_, dropped)| *dropped == 0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_file_sink_error() {
+        let mut router = Router::lossless(1);
+        router.add(SinkSpec::parse("file:///dev/full").unwrap()).unwrap();
+
+        // a full disk stops the sink writing, without blocking the router
+        for _ in 0..4 {
+            router.route(&SinkRecord::new("hk", &[1, 2, 3]));
+        }
+        router.close();
+
+        match router.take_error() {
+            Some((destination, _)) => assert_eq!(destination, Destination::File("/dev/full".to_string())),
+            None => panic!("expected a write error"),
+        }
+        assert!(router.take_error().is_none());
+    }
+}
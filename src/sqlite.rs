@@ -0,0 +1,323 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate rusqlite;
+
+use std::path::Path;
+use std::fmt;
+use std::error::Error;
+
+use self::rusqlite::{Connection, NO_PARAMS};
+use self::rusqlite::types::Value as SqlValue;
+
+use types::*;
+use prim::*;
+use value::*;
+use packet::*;
+use loclayout::*;
+use csv::{path_name, ColumnNaming};
+use hash::{definition_hash, definition_ron};
+
+
+// A single file archive of decoded packets. Each packet definition gets its
+// own table with a column per item, plus time and sequence count columns
+// that are indexed for retrieval. The definitions used to create the tables
+// are recorded, with their hashes, in the gasworks_definitions table, and the
+// time correlations used for packet times in the gasworks_time_correlations table.
+
+// number of inserts between commits
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Sql(rusqlite::Error),
+    // the archive already has a table for the packet, created from a different definition
+    DefinitionChanged(Name, String, String),
+    UnknownPacket(Name),
+    // points for a packet that don't match its table's columns, with the number of each
+    PointCount(Name, usize, usize),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Sql(error) =>
+                write!(f, "sqlite error: {}", error),
+            ArchiveError::DefinitionChanged(name, stored, hash) =>
+                write!(f, "the archive's table for {} was created from definition {}, not {}", name, stored, hash),
+            ArchiveError::UnknownPacket(name) =>
+                write!(f, "the archive has no table for {}", name),
+            ArchiveError::PointCount(name, columns, points) =>
+                write!(f, "{} points given for {}, whose table has {} item columns", points, name, columns),
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ArchiveError::Sql(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ArchiveError {
+    fn from(error: rusqlite::Error) -> ArchiveError {
+        ArchiveError::Sql(error)
+    }
+}
+
+struct PacketTable {
+    insert_sql: String,
+    num_items: usize,
+}
+
+pub struct SqliteArchive {
+    conn: Connection,
+    tables: HashMap<Name, PacketTable>,
+    pending: usize,
+}
+
+pub fn prim_sql_type(prim: &Prim) -> &'static str {
+    match prim {
+        Prim::Int(_)   => "INTEGER",
+        Prim::Float(_) => "REAL",
+        Prim::Enum(_)  => "TEXT",
+    }
+}
+
+pub fn value_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::U8(value)  => SqlValue::Integer(*value as i64),
+        Value::U16(value) => SqlValue::Integer(*value as i64),
+        Value::U32(value) => SqlValue::Integer(*value as i64),
+        // NOTE sqlite integers are 64 bit signed, so large u64 values wrap
+        Value::U64(value) => SqlValue::Integer(*value as i64),
+        Value::I8(value)  => SqlValue::Integer(*value as i64),
+        Value::I16(value) => SqlValue::Integer(*value as i64),
+        Value::I32(value) => SqlValue::Integer(*value as i64),
+        Value::I64(value) => SqlValue::Integer(*value),
+        Value::F32(value) => SqlValue::Real(*value as f64),
+        Value::F64(value) => SqlValue::Real(*value),
+        Value::Enum(name, _) => SqlValue::Text(name.clone()),
+    }
+}
+
+fn quote_name(name: &str) -> String {
+    format!("\"{}\"", name.replace("\"", "\"\""))
+}
+
+impl SqliteArchive {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteArchive, ArchiveError> {
+        SqliteArchive::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<SqliteArchive, ArchiveError> {
+        SqliteArchive::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<SqliteArchive, ArchiveError> {
+        conn.execute_batch("CREATE TABLE IF NOT EXISTS gasworks_definitions (
+                                packet     TEXT PRIMARY KEY,
+                                hash       TEXT NOT NULL,
+                                definition TEXT NOT NULL);
+                            CREATE TABLE IF NOT EXISTS gasworks_time_correlations (
+                                packet      TEXT NOT NULL,
+                                correlation TEXT NOT NULL,
+                                recorded    REAL NOT NULL);")?;
+
+        Ok(SqliteArchive { conn, tables: HashMap::new(), pending: 0 })
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    // Add a table for a packet, with a column for each item in the packet's LocLayout.
+    // If the table already exists, it must have been created from the same definition.
+    pub fn add_packet(&mut self,
+                      name:       &str,
+                      packet:     &LayoutPacketDef,
+                      loc_layout: &LocLayout) -> Result<(), ArchiveError> {
+        let hash = format!("{:016x}", definition_hash(packet));
+
+        let stored_hash: Option<String> =
+            self.conn.prepare("SELECT hash FROM gasworks_definitions WHERE packet = ?1")?
+                     .query_map(&[name], |row| row.get(0))?
+                     .next()
+                     .map_or(Ok(None), |hash| hash.map(Some))?;
+
+        let table = quote_name(name);
+        let naming = ColumnNaming::default();
+        let columns: Vec<String> =
+            loc_layout.loc_items.iter()
+                                .map(|loc_item| quote_name(&path_name(&loc_item.name, &naming)))
+                                .collect();
+
+        match stored_hash {
+            Some(stored_hash) => {
+                if stored_hash != hash {
+                    return Err(ArchiveError::DefinitionChanged(name.to_string(), stored_hash, hash));
+                }
+            },
+
+            None => {
+                let mut create = format!("CREATE TABLE {} (time REAL, seq INTEGER", table);
+                for (column, loc_item) in columns.iter().zip(loc_layout.loc_items.iter()) {
+                    create.push_str(&format!(", {} {}", column, prim_sql_type(&loc_item.typ)));
+                }
+                create.push_str(");");
+
+                self.conn.execute_batch(&create)?;
+                self.conn.execute_batch(&format!("CREATE INDEX {} ON {} (time);",
+                                                 quote_name(&format!("{}_time", name)), table))?;
+                self.conn.execute_batch(&format!("CREATE INDEX {} ON {} (seq);",
+                                                 quote_name(&format!("{}_seq", name)), table))?;
+                self.conn.execute("INSERT INTO gasworks_definitions (packet, hash, definition) VALUES (?1, ?2, ?3)",
+                                  &[name, &hash, &definition_ron(packet)])?;
+            },
+        }
+
+        let mut insert_sql = format!("INSERT INTO {} (time, seq", table);
+        for column in columns.iter() {
+            insert_sql.push_str(", ");
+            insert_sql.push_str(column);
+        }
+        insert_sql.push_str(") VALUES (?, ?");
+        for _ in columns.iter() {
+            insert_sql.push_str(", ?");
+        }
+        insert_sql.push(')');
+
+        self.tables.insert(name.to_string(),
+                           PacketTable { insert_sql, num_items: columns.len() });
+
+        Ok(())
+    }
+
+    // Insert a packet's points, in the order of the LocLayout the table was created with.
+    pub fn insert(&mut self,
+                  name:   &str,
+                  time:   Option<f64>,
+                  seq:    Option<i64>,
+                  points: &[Point]) -> Result<(), ArchiveError> {
+        let table = match self.tables.get(name) {
+            Some(table) => table,
+            None => return Err(ArchiveError::UnknownPacket(name.to_string())),
+        };
+        if points.len() != table.num_items {
+            return Err(ArchiveError::PointCount(name.to_string(), table.num_items, points.len()));
+        }
+
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+
+        let mut values = Vec::with_capacity(points.len() + 2);
+        values.push(time.map_or(SqlValue::Null, SqlValue::Real));
+        values.push(seq.map_or(SqlValue::Null, SqlValue::Integer));
+        values.extend(points.iter().map(|point| value_sql(&point.val)));
+
+        self.conn.prepare_cached(&table.insert_sql)?.execute(values)?;
+
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Commit any inserted packets.
+    pub fn flush(&mut self) -> Result<(), ArchiveError> {
+        if self.pending > 0 {
+            self.conn.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+
+        Ok(())
+    }
+
+    // Record the time correlation used for a packet's times, with the unix time it was recorded.
+    pub fn record_time_correlation(&mut self, name: &str, correlation: &str, recorded: f64) -> Result<(), ArchiveError> {
+        self.conn.execute("INSERT INTO gasworks_time_correlations (packet, correlation, recorded) VALUES (?1, ?2, ?3)",
+                          &[&name as &dyn rusqlite::ToSql, &correlation, &recorded])?;
+        Ok(())
+    }
+
+    pub fn count(&self, name: &str) -> Result<i64, ArchiveError> {
+        let sql = format!("SELECT COUNT(*) FROM {}", quote_name(name));
+        Ok(self.conn.query_row(&sql, NO_PARAMS, |row| row.get(0))?)
+    }
+}
+
+impl Drop for SqliteArchive {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+
+#[cfg(test)]
+mod test_sqlite {
+    use super::*;
+    use std::io::Cursor;
+    use decode::*;
+    use *;
+
+    #[test]
+    fn test_archive_packets() {
+        let mut enum_map = BTreeMap::new();
+        enum_map.insert(0, "Off".to_string());
+        enum_map.insert(1, "On".to_string());
+        let mode = item("mode", Prim::Enum(Enum{map : enum_map, int_prim : IntPrim::u8_be()}));
+
+        let packet : LayoutPacketDef =
+            seq("tlm".to_string(), vec!(u16_be("seq"), leaf(mode), f32_be("temp")));
+        let loc_layout = packet.locate().unwrap();
+
+        let mut archive = SqliteArchive::open_in_memory().unwrap();
+        archive.add_packet("tlm", &packet, &loc_layout).unwrap();
+
+        let v = vec![0x00, 0x07, 0x01, 0x3F, 0x80, 0x00, 0x00];
+        let points = decode_loc_layout(&loc_layout, &mut Cursor::new(v.as_slice()));
+        archive.insert("tlm", Some(10.5), Some(7), &points).unwrap();
+        match archive.insert("tlm", None, None, &points[1..]) {
+            Err(ArchiveError::PointCount(_, 3, 2)) => (),
+            result => panic!("expected a point count error, got {:?}", result),
+        }
+        archive.flush().unwrap();
+
+        assert_eq!(archive.count("tlm").unwrap(), 1);
+
+        archive.record_time_correlation("tlm", "sclk.csv piecewise 2 points", 20.0).unwrap();
+        let correlation: String =
+            archive.connection()
+                   .query_row("SELECT correlation FROM gasworks_time_correlations WHERE packet = 'tlm'", NO_PARAMS,
+                              |row| row.get(0))
+                   .unwrap();
+        assert_eq!(correlation, "sclk.csv piecewise 2 points");
+
+        let (time, mode, temp): (f64, String, f64) =
+            archive.connection()
+                   .query_row("SELECT time, \"tlm.mode\", \"tlm.temp\" FROM tlm WHERE seq = 7", NO_PARAMS,
+                              |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                   .unwrap();
+        assert_eq!(time, 10.5);
+        assert_eq!(mode, "On");
+        assert_eq!(temp, 1.0);
+
+        // a table for the same packet name can't be reused with a different definition
+        let changed : LayoutPacketDef = seq("tlm".to_string(), vec!(u16_be("seq")));
+        let changed_layout = changed.locate().unwrap();
+        match archive.add_packet("tlm", &changed, &changed_layout) {
+            Err(ArchiveError::DefinitionChanged(_, _, _)) => (),
+            result => panic!("expected a definition change, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,421 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use framing::*;
+
+
+// Byte stuffed framing, where packets are separated by a delimiter byte and any
+// delimiter within a packet is replaced by an escape sequence, rather then
+// giving each packet a length.
+//
+//   SLIP (RFC 1055): frames end with 0xC0, with 0xC0 sent as 0xDB 0xDC and 0xDB as 0xDB 0xDD
+//   KISS: SLIP framing with a leading byte holding the TNC port (high nibble) and
+//         command (low nibble), where command 0 is a data frame
+//   COBS: frames end with 0x00, and each run of non-zero bytes is prefixed with
+//         its length plus one, so the encoded frame has no zeros
+//   HDLC (asynchronous, RFC 1662): frames are between 0x7E flags, with 0x7E and
+//         0x7D sent as 0x7D followed by the byte xor 0x20, and end with the
+//         frame's FCS-16, least significant byte first
+//
+// Empty frames, as from back to back delimiters, are skipped.
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const HDLC_FLAG: u8 = 0x7E;
+const HDLC_ESC: u8 = 0x7D;
+const HDLC_XOR: u8 = 0x20;
+
+// the most bytes to buffer while waiting for a delimiter, so a stream
+// without delimiters does not grow the buffer forever
+const MAX_FRAME: usize = 2 * 65536;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Stuffing {
+    Slip,
+    // the TNC port to accept data frames from, or any port
+    Kiss(Option<u8>),
+    Cobs,
+    Hdlc,
+}
+
+impl Stuffing {
+    // Parse a stuffing name: slip, kiss, kiss:<port>, cobs, or hdlc
+    pub fn parse(name: &str) -> Result<Stuffing, String> {
+        match name {
+            "slip" => Ok(Stuffing::Slip),
+            "kiss" => Ok(Stuffing::Kiss(None)),
+            "cobs" => Ok(Stuffing::Cobs),
+            "hdlc" => Ok(Stuffing::Hdlc),
+
+            _ if name.starts_with("kiss:") => {
+                match name["kiss:".len()..].parse::<u8>() {
+                    Ok(port) if port < 16 => Ok(Stuffing::Kiss(Some(port))),
+                    _ => Err(format!("invalid KISS port in '{}', expected 0 to 15", name)),
+                }
+            },
+
+            _ => Err(format!("unknown stuffing '{}', expected slip, kiss, kiss:<port>, cobs, or hdlc", name)),
+        }
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            Stuffing::Slip | Stuffing::Kiss(_) => SLIP_END,
+            Stuffing::Cobs => 0x00,
+            Stuffing::Hdlc => HDLC_FLAG,
+        }
+    }
+
+    // Recover the payload from a frame, without its delimiters. Frames that do not
+    // carry a payload, such as KISS commands, give Some(None), and bad frames None.
+    fn unstuff(self, frame: &[u8]) -> Option<Option<Vec<u8>>> {
+        match self {
+            Stuffing::Slip => Some(Some(slip_unescape(frame))),
+
+            Stuffing::Kiss(port) => {
+                let frame = slip_unescape(frame);
+                let (&command, data) = frame.split_first()?;
+
+                let is_data = command & 0x0F == 0;
+                if is_data && port.is_none_or(|port| port == command >> 4) {
+                    Some(Some(data.to_vec()))
+                } else {
+                    Some(None)
+                }
+            },
+
+            Stuffing::Cobs => cobs_decode(frame).map(Some),
+
+            Stuffing::Hdlc => {
+                let mut frame = hdlc_unescape(frame)?;
+                if frame.len() < 2 {
+                    return None;
+                }
+
+                let payload_len = frame.len() - 2;
+                let fcs = frame[payload_len] as u16 | (frame[payload_len + 1] as u16) << 8;
+                if fcs16(&frame[..payload_len]) != fcs {
+                    return None;
+                }
+
+                frame.truncate(payload_len);
+                Some(Some(frame))
+            },
+        }
+    }
+
+    // Stuff a payload into a frame, delimiters included. For KISS the payload
+    // is sent as a data frame on the given port, or port 0.
+    pub fn encode(self, payload: &[u8]) -> Vec<u8> {
+        match self {
+            Stuffing::Slip => slip_encode(payload),
+            Stuffing::Kiss(port) => kiss_encode(port.unwrap_or(0), payload),
+            Stuffing::Cobs => cobs_encode(payload),
+            Stuffing::Hdlc => hdlc_encode(payload),
+        }
+    }
+}
+
+// Frame packets separated by a delimiter, returning their unstuffed payloads.
+// Frames that fail to unstuff, such as an HDLC frame with a bad FCS, are dropped
+// and counted.
+#[derive(Debug, Clone)]
+pub struct StuffedFramer {
+    stuffing: Stuffing,
+    buffer: Vec<u8>,
+    // how much of the buffer is known to have no delimiter
+    searched: usize,
+    errors: usize,
+}
+
+impl StuffedFramer {
+    pub fn new(stuffing: Stuffing) -> StuffedFramer {
+        StuffedFramer { stuffing, buffer: Vec::new(), searched: 0, errors: 0 }
+    }
+
+    pub fn slip() -> StuffedFramer {
+        StuffedFramer::new(Stuffing::Slip)
+    }
+
+    // KISS data frames from any TNC port
+    pub fn kiss() -> StuffedFramer {
+        StuffedFramer::new(Stuffing::Kiss(None))
+    }
+
+    pub fn cobs() -> StuffedFramer {
+        StuffedFramer::new(Stuffing::Cobs)
+    }
+
+    pub fn hdlc() -> StuffedFramer {
+        StuffedFramer::new(Stuffing::Hdlc)
+    }
+
+    pub fn stuffing(&self) -> Stuffing {
+        self.stuffing
+    }
+
+    // the number of frames dropped because they could not be unstuffed, or grew too long
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+}
+
+impl Framer for StuffedFramer {
+    fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        let delimiter = self.stuffing.delimiter();
+
+        loop {
+            let end = match self.buffer[self.searched..].iter().position(|byte| *byte == delimiter) {
+                Some(position) => self.searched + position,
+
+                None => {
+                    self.searched = self.buffer.len();
+                    if self.buffer.len() > MAX_FRAME {
+                        self.buffer.clear();
+                        self.searched = 0;
+                        self.errors += 1;
+                    }
+                    return None;
+                },
+            };
+
+            let frame: Vec<u8> = self.buffer.drain(..end + 1).take(end).collect();
+            self.searched = 0;
+
+            if frame.is_empty() {
+                continue;
+            }
+
+            match self.stuffing.unstuff(&frame) {
+                Some(Some(payload)) => return Some(payload),
+
+                Some(None) => (),
+
+                None => self.errors += 1,
+            }
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.searched = 0;
+    }
+}
+
+// NOTE an escape followed by anything else is an error, and like RFC 1055 the byte is kept
+fn slip_unescape(frame: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(frame.len());
+    let mut escaped = false;
+
+    for byte in frame {
+        if escaped {
+            payload.push(match *byte {
+                SLIP_ESC_END => SLIP_END,
+                SLIP_ESC_ESC => SLIP_ESC,
+                byte => byte,
+            });
+            escaped = false;
+        } else if *byte == SLIP_ESC {
+            escaped = true;
+        } else {
+            payload.push(*byte);
+        }
+    }
+
+    payload
+}
+
+fn slip_escape(payload: &[u8], frame: &mut Vec<u8>) {
+    for byte in payload {
+        match *byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            byte => frame.push(byte),
+        }
+    }
+}
+
+// A SLIP frame, starting with an END as well to flush any line noise.
+pub fn slip_encode(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![SLIP_END];
+    slip_escape(payload, &mut frame);
+    frame.push(SLIP_END);
+    frame
+}
+
+// A KISS data frame for a TNC port.
+pub fn kiss_encode(port: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![SLIP_END];
+    slip_escape(&[port << 4], &mut frame);
+    slip_escape(payload, &mut frame);
+    frame.push(SLIP_END);
+    frame
+}
+
+pub fn cobs_encode(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + payload.len() / 254 + 2);
+
+    // each run starts with a placeholder for its length code
+    let mut code_index = 0;
+    frame.push(0);
+
+    for (index, byte) in payload.iter().enumerate() {
+        if *byte == 0 {
+            frame[code_index] = (frame.len() - code_index) as u8;
+            code_index = frame.len();
+            frame.push(0);
+        } else {
+            frame.push(*byte);
+
+            // a full run of 254 bytes ends its block, unless it ends the payload
+            if frame.len() - code_index == 0xFF && index + 1 < payload.len() {
+                frame[code_index] = 0xFF;
+                code_index = frame.len();
+                frame.push(0);
+            }
+        }
+    }
+
+    frame[code_index] = (frame.len() - code_index) as u8;
+    frame.push(0);
+    frame
+}
+
+// decode a COBS frame without its trailing zero
+fn cobs_decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut payload = Vec::with_capacity(frame.len());
+    let mut position = 0;
+
+    while position < frame.len() {
+        let code = frame[position] as usize;
+        if code == 0 || position + code > frame.len() {
+            return None;
+        }
+
+        payload.extend_from_slice(&frame[position + 1 .. position + code]);
+        position += code;
+
+        // runs of 254 bytes are not followed by a zero, and neither is the last run
+        if code < 0xFF && position < frame.len() {
+            payload.push(0);
+        }
+    }
+
+    Some(payload)
+}
+
+// The HDLC frame check sequence (CRC-16/X.25).
+pub fn fcs16(bytes: &[u8]) -> u16 {
+    let mut fcs: u16 = 0xFFFF;
+
+    for byte in bytes {
+        fcs ^= *byte as u16;
+        for _ in 0..8 {
+            fcs = if fcs & 1 != 0 { (fcs >> 1) ^ 0x8408 } else { fcs >> 1 };
+        }
+    }
+
+    !fcs
+}
+
+// None for an escape at the end of the frame, which HDLC takes as an abort
+fn hdlc_unescape(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut payload = Vec::with_capacity(frame.len());
+    let mut escaped = false;
+
+    for byte in frame {
+        if escaped {
+            payload.push(*byte ^ HDLC_XOR);
+            escaped = false;
+        } else if *byte == HDLC_ESC {
+            escaped = true;
+        } else {
+            payload.push(*byte);
+        }
+    }
+
+    if escaped { None } else { Some(payload) }
+}
+
+// An HDLC frame between flags, with the payload's FCS appended.
+pub fn hdlc_encode(payload: &[u8]) -> Vec<u8> {
+    let fcs = fcs16(payload);
+
+    let mut frame = vec![HDLC_FLAG];
+    for byte in payload.iter().chain(&[fcs as u8, (fcs >> 8) as u8]) {
+        if *byte == HDLC_FLAG || *byte == HDLC_ESC {
+            frame.extend_from_slice(&[HDLC_ESC, *byte ^ HDLC_XOR]);
+        } else {
+            frame.push(*byte);
+        }
+    }
+    frame.push(HDLC_FLAG);
+    frame
+}
+
+
+#[cfg(test)]
+mod test_stuffing {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_stuffed_framers() {
+        let payloads = vec![vec![0x01, 0xC0, 0xDB, 0x00, 0x7E, 0x7D, 0x02],
+                            (0..600).map(|byte| (byte % 256) as u8).collect(),
+                            vec![0x00],
+                            vec![0xFF; 254]];
+
+        for stuffing in [Stuffing::Slip, Stuffing::Kiss(Some(3)), Stuffing::Cobs, Stuffing::Hdlc].iter() {
+            let mut stream = vec![stuffing.delimiter()];
+            for payload in payloads.iter() {
+                stream.extend_from_slice(&stuffing.encode(payload));
+            }
+
+            let frames: Vec<Vec<u8>> = FrameReader::new(Cursor::new(stream), StuffedFramer::new(*stuffing)).collect();
+            assert_eq!(&frames, &payloads, "{:?}", stuffing);
+        }
+
+        assert_eq!(slip_encode(&[0x01, 0xC0, 0xDB]), vec![0xC0, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0xC0]);
+        assert_eq!(cobs_encode(&[0x11, 0x00, 0x00, 0x22]), vec![0x02, 0x11, 0x01, 0x02, 0x22, 0x00]);
+        assert_eq!(cobs_encode(&[0xFF; 254]).len(), 256);
+        assert_eq!(Stuffing::parse("kiss:2"), Ok(Stuffing::Kiss(Some(2))));
+        assert!(Stuffing::parse("kiss:16").is_err());
+        assert_eq!(fcs16(b"123456789"), 0x906E);
+
+        // KISS frames for other ports, and commands, are skipped
+        let mut framer = StuffedFramer::new(Stuffing::Kiss(Some(1)));
+        framer.push(&kiss_encode(0, &[1]));
+        framer.push(&[0xC0, 0x11, 0x05, 0xC0]);
+        framer.push(&kiss_encode(1, &[2]));
+        assert_eq!(framer.next_packet(), Some(vec![2]));
+        assert_eq!(framer.next_packet(), None);
+
+        // an HDLC frame with a bad FCS is dropped, and a partial frame is kept
+        let mut framer = StuffedFramer::hdlc();
+        let mut bad = hdlc_encode(&[1, 2, 3]);
+        bad[2] ^= 0x01;
+        framer.push(&bad);
+        framer.push(&hdlc_encode(&[4, 5]));
+        framer.push(&hdlc_encode(&[6])[..3]);
+        assert_eq!(framer.next_packet(), Some(vec![4, 5]));
+        assert_eq!(framer.next_packet(), None);
+        assert_eq!(framer.errors(), 1);
+        assert_eq!(framer.buffered(), 2);
+    }
+}
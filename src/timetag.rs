@@ -0,0 +1,481 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::io::Cursor;
+
+use types::*;
+use prim::*;
+use decode::*;
+use ccsds::*;
+use correlation::*;
+
+
+// Packet time tags and their conversion to UTC. A time tag counts seconds from
+// an epoch on some time scale: CCSDS codes usually count TAI seconds from
+// 1958-01-01, GPS receivers count GPS seconds from 1980-01-06, and ground
+// software often counts UTC seconds from 1970-01-01. TAI and GPS run without
+// leap seconds, so converting them to UTC needs a table of leap seconds.
+//
+// Times are kept as seconds since 1970-01-01 on the calendar of their time scale,
+// so UTC times are unix times and a UTC epoch is given the same way.
+
+// 1958-01-01, the CCSDS epoch
+pub const CCSDS_EPOCH: f64 = -378_691_200.0;
+// 1980-01-06, the GPS epoch
+pub const GPS_EPOCH: f64 = 315_964_800.0;
+
+pub const SECONDS_PER_DAY: f64 = 86_400.0;
+pub const SECONDS_PER_WEEK: f64 = 604_800.0;
+
+// GPS time is behind TAI by the 19 leap seconds UTC had when GPS began
+const TAI_MINUS_GPS: f64 = 19.0;
+
+// TAI - UTC from the start of each year or half year a leap second was added
+// NOTE before 1972 UTC was adjusted by fractions of a second, which are not covered
+const LEAP_SECONDS: &[((i64, u32, u32), f64)] = &[
+    ((1972, 1, 1), 10.0), ((1972, 7, 1), 11.0), ((1973, 1, 1), 12.0), ((1974, 1, 1), 13.0),
+    ((1975, 1, 1), 14.0), ((1976, 1, 1), 15.0), ((1977, 1, 1), 16.0), ((1978, 1, 1), 17.0),
+    ((1979, 1, 1), 18.0), ((1980, 1, 1), 19.0), ((1981, 7, 1), 20.0), ((1982, 7, 1), 21.0),
+    ((1983, 7, 1), 22.0), ((1985, 7, 1), 23.0), ((1988, 1, 1), 24.0), ((1990, 1, 1), 25.0),
+    ((1991, 1, 1), 26.0), ((1992, 7, 1), 27.0), ((1993, 7, 1), 28.0), ((1994, 7, 1), 29.0),
+    ((1996, 1, 1), 30.0), ((1997, 7, 1), 31.0), ((1999, 1, 1), 32.0), ((2006, 1, 1), 33.0),
+    ((2009, 1, 1), 34.0), ((2012, 7, 1), 35.0), ((2015, 7, 1), 36.0), ((2017, 1, 1), 37.0),
+];
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TimeScale {
+    Utc,
+    Tai,
+    Gps,
+}
+
+// The start of a time tag's count, on its time scale.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Epoch {
+    pub seconds: f64,
+    pub scale: TimeScale,
+}
+
+impl Epoch {
+    pub fn ccsds() -> Epoch {
+        Epoch { seconds: CCSDS_EPOCH, scale: TimeScale::Tai }
+    }
+
+    pub fn gps() -> Epoch {
+        Epoch { seconds: GPS_EPOCH, scale: TimeScale::Gps }
+    }
+
+    pub fn unix() -> Epoch {
+        Epoch { seconds: 0.0, scale: TimeScale::Utc }
+    }
+
+    // ccsds, gps, unix, or a date and time on a time scale, such as tai:2000-01-01T12:00:00
+    pub fn parse(text: &str) -> Result<Epoch, String> {
+        match text {
+            "ccsds" => return Ok(Epoch::ccsds()),
+            "gps" => return Ok(Epoch::gps()),
+            "unix" => return Ok(Epoch::unix()),
+            _ => {},
+        }
+
+        let invalid = || format!("invalid epoch '{}', expected ccsds, gps, unix, or utc:, tai:, or gps: and a date", text);
+
+        let mut parts = text.splitn(2, ':');
+        let scale = match parts.next() {
+            Some("utc") => TimeScale::Utc,
+            Some("tai") => TimeScale::Tai,
+            Some("gps") => TimeScale::Gps,
+            _ => return Err(invalid()),
+        };
+        let seconds = parts.next().and_then(parse_date_time).ok_or_else(invalid)?;
+
+        Ok(Epoch { seconds, scale })
+    }
+}
+
+// A table of leap seconds, as TAI - UTC from the UTC time each value took effect.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LeapSeconds {
+    entries: Vec<(f64, f64)>,
+}
+
+impl Default for LeapSeconds {
+    fn default() -> LeapSeconds {
+        LeapSeconds::new(LEAP_SECONDS.iter()
+                                     .map(|&((year, month, day), offset)| (days_from_civil(year, month, day) as f64 * SECONDS_PER_DAY, offset))
+                                     .collect())
+    }
+}
+
+impl LeapSeconds {
+    pub fn new(mut entries: Vec<(f64, f64)>) -> LeapSeconds {
+        entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        LeapSeconds { entries }
+    }
+
+    // Parse a table with a date and TAI - UTC on each line, such as "2017-01-01 37",
+    // separated by spaces or a comma. Blank lines and lines starting with # are skipped.
+    pub fn parse(text: &str) -> Result<LeapSeconds, String> {
+        let mut entries = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|field| !field.is_empty());
+            let date = fields.next().and_then(parse_date_time);
+            let offset = fields.next().and_then(|field| field.parse::<f64>().ok()).filter(|offset| offset.is_finite());
+
+            match (date, offset) {
+                (Some(date), Some(offset)) => entries.push((date, offset)),
+                _ => return Err(format!("invalid leap second on line {}: '{}'", index + 1, line)),
+            }
+        }
+
+        Ok(LeapSeconds::new(entries))
+    }
+
+    // TAI - UTC at a UTC time
+    pub fn offset(&self, utc: f64) -> f64 {
+        self.entries.iter().rev().find(|entry| utc >= entry.0).map_or(0.0, |entry| entry.1)
+    }
+
+    // Convert a time on a time scale to UTC. During a leap second, UTC is held at
+    // the start of the next second.
+    pub fn to_utc(&self, seconds: f64, scale: TimeScale) -> f64 {
+        let tai = match scale {
+            TimeScale::Utc => return seconds,
+            TimeScale::Tai => seconds,
+            TimeScale::Gps => seconds + TAI_MINUS_GPS,
+        };
+
+        for &(start, offset) in self.entries.iter().rev() {
+            if tai - offset >= start {
+                return tai - offset;
+            } else if tai - offset + 1.0 > start {
+                return start;
+            }
+        }
+
+        tai
+    }
+}
+
+// How the time tag is held in a packet.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TimeFormat {
+    // a CCSDS time code in the bytes starting at the time item, big endian by the standard
+    Code(TimeCode),
+    // the time item's value counts units of the given number of seconds, and the fine
+    // item, if any, counts binary fractions of a second across its whole width
+    Count(f64),
+    // the time item is the GPS week number and the fine item, if any, the seconds of the week
+    GpsWeek,
+}
+
+impl TimeFormat {
+    // cuc:<coarse>.<fine>, cds:<day bytes>.<submillisecond bytes>, gps-week,
+    // or seconds with an optional unit, such as seconds:1e-9
+    pub fn parse(text: &str) -> Result<TimeFormat, String> {
+        let invalid = || format!("invalid time format '{}', expected cuc:<coarse>.<fine>, cds:<days>.<submillis>, \
+                                  gps-week, or seconds[:<unit>]", text);
+
+        let octets = |text: &str| -> Option<(u8, u8)> {
+            let mut parts = text.splitn(2, '.');
+            let first = parts.next()?.parse().ok()?;
+            let second = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+            Some((first, second))
+        };
+
+        match text {
+            "gps-week" => Ok(TimeFormat::GpsWeek),
+            "seconds" => Ok(TimeFormat::Count(1.0)),
+
+            // the unit must be a positive number of seconds, such as 1e-9
+            _ if text.starts_with("seconds:") => {
+                match text["seconds:".len()..].parse::<f64>() {
+                    Ok(unit) if unit.is_finite() && unit > 0.0 => Ok(TimeFormat::Count(unit)),
+                    _ => Err(invalid()),
+                }
+            },
+
+            _ if text.starts_with("cuc:") => {
+                match octets(&text["cuc:".len()..]) {
+                    Some((coarse, fine)) => TimeCode::cuc(coarse, fine).map(TimeFormat::Code),
+                    None => Err(invalid()),
+                }
+            },
+
+            _ if text.starts_with("cds:") => {
+                match octets(&text["cds:".len()..]) {
+                    Some((day_bytes, submilli_bytes)) => TimeCode::cds(day_bytes, submilli_bytes).map(TimeFormat::Code),
+                    None => Err(invalid()),
+                }
+            },
+
+            _ => Err(invalid()),
+        }
+    }
+
+    // the epoch the format usually counts from
+    pub fn default_epoch(&self) -> Epoch {
+        match self {
+            TimeFormat::Code(_) => Epoch::ccsds(),
+            TimeFormat::Count(_) => Epoch::unix(),
+            TimeFormat::GpsWeek => Epoch::gps(),
+        }
+    }
+}
+
+// The item marked as a packet's time, and how to convert it to UTC: either from
+// its epoch, or through a time correlation for a spacecraft clock that drifts.
+#[derive(PartialEq, Debug, Clone)]
+pub struct PacketTime {
+    pub item: LocItem,
+    pub fine: Option<LocItem>,
+    pub format: TimeFormat,
+    pub epoch: Epoch,
+    pub leap_seconds: LeapSeconds,
+    pub correlation: Option<TimeCorrelation>,
+}
+
+impl PacketTime {
+    pub fn new(item: LocItem, format: TimeFormat) -> PacketTime {
+        PacketTime { item,
+                     fine: None,
+                     epoch: format.default_epoch(),
+                     format,
+                     leap_seconds: LeapSeconds::default(),
+                     correlation: None }
+    }
+
+    // The fine item of a count is an integer of binary fractions of a second,
+    // so other items are rejected.
+    pub fn with_fine(mut self, fine: LocItem) -> Result<PacketTime, String> {
+        if let TimeFormat::Count(_) = self.format {
+            if !matches!(fine.typ, Prim::Int(_)) {
+                return Err(format!("the fine time item {} is not an integer", fine.name.join(".")));
+            }
+        }
+
+        self.fine = Some(fine);
+        Ok(self)
+    }
+
+    pub fn with_epoch(mut self, epoch: Epoch) -> PacketTime {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn with_leap_seconds(mut self, leap_seconds: LeapSeconds) -> PacketTime {
+        self.leap_seconds = leap_seconds;
+        self
+    }
+
+    // Convert the time tag through a time correlation rather then from its epoch.
+    pub fn with_correlation(mut self, correlation: TimeCorrelation) -> PacketTime {
+        self.correlation = Some(correlation);
+        self
+    }
+
+    // The packet's time tag in seconds since the epoch, if the packet is long enough to hold it.
+    pub fn elapsed(&self, bytes: &[u8]) -> Option<f64> {
+        let fits = |item: &LocItem| bytes.len() as u64 >= item.loc + item.num_bytes();
+        if !fits(&self.item) || !self.fine.as_ref().is_none_or(fits) {
+            return None;
+        }
+
+        let value = |item: &LocItem| decode_loc_item(item, &mut Cursor::new(bytes)).val;
+
+        match self.format {
+            TimeFormat::Code(code) => code.decode(&bytes[self.item.loc as usize..]),
+
+            TimeFormat::Count(unit) => {
+                let fraction = self.fine.as_ref().map_or(0.0, |fine| {
+                    value(fine).value() as u64 as f64 / 2f64.powi(8 * fine.num_bytes() as i32)
+                });
+                Some(value(&self.item).as_f64() * unit + fraction)
+            },
+
+            TimeFormat::GpsWeek => {
+                let seconds = self.fine.as_ref().map_or(0.0, |fine| value(fine).as_f64());
+                Some(value(&self.item).as_f64() * SECONDS_PER_WEEK + seconds)
+            },
+        }
+    }
+
+    // The packet's time in UTC, as seconds since the unix epoch, noting whether it was
+    // extrapolated past the time correlation's points.
+    pub fn correlated(&self, bytes: &[u8]) -> Option<Correlated> {
+        let elapsed = self.elapsed(bytes)?;
+
+        Some(match self.correlation {
+            Some(ref correlation) => correlation.correlate(elapsed),
+            None => Correlated { utc: self.leap_seconds.to_utc(self.epoch.seconds + elapsed, self.epoch.scale),
+                                 extrapolated: false },
+        })
+    }
+
+    // The packet's time in UTC, as seconds since the unix epoch.
+    pub fn utc(&self, bytes: &[u8]) -> Option<f64> {
+        self.correlated(bytes).map(|correlated| correlated.utc)
+    }
+}
+
+// the number of days in a month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// days since 1970-01-01 of a date in the proleptic Gregorian calendar
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+// the date of a number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+// Parse a date with an optional time of day, such as 2017-01-01 or 2017-01-01T12:30:00.5Z,
+// to seconds since 1970-01-01.
+pub fn parse_date_time(text: &str) -> Option<f64> {
+    let text = text.trim_end_matches('Z');
+    let mut parts = text.splitn(2, ['T', ' ']);
+
+    let mut date = parts.next()?.splitn(3, '-');
+    let year = date.next()?.parse::<i64>().ok()?;
+    let month = date.next()?.parse::<u32>().ok()?;
+    let day = date.next()?.parse::<u32>().ok()?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut seconds = days_from_civil(year, month, day) as f64 * SECONDS_PER_DAY;
+
+    if let Some(time) = parts.next() {
+        let mut time = time.splitn(3, ':');
+        let hours = time.next()?.parse::<u32>().ok()?;
+        let minutes = time.next()?.parse::<u32>().ok()?;
+        let secs = time.next().map_or(Some(0.0), |secs| secs.parse::<f64>().ok())?;
+        seconds += hours as f64 * 3600.0 + minutes as f64 * 60.0 + secs;
+    }
+
+    // such as seconds of nan or inf
+    if !seconds.is_finite() {
+        return None;
+    }
+
+    Some(seconds)
+}
+
+// Format a unix time as an ISO 8601 UTC timestamp with microseconds, such as 2017-01-01T00:00:00.000000Z.
+pub fn utc_string(utc: f64) -> String {
+    let micros = (utc * 1e6).round() as i64;
+    let seconds = micros.div_euclid(1_000_000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year, month, day,
+            second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60,
+            micros.rem_euclid(1_000_000))
+}
+
+
+#[cfg(test)]
+mod test_timetag {
+    use super::*;
+
+    #[test]
+    fn test_packet_time() {
+        let new_year = parse_date_time("2017-01-01").unwrap();
+        assert_eq!(new_year, 1_483_228_800.0);
+        assert_eq!(utc_string(new_year + 0.25), "2017-01-01T00:00:00.250000Z");
+        assert_eq!(utc_string(parse_date_time("1958-01-01T12:30:15Z").unwrap()), "1958-01-01T12:30:15.000000Z");
+
+        // TAI and GPS run ahead of UTC by their leap seconds
+        let leap_seconds = LeapSeconds::default();
+        assert_eq!(leap_seconds.offset(new_year - 1.0), 36.0);
+        assert_eq!(leap_seconds.to_utc(new_year + 37.0, TimeScale::Tai), new_year);
+        assert_eq!(leap_seconds.to_utc(new_year + 36.5, TimeScale::Tai), new_year);
+        assert_eq!(leap_seconds.to_utc(new_year + 18.0, TimeScale::Gps), new_year);
+
+        // CCSDS unsegmented code, counting TAI seconds since 1958
+        let cuc = LocItem::new(vec!["time".to_string()], Prim::Int(IntPrim::u32_be()), 1);
+        let packet_time = PacketTime::new(cuc, TimeFormat::parse("cuc:4.1").unwrap());
+        let tai = (new_year + 37.0 - CCSDS_EPOCH) as u32;
+        let mut packet = vec![0xFF];
+        packet.extend_from_slice(&[(tai >> 24) as u8, (tai >> 16) as u8, (tai >> 8) as u8, tai as u8, 0x80]);
+        assert_eq!(packet_time.utc(&packet), Some(new_year + 0.5));
+        assert_eq!(packet_time.utc(&packet[..4]), None);
+
+        // GPS nanoseconds, little endian
+        let nanos = LocItem::new(vec!["timeGPS".to_string()], Prim::Int(IntPrim::u64_le()), 0);
+        let packet_time = PacketTime::new(nanos, TimeFormat::parse("seconds:1e-9").unwrap()).with_epoch(Epoch::parse("gps").unwrap());
+        let gps = ((new_year + 18.0 - GPS_EPOCH) * 1e9) as u64;
+        assert_eq!(packet_time.utc(&gps.to_le_bytes()), Some(new_year));
+
+        // counted fractions of a second must be integers
+        let fine = LocItem::new(vec!["fine".to_string()], Prim::Float(FloatPrim::f32_be()), 8);
+        assert!(packet_time.clone().with_fine(fine).is_err());
+
+        // GPS week and seconds of week
+        let week = LocItem::new(vec!["week".to_string()], Prim::Int(IntPrim::u16_be()), 0);
+        let seconds = LocItem::new(vec!["tow".to_string()], Prim::Int(IntPrim::u32_be()), 2);
+        let packet_time = PacketTime::new(week, TimeFormat::GpsWeek).with_fine(seconds).unwrap();
+        assert_eq!(packet_time.utc(&[0x07, 0x8A, 0x00, 0x00, 0x00, 0x12]), Some(new_year));
+
+        // a custom epoch and leap second table
+        let leap_seconds = LeapSeconds::parse("# TAI - UTC\n2000-01-01, 5\n\n2010-01-01 6\n").unwrap();
+        assert_eq!(leap_seconds.offset(parse_date_time("2005-06-01").unwrap()), 5.0);
+        assert!(LeapSeconds::parse("2000-13-01 5").is_err());
+        assert!(LeapSeconds::parse("2000-01-01T00:00:nan 5").is_err());
+        assert!(LeapSeconds::parse("2000-01-01 inf").is_err());
+        assert_eq!(parse_date_time("2017-01-01T00:00:inf"), None);
+        assert_eq!(parse_date_time("2017-02-31"), None);
+        assert_eq!(parse_date_time("2017-02-29"), None);
+        assert!(parse_date_time("2016-02-29").is_some());
+        assert_eq!(Epoch::parse("tai:2000-01-01T12:00:00").unwrap(),
+                   Epoch { seconds: 946_728_000.0, scale: TimeScale::Tai });
+        assert!(Epoch::parse("mars:2000-01-01").is_err());
+        assert!(TimeFormat::parse("cuc:5.0").is_err());
+        assert!(TimeFormat::parse("seconds:0").is_err());
+        assert!(TimeFormat::parse("seconds:-1e-3").is_err());
+        assert!(TimeFormat::parse("seconds:inf").is_err());
+
+        // a drifting clock through a time correlation
+        let points = vec![CorrelationPoint { sclk: 1000.0, utc: new_year }, CorrelationPoint { sclk: 2000.0, utc: new_year + 999.0 }];
+        let packet_time = packet_time.with_correlation(TimeCorrelation::new("sclk.csv", points, Interpolation::Piecewise).unwrap());
+        assert_eq!(packet_time.correlated(&[0x00, 0x00, 0x00, 0x00, 0x0B, 0xB8]),
+                   Some(Correlated { utc: new_year + 1998.0, extrapolated: true }));
+    }
+}
@@ -0,0 +1,482 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+
+use std::collections::VecDeque;
+
+use self::byteorder::{BigEndian, ByteOrder};
+
+use ccsds::*;
+use input::*;
+
+
+// TM transfer frames (CCSDS 132.0-B), the fixed length frames a downlink is
+// made of. Each frame carries part of the packet stream of one virtual channel,
+// and packets may span frames. The first header pointer gives the offset of the
+// first packet starting in the frame's data field, so a channel can pick up
+// packets again after a lost frame.
+//
+// The primary header is 6 bytes, big endian:
+//   version (2 bits), spacecraft id (10), virtual channel (3), OCF flag (1),
+//   master channel frame count (8), virtual channel frame count (8),
+//   secondary header flag (1), sync flag (1), packet order (1), segment length id (2),
+//   first header pointer (11)
+// followed by an optional secondary header, the data field, then an optional
+// 4 byte operational control field and 2 byte frame error control field.
+
+pub const FRAME_HEADER_BYTES: usize = 6;
+
+// first header pointer values with special meanings
+const NO_PACKET_START: u16 = 0x7FF;
+const IDLE_FRAME: u16 = 0x7FE;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub spacecraft_id: u16,
+    pub virtual_channel: u8,
+    pub ocf: bool,
+    pub mc_count: u8,
+    pub vc_count: u8,
+    pub sec_header: bool,
+    pub sync: bool,
+    pub first_header_pointer: u16,
+}
+
+impl FrameHeader {
+    pub fn parse(bytes: &[u8]) -> Option<FrameHeader> {
+        if bytes.len() < FRAME_HEADER_BYTES {
+            return None;
+        }
+
+        let id = BigEndian::read_u16(&bytes[0..2]);
+        let status = BigEndian::read_u16(&bytes[4..6]);
+
+        Some(FrameHeader {
+            version: (id >> 14) as u8,
+            spacecraft_id: (id >> 4) & 0x3FF,
+            virtual_channel: ((id >> 1) & 0x7) as u8,
+            ocf: id & 0x1 != 0,
+            mc_count: bytes[2],
+            vc_count: bytes[3],
+            sec_header: status & 0x8000 != 0,
+            sync: status & 0x4000 != 0,
+            first_header_pointer: status & 0x7FF,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; FRAME_HEADER_BYTES] {
+        let id = ((self.version as u16 & 0x3) << 14) |
+                 ((self.spacecraft_id & 0x3FF) << 4) |
+                 ((self.virtual_channel as u16 & 0x7) << 1) |
+                 self.ocf as u16;
+        let status = ((self.sec_header as u16) << 15) |
+                     ((self.sync as u16) << 14) |
+                     (self.first_header_pointer & 0x7FF);
+
+        let mut bytes = [0; FRAME_HEADER_BYTES];
+        BigEndian::write_u16(&mut bytes[0..2], id);
+        bytes[2] = self.mc_count;
+        bytes[3] = self.vc_count;
+        BigEndian::write_u16(&mut bytes[4..6], status);
+        bytes
+    }
+}
+
+// The frame error control field checksum, CRC-16-CCITT with an initial value of 0xFFFF.
+pub fn frame_crc(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+// A jump in a frame count. A virtual_channel of None is the master channel count,
+// which counts every frame from the spacecraft.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct CountGap {
+    pub virtual_channel: Option<u8>,
+    pub expected: u8,
+    pub found: u8,
+}
+
+impl CountGap {
+    // the number of frames lost, assuming the count did not wrap more then once
+    pub fn missing(&self) -> u8 {
+        self.found.wrapping_sub(self.expected)
+    }
+}
+
+// A space packet taken out of the frames of a virtual channel.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TmPacket {
+    pub virtual_channel: u8,
+    // the receive time of the frame the packet ended in, if known
+    pub time: Option<f64>,
+    pub bytes: Vec<u8>,
+}
+
+impl AsRef<[u8]> for TmPacket {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl ReceiveTime for TmPacket {
+    fn receive_time(&self) -> Option<f64> {
+        self.time
+    }
+}
+
+// the packet stream of one virtual channel
+#[derive(Debug, Clone, Default)]
+struct Channel {
+    buffer: Vec<u8>,
+    // whether the buffer starts at a packet boundary
+    synced: bool,
+    vc_count: Option<u8>,
+}
+
+// Demultiplex frames by virtual channel and reassemble the packets they carry.
+// Frames with a bad length, version, or checksum are dropped, as are idle frames
+// and idle packets. When a frame count jumps, the partial packet of that channel
+// is dropped and the channel resyncs at the next first header pointer.
+#[derive(Debug, Clone)]
+pub struct TmDemux {
+    frame_length: usize,
+    fecf: bool,
+    spacecraft_id: Option<u16>,
+    channels: HashMap<u8, Channel>,
+    mc_count: Option<u8>,
+    gaps: Vec<CountGap>,
+    frames: usize,
+    bad_frames: usize,
+    idle_packets: usize,
+    discarded: usize,
+}
+
+impl TmDemux {
+    pub fn new(frame_length: usize) -> TmDemux {
+        TmDemux { frame_length,
+                  fecf: false,
+                  spacecraft_id: None,
+                  channels: HashMap::new(),
+                  mc_count: None,
+                  gaps: Vec::new(),
+                  frames: 0,
+                  bad_frames: 0,
+                  idle_packets: 0,
+                  discarded: 0 }
+    }
+
+    // frames end with a frame error control field, which is checked
+    pub fn with_fecf(mut self) -> TmDemux {
+        self.fecf = true;
+        self
+    }
+
+    // only accept frames from this spacecraft
+    pub fn with_spacecraft_id(mut self, spacecraft_id: u16) -> TmDemux {
+        self.spacecraft_id = Some(spacecraft_id);
+        self
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn bad_frames(&self) -> usize {
+        self.bad_frames
+    }
+
+    pub fn idle_packets(&self) -> usize {
+        self.idle_packets
+    }
+
+    // bytes dropped from partial packets, or while waiting for a packet to start
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    // the frame count gaps found since last taken
+    pub fn take_gaps(&mut self) -> Vec<CountGap> {
+        self.gaps.drain(..).collect()
+    }
+
+    // Take in the next frame, returning the packets it completes.
+    pub fn push_frame(&mut self, frame: &[u8]) -> Vec<TmPacket> {
+        let mut packets = Vec::new();
+
+        if frame.len() != self.frame_length {
+            self.bad_frames += 1;
+            return packets;
+        }
+
+        let header = match FrameHeader::parse(frame) {
+            Some(header) if header.version == 0 => header,
+            _ => {
+                self.bad_frames += 1;
+                return packets;
+            },
+        };
+
+        if self.spacecraft_id.is_some_and(|spacecraft_id| spacecraft_id != header.spacecraft_id) {
+            return packets;
+        }
+
+        let mut data_end = self.frame_length;
+        if self.fecf {
+            data_end -= 2;
+            if frame_crc(&frame[..data_end]) != BigEndian::read_u16(&frame[data_end..]) {
+                self.bad_frames += 1;
+                return packets;
+            }
+        }
+        if header.ocf {
+            data_end -= 4;
+        }
+
+        let mut data_start = FRAME_HEADER_BYTES;
+        if header.sec_header {
+            data_start += frame.get(data_start).map_or(self.frame_length, |byte| (byte & 0x3F) as usize + 1);
+        }
+
+        // NOTE sync flag frames carry data other then packets, which is not supported
+        if header.sync || data_start > data_end {
+            self.bad_frames += 1;
+            return packets;
+        }
+        self.frames += 1;
+
+        if let Some(last) = self.mc_count {
+            let expected = last.wrapping_add(1);
+            if header.mc_count != expected {
+                self.gaps.push(CountGap { virtual_channel: None, expected, found: header.mc_count });
+            }
+        }
+        self.mc_count = Some(header.mc_count);
+
+        let mut channel = self.channels.remove(&header.virtual_channel).unwrap_or_default();
+
+        if let Some(last) = channel.vc_count {
+            let expected = last.wrapping_add(1);
+            if header.vc_count != expected {
+                self.gaps.push(CountGap { virtual_channel: Some(header.virtual_channel), expected, found: header.vc_count });
+                self.discarded += channel.buffer.len();
+                channel.buffer.clear();
+                channel.synced = false;
+            }
+        }
+        channel.vc_count = Some(header.vc_count);
+
+        let data = &frame[data_start..data_end];
+        let pointer = header.first_header_pointer;
+
+        if pointer == IDLE_FRAME {
+            // idle frames only keep the frame counts going
+        } else if pointer == NO_PACKET_START {
+            if channel.synced {
+                channel.buffer.extend_from_slice(data);
+                self.extract(header.virtual_channel, &mut channel, &mut packets);
+            } else {
+                self.discarded += data.len();
+            }
+        } else if pointer as usize > data.len() {
+            self.bad_frames += 1;
+            self.discarded += channel.buffer.len();
+            channel.buffer.clear();
+            channel.synced = false;
+        } else {
+            let (rest, next) = data.split_at(pointer as usize);
+
+            // the bytes before the pointer finish the packet in progress
+            if channel.synced {
+                channel.buffer.extend_from_slice(rest);
+                self.extract(header.virtual_channel, &mut channel, &mut packets);
+            } else {
+                self.discarded += rest.len();
+            }
+
+            // anything left did not line up with the pointer, so resync there
+            self.discarded += channel.buffer.len();
+            channel.buffer.clear();
+            channel.synced = true;
+
+            channel.buffer.extend_from_slice(next);
+            self.extract(header.virtual_channel, &mut channel, &mut packets);
+        }
+
+        self.channels.insert(header.virtual_channel, channel);
+
+        packets
+    }
+
+    // take the complete packets from the start of a channel's buffer
+    fn extract(&mut self, virtual_channel: u8, channel: &mut Channel, packets: &mut Vec<TmPacket>) {
+        while let Some(header) = PrimaryHeader::parse(&channel.buffer) {
+            let length = header.packet_length();
+            if channel.buffer.len() < length {
+                break;
+            }
+
+            let bytes: Vec<u8> = channel.buffer.drain(..length).collect();
+            if header.is_idle() {
+                self.idle_packets += 1;
+            } else {
+                packets.push(TmPacket { virtual_channel, time: None, bytes });
+            }
+        }
+    }
+}
+
+// Space packets from a stream of frames, such as the output of a framer. Frames
+// may start with a sync marker, which is skipped. Each packet is given the
+// receive time of the frame it ended in.
+pub struct TmPackets<I> {
+    frames: I,
+    demux: TmDemux,
+    marker_len: usize,
+    packets: VecDeque<TmPacket>,
+}
+
+impl<I> TmPackets<I> {
+    pub fn new(frames: I, demux: TmDemux) -> TmPackets<I> {
+        TmPackets { frames, demux, marker_len: 0, packets: VecDeque::new() }
+    }
+
+    // the length of the sync marker at the start of each frame
+    pub fn with_marker_len(mut self, marker_len: usize) -> TmPackets<I> {
+        self.marker_len = marker_len;
+        self
+    }
+
+    pub fn demux(&self) -> &TmDemux {
+        &self.demux
+    }
+
+    pub fn demux_mut(&mut self) -> &mut TmDemux {
+        &mut self.demux
+    }
+}
+
+impl<I, P> Iterator for TmPackets<I>
+    where I: Iterator<Item=P>,
+          P: AsRef<[u8]> + ReceiveTime {
+    type Item = TmPacket;
+
+    fn next(&mut self) -> Option<TmPacket> {
+        loop {
+            if let Some(packet) = self.packets.pop_front() {
+                return Some(packet);
+            }
+
+            let frame = self.frames.next()?;
+            let time = frame.receive_time();
+            let bytes = frame.as_ref();
+            let bytes = if bytes.len() >= self.marker_len { &bytes[self.marker_len..] } else { bytes };
+
+            for mut packet in self.demux.push_frame(bytes) {
+                packet.time = time;
+                self.packets.push_back(packet);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_tmframe {
+    use super::*;
+
+    const FRAME_LENGTH: usize = 32;
+
+    fn space_packet(apid: u16, data: &[u8]) -> Vec<u8> {
+        let header = PrimaryHeader { version: 0,
+                                     packet_type: PacketType::Telemetry,
+                                     sec_header: false,
+                                     apid,
+                                     seq_flags: SeqFlags::Unsegmented,
+                                     seq_count: 0,
+                                     length: data.len() as u16 - 1 };
+
+        let mut packet = header.to_bytes().to_vec();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn frame(virtual_channel: u8, mc_count: u8, vc_count: u8, first_header_pointer: u16, data: &[u8]) -> Vec<u8> {
+        let header = FrameHeader { version: 0,
+                                   spacecraft_id: 0x42,
+                                   virtual_channel,
+                                   ocf: false,
+                                   mc_count,
+                                   vc_count,
+                                   sec_header: false,
+                                   sync: false,
+                                   first_header_pointer };
+
+        let mut frame = header.to_bytes().to_vec();
+        frame.extend_from_slice(data);
+        assert_eq!(frame.len(), FRAME_LENGTH - 2);
+        let crc = frame_crc(&frame);
+        frame.extend_from_slice(&[(crc >> 8) as u8, crc as u8]);
+        frame
+    }
+
+    #[test]
+    fn test_tm_demux() {
+        // packets of 10 and 30 bytes, an idle packet, then the first packet again
+        let first = space_packet(0x10, &[1; 4]);
+        let second = space_packet(0x20, &[2; 24]);
+        let mut stream = first.clone();
+        stream.extend_from_slice(&second);
+        stream.extend_from_slice(&space_packet(IDLE_APID, &[0; 16]));
+        stream.extend_from_slice(&first);
+        assert_eq!(stream.len(), 3 * 24);
+
+        let mut frames = vec![frame(1, 0, 0, 0, &stream[0..24]),
+                              frame(1, 1, 1, NO_PACKET_START, &stream[24..48]),
+                              frame(2, 2, 0, IDLE_FRAME, &[0; 24]),
+                              frame(1, 3, 2, 14, &stream[48..72])];
+        // a corrupt frame is dropped
+        frames.insert(1, frames[1].clone());
+        frames[1][10] ^= 0xFF;
+
+        let mut demux = TmDemux::new(FRAME_LENGTH).with_fecf().with_spacecraft_id(0x42);
+        let packets: Vec<TmPacket> = frames.iter().flat_map(|frame| demux.push_frame(frame)).collect();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].bytes, first);
+        assert_eq!(packets[1].bytes, second);
+        assert_eq!(packets[2].bytes, first);
+        assert_eq!(packets[1].virtual_channel, 1);
+        assert_eq!(demux.idle_packets(), 1);
+        assert_eq!(demux.bad_frames(), 1);
+        assert!(demux.take_gaps().is_empty());
+
+        // after a lost frame the channel drops the partial packet and picks up at the pointer
+        let frames = [frame(1, 4, 3, 0, &stream[0..24]),
+                      frame(1, 6, 5, 14, &stream[48..72])];
+        let timed = frames.iter().map(|frame| TimedPacket { time: 5.0, bytes: frame.clone() });
+        let packets: Vec<TmPacket> = TmPackets::new(timed, demux.clone()).collect();
+        assert_eq!(packets, vec![TmPacket { virtual_channel: 1, time: Some(5.0), bytes: first.clone() },
+                                 TmPacket { virtual_channel: 1, time: Some(5.0), bytes: first.clone() }]);
+
+        for frame in frames.iter() {
+            demux.push_frame(frame);
+        }
+        assert_eq!(demux.take_gaps(), vec![CountGap { virtual_channel: None, expected: 5, found: 6 },
+                                          CountGap { virtual_channel: Some(1), expected: 4, found: 5 }]);
+        assert_eq!(demux.discarded(), 14 + 14);
+    }
+}
@@ -94,7 +94,7 @@ impl<'a> PacketStream<'a> {
     pub fn new(packet: LayoutPacketDef, bytes: &'a Vec<u8>) -> PacketStream {
         PacketStream { bytes: bytes,
                        position: 0,
-                       num_bytes: packet.num_bytes() as usize,
+                       num_bytes: packet.size().min as usize,
         }
     }
 }
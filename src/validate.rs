@@ -0,0 +1,403 @@
+#[allow(unused_imports)]
+use std::collections::HashSet;
+#[allow(unused_imports)]
+use std::collections::HashMap;
+#[allow(unused_imports)]
+use std::collections::BTreeMap;
+
+use std::fmt;
+
+use types::*;
+use prim::*;
+use layout::*;
+use packet::*;
+
+
+// Check definitions for mistakes that would otherwise only show up when decoding,
+// or not at all, such as two items with the same name where one hides the other.
+// Names are checked by scope, following the sections of the decoded ValueMap:
+// each Seq, All, and array element is a section of its own, while subcom branches
+// and bit fields add their items to the enclosing section.
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum DefinitionProblem {
+    // a second item or section with the same name in a section
+    DuplicateName { path: LocPath },
+    // an item or section defined by more then one branch of an All
+    AllOverlap { path: LocPath },
+    // a variable array counted by an item not defined before it
+    UndefinedCount { path: LocPath, count: Name },
+    // a variable array counted by something other then an integer or enum item
+    NonIntegerCount { path: LocPath, count: Name },
+    // a subcom switching on an item not defined before it
+    UndefinedSwitch { path: LocPath, item: Name },
+    // a subcom branch with a different number of keys then the subcom has items
+    KeyCount { path: LocPath, keys: usize, items: usize },
+    // bit fields wider then the bytes they are read from
+    BitsOverflow { path: LocPath, bits: u64, num_bytes: u64 },
+    // a bit field wider then the integer it is decoded into
+    BitFieldOverflow { path: LocPath, bits: u32, int_bits: u32 },
+    // an enum value that its integer type can not hold
+    EnumRange { path: LocPath, value: i64 },
+}
+
+impl fmt::Display for DefinitionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionProblem::DuplicateName { path } =>
+                write!(f, "{} is defined more then once in its section", path.join(".")),
+            DefinitionProblem::AllOverlap { path } =>
+                write!(f, "{} is defined by more then one branch of an All", path.join(".")),
+            DefinitionProblem::UndefinedCount { path, count } =>
+                write!(f, "array {} is counted by {}, which is not defined before it", path.join("."), count),
+            DefinitionProblem::NonIntegerCount { path, count } =>
+                write!(f, "array {} is counted by {}, which is not an integer", path.join("."), count),
+            DefinitionProblem::UndefinedSwitch { path, item } =>
+                write!(f, "subcom {} switches on {}, which is not defined before it", path.join("."), item),
+            DefinitionProblem::KeyCount { path, keys, items } =>
+                write!(f, "subcom {} has a branch with {} keys for {} items", path.join("."), keys, items),
+            DefinitionProblem::BitsOverflow { path, bits, num_bytes } =>
+                write!(f, "bit fields at {} take {} bits of {} bytes", path.join("."), bits, num_bytes),
+            DefinitionProblem::BitFieldOverflow { path, bits, int_bits } =>
+                write!(f, "bit field {} has {} bits, more then its {} bit integer", path.join("."), bits, int_bits),
+            DefinitionProblem::EnumRange { path, value } =>
+                write!(f, "enum {} has value {}, outside its integer type", path.join("."), value),
+        }
+    }
+}
+
+// The items of packet definitions, located or not.
+pub trait DefItem {
+    fn item_name(&self) -> &str;
+    fn prim(&self) -> &Prim;
+}
+
+impl DefItem for Item {
+    fn item_name(&self) -> &str {
+        &self.name
+    }
+
+    fn prim(&self) -> &Prim {
+        &self.typ
+    }
+}
+
+impl DefItem for LocItem {
+    fn item_name(&self) -> &str {
+        self.name.last().map_or("", |name| name.as_str())
+    }
+
+    fn prim(&self) -> &Prim {
+        &self.typ
+    }
+}
+
+// What a name in a section refers to: an item with its type, or a section.
+type Scope = HashMap<Name, Option<Prim>>;
+
+struct Validation {
+    scopes: Vec<Scope>,
+    loc_path: LocPath,
+    problems: Vec<DefinitionProblem>,
+}
+
+impl Validation {
+    fn new() -> Validation {
+        Validation { scopes: vec![Scope::new()], loc_path: LocPath::new(), problems: Vec::new() }
+    }
+
+    fn path(&self, name: &str) -> LocPath {
+        let mut path = self.loc_path.clone();
+        path.push(name.to_string());
+        path
+    }
+
+    fn define(&mut self, name: &str, prim: Option<Prim>) {
+        if self.scopes.last_mut().unwrap().insert(name.to_string(), prim).is_some() {
+            let path = self.path(name);
+            self.problems.push(DefinitionProblem::DuplicateName { path });
+        }
+    }
+
+    // look up a name starting from the innermost section, as decoding does
+    fn lookup(&self, name: &str) -> Option<&Option<Prim>> {
+        self.scopes.iter().rev().filter_map(|scope| scope.get(name)).next()
+    }
+
+    fn check_prim(&mut self, name: &str, prim: &Prim) {
+        if let Prim::Enum(enum_prim) = prim {
+            let path = self.path(name);
+            self.problems.extend(enum_problems(&path, enum_prim));
+        }
+    }
+
+    // Check alternatives that each add names to the current section, starting from the
+    // same names. Returns the names each alternative added.
+    fn branches<F>(&mut self, count: usize, mut check: F) -> Vec<Scope>
+        where F: FnMut(&mut Validation, usize) {
+        let before = self.scopes.last().unwrap().clone();
+        let mut added: Vec<Scope> = Vec::new();
+
+        for index in 0 .. count {
+            *self.scopes.last_mut().unwrap() = before.clone();
+            check(self, index);
+
+            let scope = self.scopes.last().unwrap();
+            added.push(scope.iter().filter(|(name, _)| !before.contains_key(*name))
+                                   .map(|(name, prim)| (name.clone(), prim.clone()))
+                                   .collect());
+        }
+
+        let mut after = before;
+        for scope in added.iter() {
+            after.extend(scope.iter().map(|(name, prim)| (name.clone(), prim.clone())));
+        }
+        *self.scopes.last_mut().unwrap() = after;
+
+        added
+    }
+}
+
+pub fn validate_packet<T : DefItem>(packet: &PacketDef<T>) -> Vec<DefinitionProblem> {
+    let mut validation = Validation::new();
+
+    validate_packet_helper(packet, &mut validation);
+
+    validation.problems
+}
+
+fn validate_packet_helper<T : DefItem>(packet: &PacketDef<T>, validation: &mut Validation) {
+    match packet {
+        PacketDef::Seq(name, packets) => {
+            validation.scopes.push(Scope::new());
+            validation.loc_path.push(name.clone());
+            for packet in packets {
+                validate_packet_helper(packet, validation);
+            }
+            validation.loc_path.pop();
+            validation.scopes.pop();
+
+            validation.define(name, None);
+        },
+
+        PacketDef::Subcom(name, items, pairs, default) => {
+            for item in items {
+                if validation.lookup(item.item_name()).is_none() {
+                    let path = validation.path(name);
+                    validation.problems.push(DefinitionProblem::UndefinedSwitch { path, item: item.item_name().to_string() });
+                }
+            }
+
+            for (keys, _) in pairs {
+                if keys.len() != items.len() {
+                    let path = validation.path(name);
+                    validation.problems.push(DefinitionProblem::KeyCount { path, keys: keys.len(), items: items.len() });
+                }
+            }
+
+            // the branches are alternatives, so they may use the same names
+            let branches: Vec<&PacketDef<T>> = pairs.iter().map(|(_, packet)| packet)
+                                                    .chain(default.iter().map(|packet| &**packet))
+                                                    .collect();
+            validation.branches(branches.len(), |validation, index| validate_packet_helper(branches[index], validation));
+        },
+
+        PacketDef::Array(name, size, packet) => {
+            if let ArrSize::Var(count) = size {
+                let problem = match validation.lookup(count) {
+                    None => Some(DefinitionProblem::UndefinedCount { path: validation.path(name), count: count.clone() }),
+                    Some(Some(Prim::Int(_))) | Some(Some(Prim::Enum(_))) => None,
+                    Some(_) => Some(DefinitionProblem::NonIntegerCount { path: validation.path(name), count: count.clone() }),
+                };
+                validation.problems.extend(problem);
+            }
+
+            validation.scopes.push(Scope::new());
+            validation.loc_path.push(format!("{}[]", name));
+            validate_packet_helper(packet, validation);
+            validation.loc_path.pop();
+            validation.scopes.pop();
+
+            validation.define(name, None);
+        },
+
+        PacketDef::Leaf(item) => {
+            validation.check_prim(item.item_name(), item.prim());
+            validation.define(item.item_name(), Some(item.prim().clone()));
+        },
+    }
+}
+
+pub fn validate_layout(layout: &Layout) -> Vec<DefinitionProblem> {
+    let mut validation = Validation::new();
+
+    validate_layout_helper(layout, &mut validation);
+
+    validation.problems
+}
+
+fn validate_layout_helper(layout: &Layout, validation: &mut Validation) {
+    match layout {
+        Layout::Prim(item) => {
+            validation.check_prim(&item.name, &item.typ);
+            validation.define(&item.name, Some(item.typ.clone()));
+        },
+
+        Layout::Seq(name, layouts) => {
+            validation.scopes.push(Scope::new());
+            validation.loc_path.push(name.clone());
+            for layout in layouts {
+                validate_layout_helper(layout, validation);
+            }
+            validation.loc_path.pop();
+            validation.scopes.pop();
+
+            validation.define(name, None);
+        },
+
+        Layout::All(name, layouts) => {
+            validation.scopes.push(Scope::new());
+            validation.loc_path.push(name.clone());
+
+            // every branch is decoded into the same section, so a name in two branches is overwritten
+            let added = validation.branches(layouts.len(), |validation, index| validate_layout_helper(&layouts[index], validation));
+            let mut seen = HashSet::new();
+            let mut overlaps = BTreeMap::new();
+            for scope in added.iter() {
+                for name in scope.keys() {
+                    if !seen.insert(name.clone()) {
+                        overlaps.insert(name.clone(), validation.path(name));
+                    }
+                }
+            }
+            validation.problems.extend(overlaps.into_values().map(|path| DefinitionProblem::AllOverlap { path }));
+
+            validation.loc_path.pop();
+            validation.scopes.pop();
+
+            validation.define(name, None);
+        },
+
+        Layout::Array(name, _, layout) => {
+            validation.scopes.push(Scope::new());
+            validation.loc_path.push(format!("{}[]", name));
+            validate_layout_helper(layout, validation);
+            validation.loc_path.pop();
+            validation.scopes.pop();
+
+            validation.define(name, None);
+        },
+
+        Layout::Bits(bit_prim) => {
+            let bits: u64 = bit_prim.entries.iter().map(|(_, num_bits, _)| *num_bits as u64).sum();
+            if bits > bit_prim.num_bytes * 8 {
+                let path = validation.path(&bit_prim.entries[0].0);
+                validation.problems.push(DefinitionProblem::BitsOverflow { path, bits, num_bytes: bit_prim.num_bytes });
+            }
+
+            for (name, num_bits, int_prim) in bit_prim.entries.iter() {
+                let int_bits = 8 * int_prim.num_bytes() as u32;
+                if *num_bits > int_bits {
+                    let path = validation.path(name);
+                    validation.problems.push(DefinitionProblem::BitFieldOverflow { path, bits: *num_bits, int_bits });
+                }
+
+                validation.define(name, Some(Prim::Int(int_prim.clone())));
+            }
+        },
+    }
+}
+
+fn enum_problems(path: &LocPath, enum_prim: &Enum) -> Vec<DefinitionProblem> {
+    let mut problems = Vec::new();
+
+    // NOTE several values may share a state name, so only the values are checked
+    let bits = 8 * enum_prim.int_prim.num_bytes() as u32;
+    let (min, max): (i128, i128) = match enum_prim.int_prim.signedness {
+        Signedness::Unsigned => (0, (1i128 << bits) - 1),
+        Signedness::Signed   => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+    };
+    for value in enum_prim.map.keys() {
+        if (*value as i128) < min || (*value as i128) > max {
+            problems.push(DefinitionProblem::EnumRange { path: path.clone(), value: *value });
+        }
+    }
+
+    problems
+}
+
+
+#[cfg(test)]
+mod test_validate {
+    use super::*;
+    use *;
+
+    fn path(names: &[&str]) -> LocPath {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_validate_definitions() {
+        let mut states = BTreeMap::new();
+        states.insert(0, "off".to_string());
+        states.insert(1, "on".to_string());
+        states.insert(2, "on".to_string());
+        states.insert(300, "fault".to_string());
+        let mode = Prim::Enum(Enum { map: states, int_prim: IntPrim::u8_be() });
+
+        let packet : LayoutPacketDef =
+            seq("tlm".to_string(),
+                vec![u8_be("type"), u16_be("count"), leaf(item("mode", mode)), f32_be("scale"),
+                     PacketDef::Subcom("payload".to_string(),
+                                       vec![item("type", Prim::Int(IntPrim::u8_be())),
+                                            item("kind", Prim::Int(IntPrim::u8_be()))],
+                                       vec![(vec![SubcomKey::Any, SubcomKey::Any], u8_be("a")),
+                                            (vec![SubcomKey::Any], u8_be("a"))],
+                                       Some(Box::new(u8_be("count")))),
+                     array_var("samples".to_string(), "count".to_string(), u8_be("sample")),
+                     array_var("scaled".to_string(), "scale".to_string(), u8_be("sample")),
+                     array_var("later".to_string(), "total".to_string(), u8_be("sample")),
+                     u8_be("type")]);
+
+        assert_eq!(validate_packet(&packet),
+                   vec![DefinitionProblem::EnumRange { path: path(&["tlm", "mode"]), value: 300 },
+                        DefinitionProblem::UndefinedSwitch { path: path(&["tlm", "payload"]), item: "kind".to_string() },
+                        DefinitionProblem::KeyCount { path: path(&["tlm", "payload"]), keys: 1, items: 2 },
+                        DefinitionProblem::DuplicateName { path: path(&["tlm", "count"]) },
+                        DefinitionProblem::NonIntegerCount { path: path(&["tlm", "scaled"]), count: "scale".to_string() },
+                        DefinitionProblem::UndefinedCount { path: path(&["tlm", "later"]), count: "total".to_string() },
+                        DefinitionProblem::DuplicateName { path: path(&["tlm", "type"]) }]);
+
+        let mut counts = BTreeMap::new();
+        counts.insert(1, "single".to_string());
+        counts.insert(2, "pair".to_string());
+        let pairs = Prim::Enum(Enum { map: counts, int_prim: IntPrim::u8_be() });
+        let good : LayoutPacketDef =
+            seq("tlm".to_string(), vec![u8_be("count"), array_var("samples".to_string(), "count".to_string(), u8_be("count")),
+                                        leaf(item("pairs", pairs)), array_var("paired".to_string(), "pairs".to_string(), u8_be("sample"))]);
+        assert_eq!(validate_packet(&good), vec![]);
+        // an enum count decodes as its value
+        let value_map = decode_layoutpacket(&good, &mut Cursor::new(&[1, 5, 2, 7, 8][..])).unwrap();
+        assert_eq!(value_map.lookup(&"pairs".to_string()), Some(Value::Enum("pair".to_string(), 2)));
+
+        // a value given twice would otherwise be lost when the map is read
+        let states = "Enum((map: {0: \"off\", 1: \"on\", 1: \"fault\"}, int_prim: (size: Bits8, signedness: Unsigned, endianness: BigEndian)))";
+        assert!(ron::de::from_str::<Prim>(states).is_err());
+        assert!(ron::de::from_str::<Prim>(&states.replace("1: \"fault\"", "2: \"fault\"")).is_ok());
+
+        let bits = Layout::Bits(BitPrim { entries: vec![("flag".to_string(), 1, IntPrim::u8_be()),
+                                                        ("wide".to_string(), 12, IntPrim::u8_be())],
+                                          num_bytes: 1 });
+        let layout = Layout::Seq("tlm".to_string(),
+                                 vec![bits,
+                                      Layout::All("union".to_string(),
+                                                  vec![Layout::Prim(Item::new("word".to_string(), Prim::Int(IntPrim::u16_be()))),
+                                                       Layout::Seq("bytes".to_string(), vec![]),
+                                                       Layout::Prim(Item::new("word".to_string(), Prim::Int(IntPrim::i16_be())))])]);
+
+        assert_eq!(validate_layout(&layout),
+                   vec![DefinitionProblem::BitsOverflow { path: path(&["tlm", "flag"]), bits: 13, num_bytes: 1 },
+                        DefinitionProblem::BitFieldOverflow { path: path(&["tlm", "wide"]), bits: 12, int_bits: 8 },
+                        DefinitionProblem::AllOverlap { path: path(&["tlm", "union", "word"]) }]);
+    }
+}
@@ -11,6 +11,7 @@ use std::collections::BTreeMap;
 use std::option;
 
 use std::fmt;
+use std::cmp::Ordering;
 
 use self::fnv::FnvHashMap;
 
@@ -93,6 +94,47 @@ impl Value {
             Value::Enum(_, _) => panic!("Found an Enum in a value, expecting an int!"),
         }
     }
+
+    // Any value as a float, with enums giving their integer value.
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Value::U8(int)  =>   *int as f64,
+            Value::U16(int) =>   *int as f64,
+            Value::U32(int) =>   *int as f64,
+            Value::U64(int) =>   *int as f64,
+            Value::I8(int)  =>   *int as f64,
+            Value::I16(int) =>   *int as f64,
+            Value::I32(int) =>   *int as f64,
+            Value::I64(int) =>   *int as f64,
+            Value::F32(float) => *float as f64,
+            Value::F64(float) => *float,
+            Value::Enum(_, int) => *int as f64,
+        }
+    }
+
+    // Compare values by number, regardless of their type, so U8(1) equals I32(1).
+    // Integers are compared exactly, and floats are compared as f64s.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self.as_i128(), other.as_i128()) {
+            (Some(int), Some(other_int)) => Some(int.cmp(&other_int)),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+
+    fn as_i128(&self) -> Option<i128> {
+        match self {
+            Value::U8(int)  => Some(*int as i128),
+            Value::U16(int) => Some(*int as i128),
+            Value::U32(int) => Some(*int as i128),
+            Value::U64(int) => Some(*int as i128),
+            Value::I8(int)  => Some(*int as i128),
+            Value::I16(int) => Some(*int as i128),
+            Value::I32(int) => Some(*int as i128),
+            Value::I64(int) => Some(*int as i128),
+            Value::F32(_) | Value::F64(_) => None,
+            Value::Enum(_, int) => Some(*int as i128),
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
@@ -105,6 +147,8 @@ impl ValueMap {
         ValueMap { value_map: value_map }
     }
 
+    // NOTE the order of the values is not specified. Use lookup_path with the
+    // paths of a definition when the order matters.
     pub fn values(&self) -> Vec<&Value> {
         let mut values = Vec::new();
 
@@ -162,6 +206,37 @@ impl ValueMap {
 
         return None;
     }
+
+    // Look up a value by its full path. Array elements are given as "name[index]",
+    // which is checked after looking for an entry with the literal name.
+    pub fn lookup_path(&self, path: &[Name]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+
+        match self.value_map.get(first) {
+            Some(ValueEntry::Leaf(value)) if rest.is_empty() => Some(value),
+
+            Some(ValueEntry::Section(value_map)) => value_map.lookup_path(rest),
+
+            Some(_) => None,
+
+            None => {
+                let open = first.rfind('[')?;
+                if !first.ends_with(']') {
+                    return None;
+                }
+
+                let index: usize = first[open + 1 .. first.len() - 1].parse().ok()?;
+
+                match self.value_map.get(&first[..open]) {
+                    Some(ValueEntry::Array(array)) => {
+                        array.get(index).and_then(|value_map| value_map.lookup_path(rest))
+                    },
+
+                    _ => None,
+                }
+            },
+        }
+    }
 }
 
 #[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]